@@ -1,4 +1,3 @@
-
 pub mod shared;
 pub mod ui_server;
 
@@ -1,4 +1,3 @@
-
 pub mod state;
 
 pub use state::AppState;
@@ -1,18 +1,80 @@
-
 use botlib::http_client::BotServerClient;
 use std::sync::Arc;
 
+use crate::ui_server::access::AccessControl;
+use crate::ui_server::auth_guard::AuthGuard;
+use crate::ui_server::branding::BrandingStore;
+use crate::ui_server::cors::CorsConfig;
+use crate::ui_server::csrf::CsrfGuard;
+use crate::ui_server::forwarded::TrustedProxies;
+use crate::ui_server::hosts::HostMap;
+use crate::ui_server::i18n::I18n;
+use crate::ui_server::integrity::IntegrityIndex;
+use crate::ui_server::jwt::JwtVerifier;
+use crate::ui_server::maintenance::Maintenance;
+use crate::ui_server::oidc::OidcClient;
+use crate::ui_server::proxy_policy::ProxyPolicy;
+use crate::ui_server::rate_limit::RateLimiter;
+use crate::ui_server::resilience::Resilience;
+use crate::ui_server::response_cache::ResponseCache;
+use crate::ui_server::security_headers::SecurityHeaders;
+use crate::ui_server::session::SessionStore;
+use crate::ui_server::themes::ThemeService;
+use crate::ui_server::upstream::Upstreams;
+
 #[derive(Clone, Debug)]
 pub struct AppState {
     pub client: Arc<BotServerClient>,
+    pub branding: Arc<BrandingStore>,
+    pub hosts: Arc<HostMap>,
+    pub sessions: Option<Arc<SessionStore>>,
+    pub csrf: Option<Arc<CsrfGuard>>,
+    pub security_headers: Arc<SecurityHeaders>,
+    pub integrity: Arc<IntegrityIndex>,
+    pub cors: Arc<CorsConfig>,
+    pub rate_limiter: Arc<RateLimiter>,
+    pub auth_guard: Option<Arc<AuthGuard>>,
+    pub jwt: Option<Arc<JwtVerifier>>,
+    pub access: Option<Arc<AccessControl>>,
+    pub oidc: Option<Arc<OidcClient>>,
+    pub proxy_policy: Option<Arc<ProxyPolicy>>,
+    pub trusted_proxies: Arc<TrustedProxies>,
+    pub upstreams: Arc<Upstreams>,
+    pub resilience: Arc<Resilience>,
+    pub maintenance: Arc<Maintenance>,
+    pub response_cache: Arc<ResponseCache>,
+    pub i18n: Arc<I18n>,
+    pub themes: Arc<ThemeService>,
 }
 
 impl AppState {
     #[must_use]
     pub fn new() -> Self {
         let url = std::env::var("BOTSERVER_URL").ok();
+        let client = BotServerClient::new(url);
+        let upstreams = Upstreams::from_env(client.base_url());
         Self {
-            client: Arc::new(BotServerClient::new(url)),
+            client: Arc::new(client),
+            branding: Arc::new(BrandingStore::from_env()),
+            hosts: Arc::new(HostMap::from_env()),
+            sessions: SessionStore::from_env().map(Arc::new),
+            csrf: CsrfGuard::from_env().map(Arc::new),
+            security_headers: Arc::new(SecurityHeaders::from_env()),
+            integrity: Arc::new(IntegrityIndex::from_env()),
+            cors: Arc::new(CorsConfig::from_env()),
+            rate_limiter: Arc::new(RateLimiter::from_env()),
+            auth_guard: AuthGuard::from_env().map(Arc::new),
+            jwt: JwtVerifier::from_env().map(Arc::new),
+            access: AccessControl::from_env().map(Arc::new),
+            oidc: OidcClient::from_env().map(Arc::new),
+            proxy_policy: ProxyPolicy::from_env().map(Arc::new),
+            trusted_proxies: Arc::new(TrustedProxies::from_env()),
+            upstreams: Arc::new(upstreams),
+            resilience: Arc::new(Resilience::from_env()),
+            maintenance: Arc::new(Maintenance::from_env()),
+            response_cache: Arc::new(ResponseCache::from_env()),
+            i18n: Arc::new(I18n::from_env()),
+            themes: Arc::new(ThemeService::from_env()),
         }
     }
 
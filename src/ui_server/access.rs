@@ -0,0 +1,314 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode, Uri},
+    middleware::Next,
+    response::{IntoResponse, Redirect, Response},
+};
+use log::{error, info, warn};
+use serde::Deserialize;
+
+use super::hosts::HostMap;
+use super::jwt::Identity;
+use super::{cookies, route, session};
+use crate::shared::AppState;
+
+const ALWAYS_PUBLIC: &[&str] = &[
+    "/api/*",
+    "/ui/*",
+    "/ws*",
+    "/auth/*",
+    "/login",
+    "/login/*",
+    "/logout",
+    "/register",
+    "/forgot-password",
+    "/reset-password",
+    "/health",
+    "/minimal",
+];
+
+fn pattern_matches(pattern: &str, path: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => path.starts_with(prefix),
+        None => path == pattern,
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+struct AccessRule {
+    apps: Vec<String>,
+    paths: Vec<String>,
+    roles: Vec<String>,
+    public: bool,
+}
+
+impl AccessRule {
+    fn matches(&self, page: &str) -> bool {
+        let app = page_app(page);
+        app.is_some_and(|app| self.apps.iter().any(|a| a == app))
+            || self.paths.iter().any(|p| pattern_matches(p, page))
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct AccessControl {
+    rules: Vec<AccessRule>,
+}
+
+impl AccessControl {
+    #[must_use]
+    pub fn from_env() -> Option<Self> {
+        let path = std::env::var("BOTUI_ACCESS_FILE").ok()?;
+        let raw = std::fs::read_to_string(&path)
+            .map_err(|e| error!("Failed to read access file {path}: {e}"))
+            .ok()?;
+        let control = serde_json::from_str::<Self>(&raw)
+            .map_err(|e| error!("Invalid access file {path}: {e}"))
+            .ok()?;
+        info!("Access rules enabled ({} rules)", control.rules.len());
+        Some(control)
+    }
+
+    fn rule_for(&self, page: &str) -> Option<&AccessRule> {
+        if !is_document(page) || ALWAYS_PUBLIC.iter().any(|p| pattern_matches(p, page)) {
+            return None;
+        }
+        self.rules.iter().find(|rule| rule.matches(page))
+    }
+}
+
+fn page_path(uri: &Uri, headers: &HeaderMap, hosts: &HostMap) -> Option<String> {
+    let path = route::normalize(uri.path())?;
+    let path = if hosts.bot_for_request(headers, uri).is_some() {
+        path
+    } else {
+        route::without_bot(&path)
+    };
+    Some(match path.strip_prefix("/suite") {
+        Some("") => "/".to_string(),
+        Some(rest) if rest.starts_with('/') => rest.to_string(),
+        _ => path,
+    })
+}
+
+fn page_app(page: &str) -> Option<&str> {
+    let mut segments = page.split('/').filter(|s| !s.is_empty());
+    match segments.next()? {
+        "apps" => segments.next(),
+        first => Some(first),
+    }
+    .filter(|app| route::is_valid_name(app))
+}
+
+fn is_document(page: &str) -> bool {
+    let last = page.rsplit('/').next().unwrap_or_default();
+    !last.contains('.') || last.ends_with(".html") || last.ends_with(".htm")
+}
+
+pub async fn identify(
+    state: &AppState,
+    headers: &HeaderMap,
+    bot: Option<&str>,
+) -> Option<Identity> {
+    if let Some(verifier) = &state.jwt {
+        let bearer = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .map(str::to_string);
+        let cookie = verifier
+            .token_cookie()
+            .and_then(|name| cookies::get(headers, name));
+        for token in bearer.into_iter().chain(cookie) {
+            match verifier.verify(&token) {
+                Ok(identity) => return Some(identity),
+                Err(e) => warn!("Ignoring caller token: {e}"),
+            }
+        }
+    }
+
+    let store = state.sessions.as_ref()?;
+    let id = store.session_id(headers)?;
+    let token = match state.upstreams.select(session::REFRESH_PATH, bot, None) {
+        Some(upstream) => store.access_token(&id, &upstream).await,
+        None => None,
+    };
+    Some(
+        state
+            .jwt
+            .as_ref()
+            .zip(token)
+            .and_then(|(verifier, token)| verifier.verify(&token).ok())
+            .unwrap_or_default(),
+    )
+}
+
+pub fn login_url(target: &str) -> String {
+    format!("/login?redirect={}", urlencoding::encode(target))
+}
+
+fn sign_in_required(request: &Request) -> Response {
+    let headers = request.headers();
+    let target = request
+        .uri()
+        .path_and_query()
+        .map_or("/", |pq| pq.as_str())
+        .to_string();
+
+    if headers.contains_key("hx-request") {
+        let current = headers
+            .get("hx-current-url")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|url| url.parse::<Uri>().ok())
+            .and_then(|uri| uri.path_and_query().map(|pq| pq.as_str().to_string()))
+            .unwrap_or(target);
+        let mut response = (StatusCode::UNAUTHORIZED, "Authentication required").into_response();
+        if let Ok(value) = HeaderValue::from_str(&login_url(&current)) {
+            response.headers_mut().insert("hx-redirect", value);
+        }
+        return response;
+    }
+
+    let accepts_html = headers
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_none_or(|accept| accept.contains("text/html") || accept.contains("*/*"));
+    if matches!(*request.method(), Method::GET | Method::HEAD) && accepts_html {
+        return Redirect::to(&login_url(&target)).into_response();
+    }
+    (StatusCode::UNAUTHORIZED, "Authentication required").into_response()
+}
+
+pub async fn apply(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let Some(access) = state.access.clone() else {
+        return next.run(request).await;
+    };
+    let Some(page) = page_path(request.uri(), request.headers(), &state.hosts) else {
+        warn!("Refused unnormalisable path {}", request.uri().path());
+        return (StatusCode::BAD_REQUEST, "Bad request").into_response();
+    };
+    let Some(rule) = access.rule_for(&page).filter(|rule| !rule.public) else {
+        return next.run(request).await;
+    };
+
+    let bot = state
+        .hosts
+        .bot_for_request(request.headers(), request.uri())
+        .or_else(|| route::parse(request.uri().path()).bot);
+    match identify(&state, request.headers(), bot.as_deref()).await {
+        Some(identity) if rule.roles.is_empty() || identity.has_any_role(&rule.roles) => {
+            next.run(request).await
+        }
+        Some(identity) => {
+            warn!(
+                "Denied {page} to {:?}: needs one of {:?}",
+                identity.subject, rule.roles
+            );
+            (StatusCode::FORBIDDEN, "Forbidden").into_response()
+        }
+        None => sign_in_required(&request),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn control() -> AccessControl {
+        serde_json::from_str(
+            r#"{
+                "rules": [
+                    { "apps": ["admin", "monitoring"], "roles": ["admin"] },
+                    { "paths": ["/tools/security.html"], "roles": ["security"] },
+                    { "paths": ["/public/*"], "public": true },
+                    { "paths": ["/*"] }
+                ]
+            }"#,
+        )
+        .unwrap_or_default()
+    }
+
+    fn page(path: &str) -> Option<String> {
+        let uri = path.parse::<Uri>().ok()?;
+        page_path(&uri, &HeaderMap::new(), &HostMap::default())
+    }
+
+    fn roles_for(path: &str) -> Option<Vec<String>> {
+        let control = control();
+        let page = page(path)?;
+        control
+            .rule_for(&page)
+            .filter(|rule| !rule.public)
+            .map(|rule| rule.roles.clone())
+    }
+
+    #[test]
+    fn normalises_page_paths() {
+        assert_eq!(
+            page("/edu/suite/admin/users.html").as_deref(),
+            Some("/admin/users.html")
+        );
+        assert_eq!(
+            page("/suite/admin/users.html").as_deref(),
+            Some("/admin/users.html")
+        );
+        assert_eq!(page("/bot/edu/admin").as_deref(), Some("/admin"));
+        assert_eq!(
+            page("/%61dmin//./users.html").as_deref(),
+            Some("/admin/users.html")
+        );
+        assert_eq!(page("/suite").as_deref(), Some("/"));
+        assert_eq!(page("/suite/../admin/users.html"), None);
+        assert_eq!(page("/suite/%2e%2e/admin/users.html"), None);
+    }
+
+    #[test]
+    fn encoded_and_dotted_paths_hit_the_same_rule() {
+        let admin = Some(vec!["admin".to_string()]);
+        for path in [
+            "/admin/users.html",
+            "/edu/admin",
+            "/%61dmin/users.html",
+            "/suite/./admin/users.html",
+            "//admin//users.html",
+            "/apps/admin/users",
+            "/suite%2Fadmin/users.html",
+        ] {
+            assert_eq!(roles_for(path), admin, "{path}");
+        }
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        assert_eq!(
+            roles_for("/tools/security.html"),
+            Some(vec!["security".to_string()])
+        );
+        assert_eq!(roles_for("/public/about.html"), None);
+        assert_eq!(roles_for("/chat"), Some(Vec::new()));
+        assert_eq!(roles_for("/suite/js/app.js"), None);
+        assert_eq!(roles_for("/api/admin/users"), None);
+        assert_eq!(roles_for("/login"), None);
+    }
+
+    #[test]
+    fn checks_roles() {
+        let identity = Identity {
+            subject: Some("ana".to_string()),
+            roles: vec!["editor".to_string(), "admin".to_string()],
+        };
+        assert!(identity.has_any_role(&["admin".to_string()]));
+        assert!(!identity.has_any_role(&["security".to_string()]));
+        assert!(!identity.has_any_role(&[]));
+    }
+
+    #[test]
+    fn login_url_keeps_the_target() {
+        assert_eq!(
+            login_url("/edu/admin?tab=1"),
+            "/login?redirect=%2Fedu%2Fadmin%3Ftab%3D1"
+        );
+    }
+}
@@ -0,0 +1,515 @@
+use axum::{
+    http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use log::{info, warn};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+pub const CHALLENGE_PATH: &str = "/api/auth/challenge";
+pub const CHALLENGE_HEADER: &str = "x-auth-challenge";
+pub const SOLUTION_HEADER: &str = "x-auth-solution";
+
+const DEFAULT_PATHS: &[&str] = &[
+    "/api/auth/login",
+    "/api/auth/2fa/verify",
+    "/api/auth/forgot-password",
+    "/api/auth/reset-password",
+];
+const ACCOUNT_FIELDS: &[&str] = &["email", "username", "login", "user", "session_token"];
+const CHALLENGE_TTL_SECS: u64 = 120;
+const MAX_TRACKED_KEYS: usize = 100_000;
+
+#[derive(Debug)]
+struct Failures {
+    count: u32,
+    first: Instant,
+    locked_until: Option<Instant>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Allow { delay: Duration },
+    ChallengeRequired,
+    Locked { retry_after: Duration },
+}
+
+pub struct AuthGuard {
+    key: [u8; 32],
+    window: Duration,
+    delay_after: u32,
+    max_delay: Duration,
+    challenge_after: u32,
+    difficulty: u32,
+    lockout_after: u32,
+    lockout: Duration,
+    failures: Mutex<HashMap<String, Failures>>,
+    used_challenges: Mutex<HashMap<String, u64>>,
+}
+
+impl std::fmt::Debug for AuthGuard {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AuthGuard")
+            .field("window", &self.window)
+            .field("delay_after", &self.delay_after)
+            .field("challenge_after", &self.challenge_after)
+            .field("difficulty", &self.difficulty)
+            .field("lockout_after", &self.lockout_after)
+            .field("lockout", &self.lockout)
+            .finish_non_exhaustive()
+    }
+}
+
+fn env_u32(name: &str, default: u32) -> u32 {
+    std::env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+fn hashed(value: &str) -> String {
+    URL_SAFE_NO_PAD.encode(&Sha256::digest(value.to_ascii_lowercase().as_bytes())[..12])
+}
+
+fn leading_zero_bits(digest: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in digest {
+        if *byte == 0 {
+            bits += 8;
+        } else {
+            return bits + byte.leading_zeros();
+        }
+    }
+    bits
+}
+
+pub fn is_guarded(path: &str) -> bool {
+    DEFAULT_PATHS.contains(&path)
+}
+
+pub fn is_challenge_header(name: &HeaderName) -> bool {
+    name == CHALLENGE_HEADER || name == SOLUTION_HEADER
+}
+
+pub fn attempt_keys(ip: Option<IpAddr>, body: &[u8]) -> Vec<String> {
+    let account = serde_json::from_slice::<serde_json::Value>(body)
+        .ok()
+        .and_then(|value| {
+            ACCOUNT_FIELDS
+                .iter()
+                .find_map(|field| value.get(*field).and_then(|v| v.as_str()))
+                .filter(|v| !v.trim().is_empty())
+                .map(|v| hashed(v.trim()))
+        });
+    let mut keys: Vec<String> = ip.map(|ip| format!("ip:{ip}")).into_iter().collect();
+    if let Some(account) = account {
+        if let Some(ip) = ip {
+            keys.push(format!("pair:{account}@{ip}"));
+        }
+        keys.push(format!("account:{account}"));
+    }
+    keys
+}
+
+fn is_lockable(key: &str) -> bool {
+    !key.starts_with("account:")
+}
+
+impl AuthGuard {
+    #[must_use]
+    pub fn from_env() -> Option<Self> {
+        if std::env::var("BOTUI_AUTH_GUARD").is_ok_and(|v| v == "false") {
+            return None;
+        }
+
+        let guard = Self {
+            key: rand::random(),
+            window: Duration::from_secs(env_u32("BOTUI_AUTH_WINDOW_SECS", 900).into()),
+            delay_after: env_u32("BOTUI_AUTH_DELAY_AFTER", 3),
+            max_delay: Duration::from_secs(30),
+            challenge_after: env_u32("BOTUI_AUTH_CHALLENGE_AFTER", 5),
+            difficulty: env_u32("BOTUI_AUTH_POW_DIFFICULTY", 16).min(32),
+            lockout_after: env_u32("BOTUI_AUTH_LOCKOUT_AFTER", 10),
+            lockout: Duration::from_secs(env_u32("BOTUI_AUTH_LOCKOUT_SECS", 900).into()),
+            failures: Mutex::new(HashMap::new()),
+            used_challenges: Mutex::new(HashMap::new()),
+        };
+        info!("Auth brute-force guard enabled: {guard:?}");
+        Some(guard)
+    }
+
+    fn sign(&self, payload: &[u8]) -> Option<Vec<u8>> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).ok()?;
+        mac.update(payload);
+        Some(mac.finalize().into_bytes().to_vec())
+    }
+
+    fn verify(&self, payload: &[u8], signature: &[u8]) -> bool {
+        Hmac::<Sha256>::new_from_slice(&self.key).is_ok_and(|mut mac| {
+            mac.update(payload);
+            mac.verify_slice(signature).is_ok()
+        })
+    }
+
+    fn new_challenge(&self) -> Option<String> {
+        let mut payload = rand::random::<[u8; 16]>().to_vec();
+        payload.extend((now_secs() + CHALLENGE_TTL_SECS).to_be_bytes());
+        let signature = self.sign(&payload)?;
+        Some(format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(&payload),
+            URL_SAFE_NO_PAD.encode(signature)
+        ))
+    }
+
+    pub fn issue_challenge(&self) -> Response {
+        let Some(challenge) = self.new_challenge() else {
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        };
+        let mut response = Json(serde_json::json!({
+            "challenge": challenge,
+            "difficulty": self.difficulty,
+            "algorithm": "sha256",
+            "expires_in": CHALLENGE_TTL_SECS,
+        }))
+        .into_response();
+        response
+            .headers_mut()
+            .insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+        response
+    }
+
+    fn solved(&self, headers: &HeaderMap) -> bool {
+        let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+        let (Some(challenge), Some(solution)) = (header(CHALLENGE_HEADER), header(SOLUTION_HEADER))
+        else {
+            return false;
+        };
+        let Some((payload, signature)) = challenge.split_once('.') else {
+            return false;
+        };
+        let (Ok(payload), Ok(signature)) = (
+            URL_SAFE_NO_PAD.decode(payload),
+            URL_SAFE_NO_PAD.decode(signature),
+        ) else {
+            return false;
+        };
+        let Some(expires) = payload
+            .get(16..24)
+            .and_then(|bytes| <[u8; 8]>::try_from(bytes).ok())
+            .map(u64::from_be_bytes)
+        else {
+            return false;
+        };
+        if !self.verify(&payload, &signature) || expires < now_secs() {
+            return false;
+        }
+        let digest = Sha256::digest(format!("{challenge}:{solution}").as_bytes());
+        if leading_zero_bits(&digest) < self.difficulty {
+            return false;
+        }
+
+        let Ok(mut used) = self.used_challenges.lock() else {
+            return false;
+        };
+        let now = now_secs();
+        used.retain(|_, expires| *expires >= now);
+        used.insert(challenge.to_string(), expires).is_none()
+    }
+
+    pub fn check(&self, keys: &[String], headers: &HeaderMap) -> Verdict {
+        let now = Instant::now();
+        let Ok(mut failures) = self.failures.lock() else {
+            return Verdict::Allow {
+                delay: Duration::ZERO,
+            };
+        };
+        let (count, locked_until) = keys
+            .iter()
+            .filter_map(|key| failures.get(key))
+            .filter(|f| {
+                now.duration_since(f.first) < self.window
+                    || f.locked_until.is_some_and(|until| until > now)
+            })
+            .fold((0, None), |(count, locked): (u32, Option<Instant>), f| {
+                (count.max(f.count), locked.max(f.locked_until))
+            });
+
+        if let Some(until) = locked_until.filter(|until| *until > now) {
+            return Verdict::Locked {
+                retry_after: until - now,
+            };
+        }
+        if self.challenge_after > 0 && count >= self.challenge_after && !self.solved(headers) {
+            return Verdict::ChallengeRequired;
+        }
+        self.reserve(&mut failures, keys, now);
+
+        let delay = match count.checked_sub(self.delay_after) {
+            Some(over) if self.delay_after > 0 => {
+                Duration::from_secs(1u64 << over.min(5)).min(self.max_delay)
+            }
+            _ => Duration::ZERO,
+        };
+        Verdict::Allow { delay }
+    }
+
+    fn reserve(&self, failures: &mut HashMap<String, Failures>, keys: &[String], now: Instant) {
+        if failures.len() >= MAX_TRACKED_KEYS {
+            let window = self.window;
+            failures.retain(|_, f| {
+                now.duration_since(f.first) < window || f.locked_until.is_some_and(|u| u > now)
+            });
+        }
+        for key in keys {
+            let entry = failures.entry(key.clone()).or_insert(Failures {
+                count: 0,
+                first: now,
+                locked_until: None,
+            });
+            let expired_lock = entry.locked_until.is_some_and(|until| until <= now);
+            if expired_lock || now.duration_since(entry.first) >= self.window {
+                *entry = Failures {
+                    count: 0,
+                    first: now,
+                    locked_until: None,
+                };
+            }
+            entry.count += 1;
+        }
+    }
+
+    pub fn record(&self, keys: &[String], status: StatusCode) {
+        let failed = matches!(
+            status,
+            StatusCode::BAD_REQUEST
+                | StatusCode::UNAUTHORIZED
+                | StatusCode::FORBIDDEN
+                | StatusCode::UNPROCESSABLE_ENTITY
+        );
+        let Ok(mut failures) = self.failures.lock() else {
+            return;
+        };
+        let now = Instant::now();
+
+        for key in keys {
+            if failed {
+                let Some(entry) = failures.get_mut(key) else {
+                    continue;
+                };
+                if is_lockable(key) && self.lockout_after > 0 && entry.count >= self.lockout_after {
+                    entry.locked_until = Some(now + self.lockout);
+                    warn!(
+                        "Locked out {key} after {} failed auth attempts",
+                        entry.count
+                    );
+                }
+            } else if status.is_success() && !key.starts_with("ip:") {
+                failures.remove(key);
+            } else if let Some(entry) = failures.get_mut(key) {
+                entry.count = entry.count.saturating_sub(1);
+            }
+        }
+    }
+
+    pub fn release(&self, keys: &[String]) {
+        self.record(keys, StatusCode::BAD_GATEWAY);
+    }
+}
+
+pub fn rejection(verdict: &Verdict) -> Option<Response> {
+    match verdict {
+        Verdict::Allow { .. } => None,
+        Verdict::ChallengeRequired => Some(
+            (
+                StatusCode::PRECONDITION_REQUIRED,
+                Json(serde_json::json!({
+                    "error": "Additional verification required, please wait...",
+                    "code": "challenge_required",
+                    "challenge_url": CHALLENGE_PATH,
+                })),
+            )
+                .into_response(),
+        ),
+        Verdict::Locked { retry_after } => {
+            let secs = retry_after.as_secs().max(1);
+            let mut response = (
+                StatusCode::TOO_MANY_REQUESTS,
+                Json(serde_json::json!({
+                    "error": format!(
+                        "Too many failed attempts. Try again in {} minute(s).",
+                        secs.div_ceil(60)
+                    ),
+                    "code": "locked_out",
+                })),
+            )
+                .into_response();
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(secs));
+            Some(response)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn guard() -> AuthGuard {
+        AuthGuard {
+            key: [7; 32],
+            window: Duration::from_secs(900),
+            delay_after: 2,
+            max_delay: Duration::from_secs(30),
+            challenge_after: 4,
+            difficulty: 4,
+            lockout_after: 6,
+            lockout: Duration::from_secs(900),
+            failures: Mutex::new(HashMap::new()),
+            used_challenges: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn keys(ip: u8) -> Vec<String> {
+        attempt_keys(
+            Some(IpAddr::V4(Ipv4Addr::new(10, 0, 0, ip))),
+            br#"{"email": " Ana@Example.com "}"#,
+        )
+    }
+
+    fn allow(delay: u64) -> Verdict {
+        Verdict::Allow {
+            delay: Duration::from_secs(delay),
+        }
+    }
+
+    fn solved(guard: &AuthGuard, challenge: &str) -> HeaderMap {
+        let solution = (0u64..)
+            .map(|n| n.to_string())
+            .find(|n| {
+                leading_zero_bits(&Sha256::digest(format!("{challenge}:{n}").as_bytes()))
+                    >= guard.difficulty
+            })
+            .unwrap_or_default();
+        let mut headers = HeaderMap::new();
+        for (name, value) in [(CHALLENGE_HEADER, challenge), (SOLUTION_HEADER, &solution)] {
+            if let Ok(value) = HeaderValue::from_str(value) {
+                headers.insert(name, value);
+            }
+        }
+        headers
+    }
+
+    #[test]
+    fn keys_cover_ip_account_and_pair() {
+        let keys = keys(1);
+        assert_eq!(keys.len(), 3);
+        assert_eq!(keys[0], "ip:10.0.0.1");
+        assert!(keys[1].starts_with("pair:") && keys[1].ends_with("@10.0.0.1"));
+        assert!(keys[2].starts_with("account:"));
+        assert_eq!(
+            attempt_keys(None, br#"{"username": "ana@example.com"}"#),
+            vec![keys[2].clone()]
+        );
+        assert_eq!(attempt_keys(None, b"not json"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn concurrent_attempts_are_counted_before_they_finish() {
+        let guard = guard();
+        let keys = keys(1);
+        let verdicts: Vec<_> = (0..5)
+            .map(|_| guard.check(&keys, &HeaderMap::new()))
+            .collect();
+        assert_eq!(
+            verdicts,
+            vec![
+                allow(0),
+                allow(0),
+                allow(1),
+                allow(2),
+                Verdict::ChallengeRequired
+            ]
+        );
+
+        for _ in 0..4 {
+            guard.release(&keys);
+        }
+        assert_eq!(guard.check(&keys, &HeaderMap::new()), allow(0));
+    }
+
+    #[test]
+    fn success_clears_the_account() {
+        let guard = guard();
+        let keys = keys(1);
+        for _ in 0..3 {
+            guard.check(&keys, &HeaderMap::new());
+            guard.record(&keys, StatusCode::UNAUTHORIZED);
+        }
+        guard.check(&keys, &HeaderMap::new());
+        guard.record(&keys, StatusCode::OK);
+
+        let failures = guard.failures.lock().map(|f| {
+            keys.iter()
+                .map(|k| f.get(k).map_or(0, |f| f.count))
+                .collect::<Vec<_>>()
+        });
+        assert_eq!(failures.ok(), Some(vec![3, 0, 0]));
+    }
+
+    #[test]
+    fn solved_challenges_are_single_use() {
+        let guard = guard();
+        let challenge = guard.new_challenge().unwrap_or_default();
+        let headers = solved(&guard, &challenge);
+        assert!(guard.solved(&headers));
+        assert!(!guard.solved(&headers));
+
+        let mut wrong = solved(&guard, &guard.new_challenge().unwrap_or_default());
+        wrong.insert(
+            CHALLENGE_HEADER,
+            HeaderValue::from_static("forged.challenge"),
+        );
+        assert!(!guard.solved(&wrong));
+    }
+
+    #[test]
+    fn lockout_stays_with_the_attacking_ip() {
+        let guard = guard();
+        let attacker = keys(1);
+        for _ in 0..6 {
+            if guard.check(&attacker, &HeaderMap::new()) == Verdict::ChallengeRequired {
+                let headers = solved(&guard, &guard.new_challenge().unwrap_or_default());
+                assert!(matches!(
+                    guard.check(&attacker, &headers),
+                    Verdict::Allow { .. }
+                ));
+            }
+            guard.record(&attacker, StatusCode::UNAUTHORIZED);
+        }
+        assert!(matches!(
+            guard.check(&attacker, &HeaderMap::new()),
+            Verdict::Locked { .. }
+        ));
+
+        let owner = keys(2);
+        assert_eq!(
+            guard.check(&owner, &HeaderMap::new()),
+            Verdict::ChallengeRequired
+        );
+    }
+}
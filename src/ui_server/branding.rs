@@ -0,0 +1,386 @@
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+use super::coalesce::Coalesce;
+use super::upstream::Upstream;
+use super::{html, route};
+
+pub const CONFIG_PATH: &str = "/api/bot/config";
+const DEFAULT_PROFILE: &str = "default";
+const MAX_REMOTE_PROFILES: usize = 1024;
+const FAILURE_TTL: Duration = Duration::from_secs(30);
+const FETCH_TIMEOUT: Duration = Duration::from_secs(3);
+
+type Fetched = (Instant, Duration, Branding);
+
+fn profile_name(bot_name: Option<&str>) -> Option<&str> {
+    bot_name.filter(|name| *name != DEFAULT_PROFILE && route::is_valid_name(name))
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Branding {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub favicon: Option<String>,
+    pub logo: Option<String>,
+    pub theme_color: Option<String>,
+    pub theme: Option<String>,
+}
+
+impl Branding {
+    fn or(self, fallback: Self) -> Self {
+        Self {
+            title: self.title.or(fallback.title),
+            description: self.description.or(fallback.description),
+            favicon: self.favicon.or(fallback.favicon),
+            logo: self.logo.or(fallback.logo),
+            theme_color: self.theme_color.or(fallback.theme_color),
+            theme: self.theme.or(fallback.theme),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    fn from_bot_config(config: &serde_json::Value) -> Self {
+        let field = |keys: &[&str]| {
+            keys.iter()
+                .find_map(|key| config.get(*key).and_then(|v| v.as_str()))
+                .filter(|v| !v.trim().is_empty())
+                .map(str::to_string)
+        };
+        Self {
+            title: field(&["theme_title", "theme-title"]),
+            description: field(&["theme_description", "theme-description", "description"]),
+            favicon: field(&["theme_favicon", "theme-favicon"]),
+            logo: field(&["theme_logo", "theme-logo"]),
+            theme_color: field(&["theme_color1", "theme-color1"]),
+            theme: field(&["theme_base", "theme-base"]),
+        }
+    }
+
+    pub fn apply(&self, html: &mut String) {
+        if let Some(title) = &self.title {
+            html::set_title(html, title);
+        }
+        if let Some(description) = &self.description {
+            html::upsert_tag(
+                html,
+                "meta",
+                r#"name="description""#,
+                &format!(
+                    r#"<meta name="description" content="{}">"#,
+                    html::escape(description)
+                ),
+            );
+        }
+        if let Some(color) = &self.theme_color {
+            html::upsert_tag(
+                html,
+                "meta",
+                r#"name="theme-color""#,
+                &format!(
+                    r#"<meta name="theme-color" content="{}">"#,
+                    html::escape(color)
+                ),
+            );
+        }
+        if let Some(favicon) = &self.favicon {
+            html::upsert_tag(
+                html,
+                "link",
+                r#"rel="icon""#,
+                &format!(r#"<link rel="icon" href="{}">"#, html::escape(favicon)),
+            );
+        }
+        if let Some(logo) = &self.logo {
+            let img = format!(
+                r#"<img class="logo-icon logo-icon-img" src="{}" alt="{}">"#,
+                html::escape(logo),
+                html::escape(self.title.as_deref().unwrap_or("Logo"))
+            );
+            *html = html::rewrite_tags(html, "div", |tag| {
+                tag.contains("data-branding-logo")
+                    .then(|| format!("{tag}{img}"))
+            });
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct BrandingStore {
+    local: HashMap<String, Branding>,
+    remote: RwLock<HashMap<String, Fetched>>,
+    fetches: Coalesce<Branding>,
+    ttl: Duration,
+}
+
+impl BrandingStore {
+    #[must_use]
+    pub fn from_env() -> Self {
+        let local = std::env::var("BOTUI_BRANDING_FILE")
+            .ok()
+            .map(|path| match std::fs::read_to_string(&path) {
+                Ok(raw) => {
+                    serde_json::from_str::<HashMap<String, Branding>>(&raw).unwrap_or_else(|e| {
+                        error!("Invalid branding file {path}: {e}");
+                        HashMap::new()
+                    })
+                }
+                Err(e) => {
+                    error!("Failed to read branding file {path}: {e}");
+                    HashMap::new()
+                }
+            })
+            .unwrap_or_default();
+
+        if !local.is_empty() {
+            info!("Loaded {} local branding profile(s)", local.len());
+        }
+
+        let ttl = std::env::var("BOTUI_BRANDING_TTL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(300);
+
+        Self {
+            local,
+            remote: RwLock::new(HashMap::new()),
+            fetches: Coalesce::default(),
+            ttl: Duration::from_secs(ttl),
+        }
+    }
+
+    fn cached(&self, bot_name: &str) -> Option<Branding> {
+        let remote = self.remote.read().ok()?;
+        remote
+            .get(bot_name)
+            .filter(|(fetched, ttl, _)| fetched.elapsed() < *ttl)
+            .map(|(_, _, branding)| branding.clone())
+    }
+
+    fn store(&self, bot_name: &str, ttl: Duration, branding: Branding) {
+        let Ok(mut remote) = self.remote.write() else {
+            return;
+        };
+        if remote.len() >= MAX_REMOTE_PROFILES && !remote.contains_key(bot_name) {
+            remote.retain(|_, (fetched, ttl, _)| fetched.elapsed() < *ttl);
+            if remote.len() >= MAX_REMOTE_PROFILES {
+                let oldest = remote
+                    .iter()
+                    .min_by_key(|(_, (fetched, _, _))| *fetched)
+                    .map(|(name, _)| name.clone());
+                if let Some(oldest) = oldest {
+                    remote.remove(&oldest);
+                }
+            }
+        }
+        remote.insert(bot_name.to_string(), (Instant::now(), ttl, branding));
+    }
+
+    async fn fetch(upstream: &Upstream, bot_name: &str) -> Option<Branding> {
+        let response = upstream
+            .client()
+            .get(format!("{}{CONFIG_PATH}", upstream.base_url()))
+            .timeout(FETCH_TIMEOUT)
+            .query(&[("bot_name", bot_name)])
+            .send()
+            .await;
+
+        match response {
+            Ok(resp) if resp.status().is_success() => {
+                match resp.json::<serde_json::Value>().await {
+                    Ok(config) => Some(Branding::from_bot_config(&config)),
+                    Err(e) => {
+                        warn!("Invalid bot config for '{bot_name}': {e}");
+                        None
+                    }
+                }
+            }
+            Ok(resp) => {
+                debug!("Bot config for '{bot_name}' returned {}", resp.status());
+                None
+            }
+            Err(e) => {
+                warn!("Failed to fetch bot config for '{bot_name}': {e}");
+                None
+            }
+        }
+    }
+
+    async fn remote(&self, upstream: &Upstream, bot_name: &str) -> Branding {
+        if let Some(branding) = self.cached(bot_name) {
+            return branding;
+        }
+        self.fetches
+            .run(bot_name, || async {
+                let fetched = Self::fetch(upstream, bot_name).await;
+                let ttl = if fetched.is_some() {
+                    self.ttl
+                } else {
+                    FAILURE_TTL.min(self.ttl)
+                };
+                let branding = fetched.unwrap_or_default();
+                self.store(bot_name, ttl, branding.clone());
+                branding
+            })
+            .await
+    }
+
+    pub async fn resolve(
+        &self,
+        upstream: Option<&Upstream>,
+        bot_name: Option<&str>,
+    ) -> Option<Branding> {
+        let remote = match (profile_name(bot_name), upstream) {
+            (Some(name), Some(upstream)) => self.remote(upstream, name).await,
+            (Some(name), None) => self.cached(name).unwrap_or_default(),
+            (None, _) => Branding::default(),
+        };
+        self.merge(bot_name, remote)
+    }
+
+    pub fn resolve_cached(&self, bot_name: Option<&str>) -> Option<Branding> {
+        let remote = profile_name(bot_name)
+            .and_then(|name| self.cached(name))
+            .unwrap_or_default();
+        self.merge(bot_name, remote)
+    }
+
+    fn merge(&self, bot_name: Option<&str>, remote: Branding) -> Option<Branding> {
+        let fallback = self.local.get(DEFAULT_PROFILE).cloned().unwrap_or_default();
+        let Some(bot_name) = profile_name(bot_name) else {
+            return Some(fallback).filter(|b| !b.is_empty());
+        };
+        let branding = self
+            .local
+            .get(bot_name)
+            .cloned()
+            .unwrap_or_default()
+            .or(remote)
+            .or(fallback);
+
+        Some(branding).filter(|b| !b.is_empty())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store(local: HashMap<String, Branding>) -> BrandingStore {
+        BrandingStore {
+            local,
+            remote: RwLock::new(HashMap::new()),
+            fetches: Coalesce::default(),
+            ttl: Duration::from_secs(300),
+        }
+    }
+
+    #[test]
+    fn reads_bot_config_keys() {
+        let branding = Branding::from_bot_config(&serde_json::json!({
+            "theme-title": "Edu",
+            "theme_logo": "/edu/logo.png",
+            "theme_color1": " ",
+            "theme-base": "cyberpunk",
+        }));
+        assert_eq!(branding.title.as_deref(), Some("Edu"));
+        assert_eq!(branding.logo.as_deref(), Some("/edu/logo.png"));
+        assert_eq!(branding.theme_color, None);
+        assert_eq!(branding.theme.as_deref(), Some("cyberpunk"));
+    }
+
+    #[test]
+    fn renders_head_and_logo() {
+        let branding = Branding {
+            title: Some("Edu <Bot>".to_string()),
+            logo: Some("/edu/logo.png?a=1&b=2".to_string()),
+            ..Branding::default()
+        };
+        let mut page = r#"<html><head><title>General Bots</title></head><body><aside><div class="sidebar-logo" data-branding-logo></div><div class="x"></div></aside></body></html>"#.to_string();
+        branding.apply(&mut page);
+        assert!(page.contains("<title>Edu &lt;Bot&gt;</title>"));
+        assert!(page.contains(r#"data-branding-logo><img class="logo-icon logo-icon-img" src="/edu/logo.png?a=1&amp;b=2" alt="Edu &lt;Bot&gt;"></div><div class="x"></div>"#));
+        assert!(!page.contains("<script"));
+    }
+
+    #[tokio::test]
+    async fn resolves_local_profiles_without_fetching() {
+        let local = HashMap::from([
+            (
+                "default".to_string(),
+                Branding {
+                    title: Some("General Bots".to_string()),
+                    favicon: Some("/favicon.ico".to_string()),
+                    ..Branding::default()
+                },
+            ),
+            (
+                "edu".to_string(),
+                Branding {
+                    title: Some("Edu".to_string()),
+                    ..Branding::default()
+                },
+            ),
+        ]);
+        let store = store(local);
+        store.store("edu", Duration::from_secs(60), Branding::default());
+
+        let edu = store.resolve(None, Some("edu")).await;
+        assert_eq!(edu.as_ref().and_then(|b| b.title.as_deref()), Some("Edu"));
+        assert_eq!(
+            edu.and_then(|b| b.favicon),
+            Some("/favicon.ico".to_string())
+        );
+
+        let invalid = store.resolve(None, Some("../x")).await;
+        assert_eq!(
+            invalid.and_then(|b| b.title),
+            Some("General Bots".to_string())
+        );
+        assert!(store.cached("../x").is_none());
+    }
+
+    #[test]
+    fn cached_lookup_never_fetches() {
+        let store = store(HashMap::new());
+        assert!(store.resolve_cached(Some("edu")).is_none());
+        store.store(
+            "edu",
+            Duration::from_secs(60),
+            Branding {
+                theme: Some("typewriter".to_string()),
+                ..Branding::default()
+            },
+        );
+        assert_eq!(
+            store.resolve_cached(Some("edu")).and_then(|b| b.theme),
+            Some("typewriter".to_string())
+        );
+    }
+
+    #[test]
+    fn bounds_the_remote_cache() {
+        let store = store(HashMap::new());
+        store.store("gone", Duration::ZERO, Branding::default());
+        assert!(store.cached("gone").is_none());
+        for i in 0..MAX_REMOTE_PROFILES + 10 {
+            store.store(
+                &format!("bot{i}"),
+                Duration::from_secs(60),
+                Branding::default(),
+            );
+        }
+        let len = store.remote.read().map(|r| r.len()).unwrap_or_default();
+        assert_eq!(len, MAX_REMOTE_PROFILES);
+        assert!(store.cached("bot0").is_none());
+        assert!(store
+            .cached(&format!("bot{}", MAX_REMOTE_PROFILES + 9))
+            .is_some());
+    }
+}
@@ -0,0 +1,69 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use tokio::sync::OnceCell;
+
+#[derive(Debug)]
+pub struct Coalesce<T> {
+    inflight: Mutex<HashMap<String, Arc<OnceCell<T>>>>,
+}
+
+impl<T> Default for Coalesce<T> {
+    fn default() -> Self {
+        Self {
+            inflight: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<T: Clone> Coalesce<T> {
+    pub async fn run<F, Fut>(&self, key: &str, fetch: F) -> T
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = T>,
+    {
+        let cell = match self.inflight.lock() {
+            Ok(mut inflight) => inflight.entry(key.to_string()).or_default().clone(),
+            Err(_) => Arc::new(OnceCell::new()),
+        };
+        let value = cell.get_or_init(fetch).await.clone();
+        if let Ok(mut inflight) = self.inflight.lock() {
+            if inflight.get(key).is_some_and(|c| Arc::ptr_eq(c, &cell)) {
+                inflight.remove(key);
+            }
+        }
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn shares_one_fetch_between_callers() {
+        let coalesce = Arc::new(Coalesce::<usize>::default());
+        let calls = Arc::new(AtomicUsize::new(0));
+        let tasks: Vec<_> = (0..5)
+            .map(|_| {
+                let (coalesce, calls) = (coalesce.clone(), calls.clone());
+                tokio::spawn(async move {
+                    coalesce
+                        .run("bot", || async {
+                            tokio::time::sleep(Duration::from_millis(20)).await;
+                            calls.fetch_add(1, Ordering::SeqCst) + 1
+                        })
+                        .await
+                })
+            })
+            .collect();
+        for task in tasks {
+            assert_eq!(task.await.ok(), Some(1));
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        assert_eq!(coalesce.run("bot", || async { 7 }).await, 7);
+    }
+}
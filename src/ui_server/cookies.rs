@@ -0,0 +1,129 @@
+use axum::http::{header, HeaderMap};
+
+fn pairs(headers: &HeaderMap) -> impl Iterator<Item = (&str, &str)> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .map(|(name, value)| (name.trim(), value.trim()))
+}
+
+pub fn get(headers: &HeaderMap, name: &str) -> Option<String> {
+    pairs(headers)
+        .find(|(cookie, _)| *cookie == name)
+        .map(|(_, value)| value.to_string())
+}
+
+pub fn without(headers: &HeaderMap, names: &[&str]) -> Option<String> {
+    let kept: Vec<String> = pairs(headers)
+        .filter(|(cookie, _)| !names.contains(cookie))
+        .map(|(cookie, value)| format!("{cookie}={value}"))
+        .collect();
+    (!kept.is_empty()).then(|| kept.join("; "))
+}
+
+pub fn set_by(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get_all(header::SET_COOKIE)
+        .iter()
+        .rev()
+        .filter_map(|value| value.to_str().ok())
+        .filter_map(|value| value.split(';').next()?.split_once('='))
+        .find(|(cookie, _)| cookie.trim() == name)
+        .map(|(_, value)| value.trim().to_string())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CookieOptions {
+    pub http_only: bool,
+    pub secure: bool,
+    pub same_site: &'static str,
+}
+
+pub fn set(name: &str, value: &str, max_age_secs: u64, options: CookieOptions) -> String {
+    let mut cookie = format!(
+        "{name}={value}; Path=/; Max-Age={max_age_secs}; SameSite={}",
+        options.same_site
+    );
+    if options.http_only {
+        cookie.push_str("; HttpOnly");
+    }
+    if options.secure {
+        cookie.push_str("; Secure");
+    }
+    cookie
+}
+
+pub fn clear(name: &str, options: CookieOptions) -> String {
+    set(name, "", 0, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn headers(cookies: &[&'static str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for cookie in cookies {
+            headers.append(header::COOKIE, HeaderValue::from_static(cookie));
+        }
+        headers
+    }
+
+    #[test]
+    fn reads_cookies_across_headers() {
+        let headers = headers(&["a=1; gb_session = abc ", "b=2"]);
+        assert_eq!(get(&headers, "gb_session").as_deref(), Some("abc"));
+        assert_eq!(get(&headers, "b").as_deref(), Some("2"));
+        assert_eq!(get(&headers, "gb"), None);
+    }
+
+    #[test]
+    fn strips_named_cookies() {
+        let headers = headers(&["a=1; gb_session=abc; b=2"]);
+        assert_eq!(
+            without(&headers, &["gb_session"]).as_deref(),
+            Some("a=1; b=2")
+        );
+        assert_eq!(without(&headers, &["a", "b", "gb_session"]), None);
+    }
+
+    #[test]
+    fn reads_cookies_set_by_a_response() {
+        let mut response = HeaderMap::new();
+        for cookie in [
+            "gb_session=old; Max-Age=0",
+            "a=1",
+            "gb_session=new; HttpOnly",
+        ] {
+            response.append(header::SET_COOKIE, HeaderValue::from_static(cookie));
+        }
+        assert_eq!(set_by(&response, "gb_session").as_deref(), Some("new"));
+        assert_eq!(set_by(&response, "b"), None);
+    }
+
+    #[test]
+    fn renders_cookie_attributes() {
+        let options = CookieOptions {
+            http_only: true,
+            secure: true,
+            same_site: "Strict",
+        };
+        assert_eq!(
+            set("gb_session", "abc", 60, options),
+            "gb_session=abc; Path=/; Max-Age=60; SameSite=Strict; HttpOnly; Secure"
+        );
+        let public = CookieOptions {
+            http_only: false,
+            secure: false,
+            same_site: "Lax",
+        };
+        assert_eq!(
+            clear("gb_session_active", public),
+            "gb_session_active=; Path=/; Max-Age=0; SameSite=Lax"
+        );
+    }
+}
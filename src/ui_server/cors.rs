@@ -0,0 +1,347 @@
+use axum::{
+    extract::OriginalUri,
+    http::{header, request::Parts, HeaderMap, HeaderName, HeaderValue, Method, Uri},
+};
+use log::{error, info, warn};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tower_http::cors::{AllowHeaders, AllowMethods, AllowOrigin, CorsLayer, ExposeHeaders};
+
+use super::hosts::HostMap;
+use super::route;
+
+fn default_methods() -> Vec<String> {
+    ["GET", "POST", "PUT", "PATCH", "DELETE"]
+        .iter()
+        .map(|m| (*m).to_string())
+        .collect()
+}
+
+fn default_headers() -> Vec<String> {
+    [
+        "authorization",
+        "content-type",
+        "x-requested-with",
+        "x-csrf-token",
+        "hx-request",
+        "hx-target",
+        "hx-trigger",
+        "hx-current-url",
+    ]
+    .iter()
+    .map(|h| (*h).to_string())
+    .collect()
+}
+
+const fn default_max_age() -> u64 {
+    600
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CorsPolicy {
+    pub origins: Vec<String>,
+    pub credentials: bool,
+    pub methods: Vec<String>,
+    pub headers: Vec<String>,
+    pub expose_headers: Vec<String>,
+    pub max_age_secs: u64,
+}
+
+impl Default for CorsPolicy {
+    fn default() -> Self {
+        Self {
+            origins: Vec::new(),
+            credentials: false,
+            methods: default_methods(),
+            headers: default_headers(),
+            expose_headers: Vec::new(),
+            max_age_secs: default_max_age(),
+        }
+    }
+}
+
+fn origin_matches(pattern: &str, origin: &str) -> bool {
+    if pattern == "*" || pattern.eq_ignore_ascii_case(origin) {
+        return true;
+    }
+    let Some((scheme, host_pattern)) = pattern.split_once("://*.") else {
+        return false;
+    };
+    let Some((origin_scheme, origin_host)) = origin.split_once("://") else {
+        return false;
+    };
+    scheme.eq_ignore_ascii_case(origin_scheme)
+        && origin_host
+            .to_ascii_lowercase()
+            .strip_suffix(&host_pattern.to_ascii_lowercase())
+            .is_some_and(|sub| sub.len() > 1 && sub.ends_with('.'))
+}
+
+impl CorsPolicy {
+    fn allows(&self, origin: &str) -> bool {
+        self.origins.iter().any(|p| origin_matches(p, origin))
+    }
+
+    fn is_unsafe(&self) -> bool {
+        self.credentials && self.origins.iter().any(|o| o == "*")
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct CorsConfig {
+    default: Option<CorsPolicy>,
+    routes: HashMap<String, CorsPolicy>,
+    bots: HashMap<String, Vec<String>>,
+}
+
+impl CorsConfig {
+    #[must_use]
+    pub fn from_env() -> Self {
+        let mut config = std::env::var("BOTUI_CORS_FILE")
+            .ok()
+            .and_then(|path| match std::fs::read_to_string(&path) {
+                Ok(raw) => serde_json::from_str::<Self>(&raw)
+                    .map_err(|e| error!("Invalid CORS file {path}: {e}"))
+                    .ok(),
+                Err(e) => {
+                    error!("Failed to read CORS file {path}: {e}");
+                    None
+                }
+            })
+            .unwrap_or_default();
+
+        if let Ok(origins) = std::env::var("BOTUI_CORS_ORIGINS") {
+            let origins = origins
+                .split(',')
+                .map(str::trim)
+                .filter(|o| !o.is_empty())
+                .map(str::to_string);
+            config
+                .default
+                .get_or_insert_with(CorsPolicy::default)
+                .origins
+                .extend(origins);
+        }
+
+        config.drop_unsafe();
+        if config.is_configured() {
+            info!(
+                "CORS enabled ({} route policies, {} bot origin lists)",
+                config.routes.len() + usize::from(config.default.is_some()),
+                config.bots.len()
+            );
+        }
+        config
+    }
+
+    fn drop_unsafe(&mut self) {
+        if self.default.as_ref().is_some_and(CorsPolicy::is_unsafe) {
+            error!(
+                "Ignoring default CORS policy: origin \"*\" cannot be combined with credentials"
+            );
+            self.default = None;
+        }
+        self.routes.retain(|mount, policy| {
+            if policy.is_unsafe() {
+                error!("Ignoring CORS policy for {mount}: origin \"*\" cannot be combined with credentials");
+            }
+            !policy.is_unsafe()
+        });
+    }
+
+    pub fn is_configured(&self) -> bool {
+        self.default.is_some() || !self.routes.is_empty() || !self.bots.is_empty()
+    }
+
+    fn policy(&self, mount: &str) -> Option<&CorsPolicy> {
+        self.routes.get(mount).or(self.default.as_ref())
+    }
+
+    pub fn allows(&self, mount: &str, origin: &str, bot: Option<&str>) -> bool {
+        self.policy(mount).is_some_and(|p| p.allows(origin))
+            || bot
+                .and_then(|b| self.bots.get(b))
+                .is_some_and(|list| list.iter().any(|p| origin_matches(p, origin)))
+    }
+
+    pub fn layer(self: &Arc<Self>, mount: &'static str, hosts: Arc<HostMap>) -> Option<CorsLayer> {
+        if self.policy(mount).is_none() && self.bots.is_empty() {
+            return None;
+        }
+        let policy = self.policy(mount).cloned().unwrap_or_default();
+
+        let config = Arc::clone(self);
+        let allow_origin = AllowOrigin::predicate(move |origin: &HeaderValue, parts: &Parts| {
+            let Ok(origin) = origin.to_str() else {
+                return false;
+            };
+            let uri = parts
+                .extensions
+                .get::<OriginalUri>()
+                .map_or(&parts.uri, |original| &original.0);
+            let bot = request_bot(uri, &parts.headers, &hosts);
+            config.allows(mount, origin, bot.as_deref())
+        });
+
+        let allow_methods = if policy.methods.iter().any(|m| m == "*") {
+            AllowMethods::mirror_request()
+        } else {
+            AllowMethods::list(
+                policy
+                    .methods
+                    .iter()
+                    .filter_map(|m| Method::from_bytes(m.to_ascii_uppercase().as_bytes()).ok()),
+            )
+        };
+        let allow_headers = if policy.headers.iter().any(|h| h == "*") {
+            AllowHeaders::mirror_request()
+        } else {
+            AllowHeaders::list(header_names(&policy.headers))
+        };
+
+        Some(
+            CorsLayer::new()
+                .allow_origin(allow_origin)
+                .allow_methods(allow_methods)
+                .allow_headers(allow_headers)
+                .expose_headers(ExposeHeaders::list(header_names(&policy.expose_headers)))
+                .allow_credentials(policy.credentials)
+                .max_age(Duration::from_secs(policy.max_age_secs)),
+        )
+    }
+
+    pub fn allows_ws(&self, headers: &HeaderMap, bot: Option<&str>) -> bool {
+        let Some(origin) = headers.get(header::ORIGIN).and_then(|v| v.to_str().ok()) else {
+            warn!("Rejected WebSocket without Origin (bot: {bot:?})");
+            return false;
+        };
+        let same_host = headers
+            .get(header::HOST)
+            .and_then(|v| v.to_str().ok())
+            .zip(origin.split_once("://").map(|(_, authority)| authority))
+            .is_some_and(|(host, authority)| host.eq_ignore_ascii_case(authority));
+        if same_host || self.allows("/ws", origin, bot) {
+            return true;
+        }
+        warn!("Rejected WebSocket from origin {origin} (bot: {bot:?})");
+        false
+    }
+}
+
+fn header_names(names: &[String]) -> Vec<HeaderName> {
+    names
+        .iter()
+        .filter_map(|h| HeaderName::try_from(h.as_str()).ok())
+        .collect()
+}
+
+fn request_bot(uri: &Uri, headers: &HeaderMap, hosts: &HostMap) -> Option<String> {
+    route::parse(uri.path())
+        .bot
+        .or_else(|| route::parse_ws(uri.path()).bot)
+        .or_else(|| {
+            uri.query()?
+                .split('&')
+                .filter_map(|pair| pair.split_once('='))
+                .find(|(key, _)| *key == "bot_name")
+                .map(|(_, value)| value.to_string())
+                .filter(|value| route::is_valid_name(value))
+        })
+        .or_else(|| hosts.bot_for_request(headers, uri))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(json: &str) -> CorsConfig {
+        let mut config: CorsConfig = serde_json::from_str(json).unwrap_or_default();
+        config.drop_unsafe();
+        config
+    }
+
+    fn upgrade(origin: Option<&'static str>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::HOST, HeaderValue::from_static("bots.example.com"));
+        if let Some(origin) = origin {
+            headers.insert(header::ORIGIN, HeaderValue::from_static(origin));
+        }
+        headers
+    }
+
+    #[test]
+    fn matches_exact_and_subdomain_origins() {
+        assert!(origin_matches("https://a.example", "https://A.example"));
+        assert!(origin_matches(
+            "https://*.example.com",
+            "https://x.example.com"
+        ));
+        assert!(origin_matches(
+            "https://*.example.com",
+            "https://a.b.example.com"
+        ));
+        assert!(!origin_matches(
+            "https://*.example.com",
+            "https://example.com"
+        ));
+        assert!(!origin_matches(
+            "https://*.example.com",
+            "https://evilexample.com"
+        ));
+        assert!(!origin_matches(
+            "https://*.example.com",
+            "http://x.example.com"
+        ));
+        assert!(origin_matches("*", "https://anything.example"));
+    }
+
+    #[test]
+    fn routes_fall_back_to_default_and_bots_add_origins() {
+        let config = config(
+            r#"{
+                "default": { "origins": ["https://partner.example"] },
+                "routes": { "/ws": { "origins": ["https://*.chat.example"] } },
+                "bots": { "edu": ["https://school.example"] }
+            }"#,
+        );
+        assert!(config.allows("/api", "https://partner.example", None));
+        assert!(!config.allows("/ws", "https://partner.example", None));
+        assert!(config.allows("/ws", "https://a.chat.example", None));
+        assert!(config.allows("/api", "https://school.example", Some("edu")));
+        assert!(!config.allows("/api", "https://school.example", Some("crm")));
+    }
+
+    #[test]
+    fn rejects_any_origin_with_credentials() {
+        let config = config(
+            r#"{
+                "default": { "origins": ["*"], "credentials": true },
+                "routes": {
+                    "/api": { "origins": ["*"], "credentials": true },
+                    "/apps": { "origins": ["*"] }
+                }
+            }"#,
+        );
+        assert!(config.default.is_none());
+        assert!(!config.routes.contains_key("/api"));
+        assert!(config.allows("/apps", "https://any.example", None));
+        assert!(!config.allows("/api", "https://any.example", None));
+    }
+
+    #[test]
+    fn websockets_need_a_same_host_or_allowed_origin() {
+        let unconfigured = CorsConfig::default();
+        assert!(unconfigured.allows_ws(&upgrade(Some("https://bots.example.com")), None));
+        assert!(!unconfigured.allows_ws(&upgrade(Some("https://evil.example")), None));
+        assert!(!unconfigured.allows_ws(&upgrade(None), None));
+
+        let config =
+            config(r#"{ "routes": { "/ws": { "origins": ["https://partner.example"] } } }"#);
+        assert!(config.allows_ws(&upgrade(Some("https://partner.example")), None));
+        assert!(!config.allows_ws(&upgrade(Some("https://evil.example")), None));
+    }
+}
@@ -0,0 +1,281 @@
+use axum::{
+    body::Body,
+    http::{header, HeaderMap, HeaderValue, Method, Response},
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use log::info;
+use sha2::{Digest, Sha256};
+
+use super::cookies::{self, CookieOptions};
+
+pub const COOKIE_NAME: &str = "gb_csrf";
+pub const HEADER_NAME: &str = "x-csrf-token";
+
+const TOKEN_MAX_AGE_SECS: u64 = 86_400;
+const DEFAULT_EXEMPT: &[&str] = &["/api/webhooks/*", "/api/webhook/*"];
+
+pub struct CsrfGuard {
+    key: Vec<u8>,
+    exempt: Vec<String>,
+    cookie_options: CookieOptions,
+}
+
+impl std::fmt::Debug for CsrfGuard {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CsrfGuard")
+            .field("exempt", &self.exempt)
+            .field("cookie_options", &self.cookie_options)
+            .finish_non_exhaustive()
+    }
+}
+
+impl CsrfGuard {
+    #[must_use]
+    pub fn from_env() -> Option<Self> {
+        let session_key = std::env::var("BOTUI_SESSION_KEY")
+            .ok()
+            .filter(|k| !k.is_empty());
+        let enabled = match std::env::var("BOTUI_CSRF").as_deref() {
+            Ok("true" | "1") => true,
+            Ok("false" | "0") => false,
+            _ => session_key.is_some(),
+        };
+        if !enabled {
+            return None;
+        }
+
+        let key = match std::env::var("BOTUI_CSRF_KEY")
+            .ok()
+            .filter(|k| !k.is_empty())
+        {
+            Some(secret) => Sha256::digest(secret.as_bytes()).to_vec(),
+            None => match session_key {
+                Some(secret) => Sha256::digest(format!("csrf:{secret}").as_bytes()).to_vec(),
+                None => rand::random::<[u8; 32]>().to_vec(),
+            },
+        };
+
+        let exempt: Vec<String> = DEFAULT_EXEMPT
+            .iter()
+            .map(|p| (*p).to_string())
+            .chain(
+                std::env::var("BOTUI_CSRF_EXEMPT")
+                    .unwrap_or_default()
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|p| !p.is_empty())
+                    .map(str::to_string),
+            )
+            .collect();
+
+        let secure = std::env::var("BOTUI_COOKIE_SECURE").map_or(true, |v| v != "false");
+
+        info!(
+            "CSRF protection enabled ({} exempt path pattern(s))",
+            exempt.len()
+        );
+
+        Some(Self {
+            key,
+            exempt,
+            cookie_options: CookieOptions {
+                http_only: false,
+                secure,
+                same_site: "Strict",
+            },
+        })
+    }
+
+    fn mac(&self, session: Option<&str>) -> Option<Hmac<Sha256>> {
+        let session = session.unwrap_or_default().as_bytes();
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).ok()?;
+        mac.update(&(session.len() as u64).to_be_bytes());
+        mac.update(session);
+        Some(mac)
+    }
+
+    fn mint(&self, session: Option<&str>) -> Option<String> {
+        let nonce = rand::random::<[u8; 16]>();
+        let mut mac = self.mac(session)?;
+        mac.update(&nonce);
+        Some(format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(nonce),
+            URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes())
+        ))
+    }
+
+    fn is_valid(&self, token: &str, session: Option<&str>) -> bool {
+        let Some((nonce, signature)) = token.split_once('.') else {
+            return false;
+        };
+        let (Ok(nonce), Ok(signature), Some(mut mac)) = (
+            URL_SAFE_NO_PAD.decode(nonce),
+            URL_SAFE_NO_PAD.decode(signature),
+            self.mac(session),
+        ) else {
+            return false;
+        };
+        mac.update(&nonce);
+        mac.verify_slice(&signature).is_ok()
+    }
+
+    fn is_exempt(&self, path: &str) -> bool {
+        self.exempt
+            .iter()
+            .any(|pattern| match pattern.strip_suffix('*') {
+                Some(prefix) => path.starts_with(prefix),
+                None => path == pattern,
+            })
+    }
+
+    pub fn issue(&self, headers: &HeaderMap, session: Option<&str>) -> (String, Option<String>) {
+        if let Some(token) =
+            cookies::get(headers, COOKIE_NAME).filter(|t| self.is_valid(t, session))
+        {
+            return (token, None);
+        }
+        let token = self.mint(session).unwrap_or_default();
+        let cookie = cookies::set(COOKIE_NAME, &token, TOKEN_MAX_AGE_SECS, self.cookie_options);
+        (token, Some(cookie))
+    }
+
+    pub fn check(
+        &self,
+        method: &Method,
+        path: &str,
+        headers: &HeaderMap,
+        session: Option<&str>,
+    ) -> Result<(), &'static str> {
+        if matches!(
+            *method,
+            Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE
+        ) || self.is_exempt(path)
+            || headers.contains_key(header::AUTHORIZATION)
+        {
+            return Ok(());
+        }
+
+        let Some(cookie) = cookies::get(headers, COOKIE_NAME) else {
+            return Err("missing CSRF cookie");
+        };
+        let Some(sent) = headers.get(HEADER_NAME).and_then(|v| v.to_str().ok()) else {
+            return Err("missing CSRF header");
+        };
+        if !self.is_valid(&cookie, session) {
+            return Err("invalid CSRF cookie");
+        }
+        let matches = cookie.len() == sent.len()
+            && cookie
+                .bytes()
+                .zip(sent.bytes())
+                .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+                == 0;
+        if matches {
+            Ok(())
+        } else {
+            Err("CSRF header does not match cookie")
+        }
+    }
+}
+
+pub fn attach_cookie(cookie: Option<String>, response: &mut Response<Body>) {
+    if let Some(value) = cookie.and_then(|c| HeaderValue::from_str(&c).ok()) {
+        response.headers_mut().append(header::SET_COOKIE, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn guard() -> CsrfGuard {
+        CsrfGuard {
+            key: b"test-csrf-key".to_vec(),
+            exempt: DEFAULT_EXEMPT.iter().map(|p| (*p).to_string()).collect(),
+            cookie_options: CookieOptions {
+                http_only: false,
+                secure: true,
+                same_site: "Strict",
+            },
+        }
+    }
+
+    fn request(cookie: &str, header: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if let Ok(value) = HeaderValue::from_str(&format!("{COOKIE_NAME}={cookie}")) {
+            headers.insert(header::COOKIE, value);
+        }
+        if let Ok(value) = HeaderValue::from_str(header) {
+            headers.insert(HEADER_NAME, value);
+        }
+        headers
+    }
+
+    #[test]
+    fn tokens_are_bound_to_the_session() {
+        let guard = guard();
+        let token = guard.mint(Some("session-a")).unwrap_or_default();
+
+        assert!(guard.is_valid(&token, Some("session-a")));
+        assert!(!guard.is_valid(&token, Some("session-b")));
+        assert!(!guard.is_valid(&token, None));
+        assert!(!guard.is_valid("forged.token", Some("session-a")));
+
+        let anonymous = guard.mint(None).unwrap_or_default();
+        assert!(guard.is_valid(&anonymous, None));
+        assert!(!guard.is_valid(&anonymous, Some("session-a")));
+    }
+
+    #[test]
+    fn issue_reuses_only_tokens_of_the_same_session() {
+        let guard = guard();
+        let (token, cookie) = guard.issue(&HeaderMap::new(), Some("a"));
+        assert!(cookie.is_some_and(|c| c.starts_with(&format!("{COOKIE_NAME}={token};"))));
+
+        let headers = request(&token, "");
+        assert_eq!(guard.issue(&headers, Some("a")), (token.clone(), None));
+        let (rotated, cookie) = guard.issue(&headers, Some("b"));
+        assert_ne!(rotated, token);
+        assert!(cookie.is_some());
+    }
+
+    #[test]
+    fn check_requires_a_matching_header() {
+        let guard = guard();
+        let token = guard.mint(Some("a")).unwrap_or_default();
+        let post = Method::POST;
+
+        assert_eq!(
+            guard.check(&post, "/api/x", &request(&token, &token), Some("a")),
+            Ok(())
+        );
+        assert_eq!(
+            guard.check(&post, "/api/x", &request(&token, "other"), Some("a")),
+            Err("CSRF header does not match cookie")
+        );
+        assert_eq!(
+            guard.check(&post, "/api/x", &request(&token, &token), Some("b")),
+            Err("invalid CSRF cookie")
+        );
+        assert_eq!(
+            guard.check(&post, "/api/x", &HeaderMap::new(), Some("a")),
+            Err("missing CSRF cookie")
+        );
+    }
+
+    #[test]
+    fn check_skips_safe_and_exempt_requests() {
+        let guard = guard();
+        let empty = HeaderMap::new();
+        assert_eq!(guard.check(&Method::GET, "/api/x", &empty, None), Ok(()));
+        assert_eq!(
+            guard.check(&Method::POST, "/api/webhooks/stripe", &empty, None),
+            Ok(())
+        );
+        let mut bearer = HeaderMap::new();
+        bearer.insert(header::AUTHORIZATION, HeaderValue::from_static("Bearer t"));
+        assert_eq!(guard.check(&Method::POST, "/api/x", &bearer, None), Ok(()));
+    }
+}
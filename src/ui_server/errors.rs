@@ -0,0 +1,289 @@
+use axum::{
+    http::{header, HeaderMap, HeaderName, HeaderValue, Method, StatusCode, Uri},
+    response::{Html, IntoResponse, Json, Response},
+};
+use log::{debug, error, warn};
+
+use super::{html, read_ui_text};
+
+const REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+const ERROR_FRAGMENT: HeaderName = HeaderName::from_static("x-gb-error");
+const ERROR_PAGE: &str = "suite/error.html";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    CsrfFailed,
+    BadRequest,
+    Unauthenticated,
+    Forbidden,
+    NotFound,
+    MethodNotAllowed,
+    PayloadTooLarge,
+    UpstreamUnreachable,
+    UpstreamTimeout,
+    Unavailable,
+    Maintenance,
+    Internal,
+}
+
+impl ErrorKind {
+    pub fn status(self) -> StatusCode {
+        match self {
+            Self::CsrfFailed | Self::Forbidden => StatusCode::FORBIDDEN,
+            Self::BadRequest => StatusCode::BAD_REQUEST,
+            Self::Unauthenticated => StatusCode::UNAUTHORIZED,
+            Self::NotFound => StatusCode::NOT_FOUND,
+            Self::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+            Self::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::UpstreamUnreachable => StatusCode::BAD_GATEWAY,
+            Self::UpstreamTimeout => StatusCode::GATEWAY_TIMEOUT,
+            Self::Unavailable | Self::Maintenance => StatusCode::SERVICE_UNAVAILABLE,
+            Self::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn code(self) -> &'static str {
+        match self {
+            Self::CsrfFailed => "csrf_failed",
+            Self::BadRequest => "bad_request",
+            Self::Unauthenticated => "unauthenticated",
+            Self::Forbidden => "forbidden",
+            Self::NotFound => "not_found",
+            Self::MethodNotAllowed => "method_not_allowed",
+            Self::PayloadTooLarge => "payload_too_large",
+            Self::UpstreamUnreachable => "upstream_unreachable",
+            Self::UpstreamTimeout => "upstream_timeout",
+            Self::Unavailable => "service_unavailable",
+            Self::Maintenance => "maintenance",
+            Self::Internal => "internal_error",
+        }
+    }
+
+    fn title(self) -> &'static str {
+        match self {
+            Self::CsrfFailed => "Your session needs a refresh",
+            Self::BadRequest | Self::MethodNotAllowed => "That request could not be handled",
+            Self::Unauthenticated => "Please sign in",
+            Self::Forbidden => "Access denied",
+            Self::NotFound => "Not found",
+            Self::PayloadTooLarge => "That is too large",
+            Self::UpstreamUnreachable | Self::Unavailable => "Service unavailable",
+            Self::UpstreamTimeout => "This is taking too long",
+            Self::Maintenance => "Down for maintenance",
+            Self::Internal => "Something went wrong",
+        }
+    }
+
+    fn message(self) -> &'static str {
+        match self {
+            Self::CsrfFailed => "The page was open for too long. Reload it and try again.",
+            Self::BadRequest => "Please check what you entered and try again.",
+            Self::Unauthenticated => "Your session has ended. Sign in and try again.",
+            Self::Forbidden => "You do not have permission to do that.",
+            Self::NotFound => "We could not find what you were looking for.",
+            Self::MethodNotAllowed => "This address does not accept that kind of request.",
+            Self::PayloadTooLarge => "Please send a smaller file or less data.",
+            Self::UpstreamUnreachable => "We could not reach the service. Please try again.",
+            Self::UpstreamTimeout => "The service took too long to respond. Please try again.",
+            Self::Unavailable => {
+                "The service is temporarily unavailable. Please try again in a moment."
+            }
+            Self::Maintenance => "We are upgrading the service. Please try again shortly.",
+            Self::Internal => "An unexpected error occurred. Please try again.",
+        }
+    }
+}
+
+fn request_id(headers: &HeaderMap) -> String {
+    headers
+        .get(&REQUEST_ID)
+        .and_then(|v| v.to_str().ok())
+        .filter(|id| {
+            !id.is_empty()
+                && id.len() <= 64
+                && id
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        })
+        .map_or_else(
+            || {
+                rand::random::<[u8; 8]>()
+                    .iter()
+                    .map(|b| format!("{b:02x}"))
+                    .collect()
+            },
+            str::to_string,
+        )
+}
+
+pub fn wants_html(headers: &HeaderMap) -> bool {
+    let navigate = headers
+        .get("sec-fetch-mode")
+        .is_some_and(|v| v.as_bytes() == b"navigate");
+    navigate
+        || headers
+            .get(header::ACCEPT)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|accept| accept.contains("text/html"))
+}
+
+pub fn is_htmx(headers: &HeaderMap) -> bool {
+    headers
+        .get("hx-request")
+        .is_some_and(|v| v.as_bytes() == b"true")
+}
+
+fn fragment(kind: ErrorKind, id: &str, method: &Method, uri: &Uri) -> String {
+    // GETs can simply be repeated in place; anything else reloads the page
+    let retry = if method == Method::GET {
+        format!(
+            r#"<button type="button" class="gb-error-retry" hx-get="{}" hx-target="closest .gb-error" hx-swap="outerHTML" style="padding:0.375rem 0.875rem;border-radius:0.375rem;border:1px solid var(--border,#334155);background:var(--primary,#3b82f6);color:#fff;cursor:pointer">Try again</button>"#,
+            html::escape(&uri.to_string())
+        )
+    } else {
+        r#"<a class="gb-error-retry" href="" style="color:var(--primary,#3b82f6)">Reload the page</a>"#
+            .to_string()
+    };
+    format!(
+        r#"<div class="gb-error" role="alert" data-request-id="{id}" style="margin:1rem;padding:1rem 1.25rem;border:1px solid var(--error,#ef4444);border-radius:0.5rem;background:var(--surface,transparent);color:var(--text,inherit)"><strong>{}</strong><p style="margin:0.25rem 0 0.75rem;color:var(--text-secondary,inherit)">{}</p>{retry}<small style="display:block;margin-top:0.75rem;opacity:0.7">Reference: {id}</small></div>"#,
+        kind.title(),
+        kind.message()
+    )
+}
+
+fn page(kind: ErrorKind, id: &str) -> String {
+    let template = read_ui_text(ERROR_PAGE).unwrap_or_else(|e| {
+        warn!("Error page template unavailable: {e}");
+        "<!doctype html><title>{{title}}</title><h1>{{title}}</h1><p>{{message}}</p><p>Reference: {{request_id}}</p>".to_string()
+    });
+    template
+        .replace("{{status}}", kind.status().as_str())
+        .replace("{{title}}", kind.title())
+        .replace("{{message}}", kind.message())
+        .replace("{{request_id}}", id)
+}
+
+pub fn respond(
+    headers: &HeaderMap,
+    method: &Method,
+    uri: &Uri,
+    kind: ErrorKind,
+    detail: &str,
+) -> Response {
+    let id = request_id(headers);
+    if kind == ErrorKind::Maintenance {
+        debug!("[{id}] {method} {} held for maintenance", uri.path());
+    } else if kind.status().is_client_error() {
+        warn!(
+            "[{id}] {method} {} rejected ({}): {detail}",
+            uri.path(),
+            kind.code()
+        );
+    } else {
+        error!(
+            "[{id}] {method} {} failed ({}): {detail}",
+            uri.path(),
+            kind.code()
+        );
+    }
+
+    let mut response = if is_htmx(headers) {
+        let mut response = (kind.status(), Html(fragment(kind, &id, method, uri))).into_response();
+        response
+            .headers_mut()
+            .insert(ERROR_FRAGMENT, HeaderValue::from_static("fragment"));
+        response
+    } else if wants_html(headers) {
+        (kind.status(), Html(page(kind, &id))).into_response()
+    } else {
+        (
+            kind.status(),
+            Json(serde_json::json!({
+                "error": kind.message(),
+                "code": kind.code(),
+                "request_id": id,
+            })),
+        )
+            .into_response()
+    };
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response.headers_mut().insert(REQUEST_ID, value);
+    }
+    response
+        .headers_mut()
+        .insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    response
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Failure {
+    pub kind: ErrorKind,
+    pub detail: String,
+    pub retry_after: Option<u64>,
+}
+
+impl Failure {
+    pub fn new(kind: ErrorKind, detail: impl Into<String>) -> Self {
+        Self {
+            kind,
+            detail: detail.into(),
+            retry_after: None,
+        }
+    }
+
+    pub fn respond(&self, headers: &HeaderMap, method: &Method, uri: &Uri) -> Response {
+        let mut response = respond(headers, method, uri, self.kind, &self.detail);
+        if let Some(retry_after) = self.retry_after {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negotiates_by_caller() {
+        let uri = Uri::from_static("/api/tasks?x=1");
+        let content_type = |headers: &HeaderMap| {
+            respond(
+                headers,
+                &Method::GET,
+                &uri,
+                ErrorKind::UpstreamTimeout,
+                "detail",
+            )
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string)
+        };
+
+        let mut api = HeaderMap::new();
+        api.insert(header::ACCEPT, HeaderValue::from_static("*/*"));
+        assert_eq!(content_type(&api).as_deref(), Some("application/json"));
+
+        let mut htmx = HeaderMap::new();
+        htmx.insert("hx-request", HeaderValue::from_static("true"));
+        htmx.insert(header::ACCEPT, HeaderValue::from_static("text/html"));
+        let response = respond(&htmx, &Method::GET, &uri, ErrorKind::Internal, "boom");
+        assert!(response.headers().contains_key(&ERROR_FRAGMENT));
+
+        let mut browser = HeaderMap::new();
+        browser.insert("sec-fetch-mode", HeaderValue::from_static("navigate"));
+        assert!(content_type(&browser).is_some_and(|t| t.starts_with("text/html")));
+    }
+
+    #[test]
+    fn request_id_is_reused_only_when_safe() {
+        let mut headers = HeaderMap::new();
+        headers.insert(&REQUEST_ID, HeaderValue::from_static("abc-123"));
+        assert_eq!(request_id(&headers), "abc-123");
+        headers.insert(&REQUEST_ID, HeaderValue::from_static("<script>"));
+        assert_eq!(request_id(&headers).len(), 16);
+    }
+}
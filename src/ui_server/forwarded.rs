@@ -0,0 +1,426 @@
+use axum::extract::Request;
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue};
+use log::{info, warn};
+use std::net::IpAddr;
+
+use super::rate_limit;
+use crate::shared::AppState;
+
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+const X_FORWARDED_PROTO: HeaderName = HeaderName::from_static("x-forwarded-proto");
+const X_FORWARDED_HOST: HeaderName = HeaderName::from_static("x-forwarded-host");
+const FORWARDED: HeaderName = HeaderName::from_static("forwarded");
+
+const HOP_BY_HOP: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+pub fn is_hop_by_hop(name: &HeaderName, headers: &HeaderMap) -> bool {
+    HOP_BY_HOP.contains(&name.as_str())
+        || headers
+            .get_all(header::CONNECTION)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .any(|listed| listed.trim().eq_ignore_ascii_case(name.as_str()))
+}
+
+pub fn is_forwarding_header(name: &HeaderName) -> bool {
+    [
+        X_FORWARDED_FOR,
+        X_FORWARDED_PROTO,
+        X_FORWARDED_HOST,
+        FORWARDED,
+    ]
+    .contains(name)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Network {
+    addr: IpAddr,
+    prefix: u32,
+}
+
+impl Network {
+    pub fn parse(spec: &str) -> Option<Self> {
+        let (addr, prefix) = match spec.split_once('/') {
+            Some((addr, prefix)) => (addr.parse::<IpAddr>().ok()?, prefix.parse().ok()?),
+            None => {
+                let addr = spec.parse::<IpAddr>().ok()?;
+                (addr, if addr.is_ipv4() { 32 } else { 128 })
+            }
+        };
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        (prefix <= max).then_some(Self { addr, prefix })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+            IpAddr::V4(_) => ip,
+        };
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+pub fn networks_from_env(name: &str) -> Vec<Network> {
+    let mut networks = Vec::new();
+    for spec in std::env::var(name)
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+    {
+        match Network::parse(spec) {
+            Some(network) => networks.push(network),
+            None => warn!("Ignoring invalid network in {name}: {spec}"),
+        }
+    }
+    networks
+}
+
+#[derive(Debug, Default)]
+pub struct TrustedProxies {
+    networks: Vec<Network>,
+}
+
+impl TrustedProxies {
+    #[must_use]
+    pub fn from_env() -> Self {
+        let networks = networks_from_env("BOTUI_TRUSTED_PROXIES");
+        if !networks.is_empty() {
+            info!(
+                "Trusting forwarding headers from {} networks",
+                networks.len()
+            );
+        }
+        Self { networks }
+    }
+
+    fn trusts(&self, ip: IpAddr) -> bool {
+        self.networks.iter().any(|n| n.contains(ip))
+    }
+
+    pub fn client_ip(&self, peer: Option<IpAddr>, headers: &HeaderMap) -> Option<IpAddr> {
+        let peer = peer?;
+        if !self.trusts(peer) {
+            return Some(peer);
+        }
+        let mut chain = forwarded_for(headers);
+        if chain.is_empty() {
+            chain = forwarded_element(headers, "for")
+                .into_iter()
+                .filter_map(|node| parse_node(&node))
+                .collect();
+        }
+        Some(
+            chain
+                .into_iter()
+                .rev()
+                .find(|ip| !self.trusts(*ip))
+                .unwrap_or(peer),
+        )
+    }
+
+    fn inherited<'a>(
+        &self,
+        peer: Option<IpAddr>,
+        incoming: &'a HeaderMap,
+        name: &HeaderName,
+    ) -> Vec<&'a HeaderValue> {
+        match peer {
+            Some(ip) if self.trusts(ip) => incoming.get_all(name).iter().collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn inherited_str(
+        &self,
+        peer: Option<IpAddr>,
+        incoming: &HeaderMap,
+        name: &HeaderName,
+    ) -> Option<String> {
+        self.inherited(peer, incoming, name)
+            .first()
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string)
+    }
+
+    fn proto_and_host(
+        &self,
+        peer: Option<IpAddr>,
+        incoming: &HeaderMap,
+    ) -> (String, Option<String>) {
+        let proto = self
+            .inherited_str(peer, incoming, &X_FORWARDED_PROTO)
+            .unwrap_or_else(|| "http".to_string());
+        let host = self
+            .inherited_str(peer, incoming, &X_FORWARDED_HOST)
+            .or_else(|| {
+                incoming
+                    .get(header::HOST)
+                    .and_then(|v| v.to_str().ok())
+                    .map(str::to_string)
+            });
+        (proto, host)
+    }
+
+    pub fn origin(&self, peer: Option<IpAddr>, incoming: &HeaderMap) -> Option<String> {
+        let (proto, host) = self.proto_and_host(peer, incoming);
+        host.map(|host| format!("{proto}://{host}"))
+    }
+
+    pub fn forward(&self, peer: Option<IpAddr>, incoming: &HeaderMap, outgoing: &mut HeaderMap) {
+        let (proto, host) = self.proto_and_host(peer, incoming);
+
+        let (for_node, forwarded_element) = match peer {
+            Some(ip) => {
+                let node = match ip {
+                    IpAddr::V4(v4) => v4.to_string(),
+                    IpAddr::V6(v6) => format!("\"[{v6}]\""),
+                };
+                let mut element = format!("for={node};proto={proto}");
+                if let Some(host) = &host {
+                    element.push_str(&format!(";host=\"{host}\""));
+                }
+                (Some(ip.to_string()), Some(element))
+            }
+            None => (None, None),
+        };
+        let for_chain = append(self.inherited(peer, incoming, &X_FORWARDED_FOR), for_node);
+        let forwarded = append(
+            self.inherited(peer, incoming, &FORWARDED),
+            forwarded_element,
+        );
+
+        for (name, value) in [
+            (X_FORWARDED_FOR, for_chain),
+            (X_FORWARDED_PROTO, HeaderValue::from_str(&proto).ok()),
+            (
+                X_FORWARDED_HOST,
+                host.and_then(|v| HeaderValue::from_str(&v).ok()),
+            ),
+            (FORWARDED, forwarded),
+        ] {
+            outgoing.remove(&name);
+            if let Some(value) = value {
+                outgoing.insert(name, value);
+            }
+        }
+    }
+}
+
+fn append(list: Vec<&HeaderValue>, element: Option<String>) -> Option<HeaderValue> {
+    let mut bytes = Vec::new();
+    for value in list
+        .into_iter()
+        .map(HeaderValue::as_bytes)
+        .chain(element.as_deref().map(str::as_bytes))
+    {
+        if !bytes.is_empty() {
+            bytes.extend_from_slice(b", ");
+        }
+        bytes.extend_from_slice(value);
+    }
+    if bytes.is_empty() {
+        return None;
+    }
+    HeaderValue::from_bytes(&bytes).ok()
+}
+
+fn forwarded_for(headers: &HeaderMap) -> Vec<IpAddr> {
+    headers
+        .get_all(X_FORWARDED_FOR)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(parse_node)
+        .collect()
+}
+
+fn forwarded_element(headers: &HeaderMap, key: &str) -> Vec<String> {
+    headers
+        .get_all(FORWARDED)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(|element| {
+            element.split(';').find_map(|pair| {
+                let (name, value) = pair.trim().split_once('=')?;
+                name.eq_ignore_ascii_case(key)
+                    .then(|| value.trim().trim_matches('"').to_string())
+            })
+        })
+        .collect()
+}
+
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim();
+    if let Ok(ip) = node.parse() {
+        return Some(ip);
+    }
+    if let Some(rest) = node.strip_prefix('[') {
+        return rest.split_once(']')?.0.parse().ok();
+    }
+    node.rsplit_once(':')?.0.parse().ok()
+}
+
+pub fn client_ip(state: &AppState, request: &Request) -> Option<IpAddr> {
+    state.trusted_proxies.client_ip(
+        rate_limit::peer_addr(request).map(|addr| addr.ip()),
+        request.headers(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::IpAddr;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap_or(IpAddr::from([0, 0, 0, 0]))
+    }
+
+    #[test]
+    fn cidr_membership() {
+        let net = Network::parse("10.0.0.0/8");
+        assert!(net.is_some_and(|n| n.contains(ip("10.1.2.3"))));
+        assert!(net.is_some_and(|n| n.contains(ip("::ffff:10.1.2.3"))));
+        assert!(!net.is_some_and(|n| n.contains(ip("11.0.0.1"))));
+        assert!(Network::parse("fd00::/8").is_some_and(|n| n.contains(ip("fd12::1"))));
+        assert!(Network::parse("10.0.0.0/33").is_none());
+    }
+
+    #[test]
+    fn client_ip_skips_trusted_hops_only() {
+        let proxies = TrustedProxies {
+            networks: Network::parse("10.0.0.0/8").into_iter().collect(),
+        };
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "x-forwarded-for",
+            HeaderValue::from_static("6.6.6.6, 203.0.113.7, 10.0.0.2"),
+        );
+        assert_eq!(
+            proxies.client_ip(Some(ip("10.0.0.1")), &headers),
+            Some(ip("203.0.113.7"))
+        );
+        assert_eq!(
+            proxies.client_ip(Some(ip("198.51.100.1")), &headers),
+            Some(ip("198.51.100.1"))
+        );
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "forwarded",
+            HeaderValue::from_static("for=\"[2001:db8::1]:443\";proto=https"),
+        );
+        assert_eq!(
+            proxies.client_ip(Some(ip("10.0.0.1")), &headers),
+            Some(ip("2001:db8::1"))
+        );
+    }
+
+    fn proxies(spec: &str) -> TrustedProxies {
+        TrustedProxies {
+            networks: Network::parse(spec).into_iter().collect(),
+        }
+    }
+
+    fn forward(proxies: &TrustedProxies, peer: &str, incoming: &HeaderMap) -> HeaderMap {
+        let mut outgoing = incoming.clone();
+        proxies.forward(Some(ip(peer)), incoming, &mut outgoing);
+        outgoing
+    }
+
+    fn value<'a>(headers: &'a HeaderMap, name: &HeaderName) -> &'a [u8] {
+        headers.get(name).map_or(&[], HeaderValue::as_bytes)
+    }
+
+    #[test]
+    fn connection_listed_headers_are_hop_by_hop() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::CONNECTION,
+            HeaderValue::from_static("close, X-Debug-Token"),
+        );
+        assert!(is_hop_by_hop(&header::CONNECTION, &headers));
+        assert!(is_hop_by_hop(&header::UPGRADE, &headers));
+        assert!(is_hop_by_hop(
+            &HeaderName::from_static("x-debug-token"),
+            &headers
+        ));
+        assert!(!is_hop_by_hop(&header::ACCEPT, &headers));
+        assert!(!is_hop_by_hop(&header::ACCEPT, &HeaderMap::new()));
+    }
+
+    #[test]
+    fn chain_is_extended_only_for_trusted_peers() {
+        let proxies = proxies("10.0.0.0/8");
+        let mut incoming = HeaderMap::new();
+        incoming.insert(header::HOST, HeaderValue::from_static("bots.example.com"));
+        incoming.insert(X_FORWARDED_FOR, HeaderValue::from_static("203.0.113.7"));
+        incoming.insert(X_FORWARDED_PROTO, HeaderValue::from_static("https"));
+        incoming.insert(FORWARDED, HeaderValue::from_static("for=203.0.113.7"));
+
+        let trusted = forward(&proxies, "10.0.0.1", &incoming);
+        assert_eq!(value(&trusted, &X_FORWARDED_FOR), b"203.0.113.7, 10.0.0.1");
+        assert_eq!(value(&trusted, &X_FORWARDED_PROTO), b"https");
+        assert_eq!(
+            value(&trusted, &FORWARDED),
+            br#"for=203.0.113.7, for=10.0.0.1;proto=https;host="bots.example.com""#
+        );
+
+        let direct = forward(&proxies, "198.51.100.1", &incoming);
+        assert_eq!(value(&direct, &X_FORWARDED_FOR), b"198.51.100.1");
+        assert_eq!(value(&direct, &X_FORWARDED_PROTO), b"http");
+        assert_eq!(
+            value(&direct, &FORWARDED),
+            br#"for=198.51.100.1;proto=http;host="bots.example.com""#
+        );
+    }
+
+    #[test]
+    fn ipv6_nodes_are_quoted() {
+        let outgoing = forward(&proxies("10.0.0.0/8"), "2001:db8::1", &HeaderMap::new());
+        assert_eq!(value(&outgoing, &X_FORWARDED_FOR), b"2001:db8::1");
+        assert_eq!(
+            value(&outgoing, &FORWARDED),
+            br#"for="[2001:db8::1]";proto=http"#
+        );
+    }
+
+    #[test]
+    fn opaque_inherited_chains_are_kept() {
+        let mut incoming = HeaderMap::new();
+        let Ok(opaque) = HeaderValue::from_bytes(b"203.0.113.7, caf\xe9") else {
+            return;
+        };
+        incoming.append(X_FORWARDED_FOR, opaque);
+        incoming.append(X_FORWARDED_FOR, HeaderValue::from_static("10.0.0.9"));
+
+        let outgoing = forward(&proxies("10.0.0.0/8"), "10.0.0.1", &incoming);
+        assert_eq!(
+            value(&outgoing, &X_FORWARDED_FOR),
+            b"203.0.113.7, caf\xe9, 10.0.0.9, 10.0.0.1"
+        );
+    }
+}
@@ -0,0 +1,161 @@
+use axum::http::{header, HeaderMap, Uri};
+use log::{info, warn};
+use std::collections::HashMap;
+
+pub const BOT_HEADER: &str = "x-bot-name";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BotSource {
+    Host,
+    Path,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum WildcardTarget {
+    Bot(String),
+    Subdomain,
+}
+
+#[derive(Debug, Default)]
+pub struct HostMap {
+    exact: HashMap<String, String>,
+    wildcard: Vec<(String, WildcardTarget)>,
+}
+
+impl HostMap {
+    #[must_use]
+    pub fn from_env() -> Self {
+        std::env::var("BOTUI_HOST_MAP")
+            .map(|raw| Self::parse(&raw))
+            .unwrap_or_default()
+    }
+
+    fn parse(raw: &str) -> Self {
+        let mut map = Self::default();
+        for entry in raw.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let Some((host, bot)) = entry.split_once('=') else {
+                warn!("Ignoring host mapping without '=': {entry}");
+                continue;
+            };
+            let host = host.trim().to_ascii_lowercase();
+            let bot = bot.trim();
+            if host.is_empty() || bot.is_empty() {
+                warn!("Ignoring incomplete host mapping: {entry}");
+                continue;
+            }
+
+            if let Some(suffix) = host.strip_prefix("*.") {
+                let target = if bot == "*" {
+                    WildcardTarget::Subdomain
+                } else {
+                    WildcardTarget::Bot(bot.to_string())
+                };
+                map.wildcard.push((suffix.to_string(), target));
+            } else {
+                map.exact.insert(host, bot.to_string());
+            }
+        }
+
+        if !map.is_empty() {
+            info!(
+                "Host bot mapping: {} exact, {} wildcard",
+                map.exact.len(),
+                map.wildcard.len()
+            );
+        }
+        map
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.exact.is_empty() && self.wildcard.is_empty()
+    }
+
+    pub fn bot_for_host(&self, host: &str) -> Option<String> {
+        let host = host.to_ascii_lowercase();
+        if let Some(bot) = self.exact.get(&host) {
+            return Some(bot.clone());
+        }
+
+        self.wildcard.iter().find_map(|(suffix, target)| {
+            let label = host.strip_suffix(suffix.as_str())?.strip_suffix('.')?;
+            if label.is_empty() || label.contains('.') {
+                return None;
+            }
+            Some(match target {
+                WildcardTarget::Bot(bot) => bot.clone(),
+                WildcardTarget::Subdomain => label.to_string(),
+            })
+        })
+    }
+
+    pub fn bot_for_request(&self, headers: &HeaderMap, uri: &Uri) -> Option<String> {
+        if self.is_empty() {
+            return None;
+        }
+        let host = headers
+            .get(header::HOST)
+            .and_then(|v| v.to_str().ok())
+            .or_else(|| uri.host())?;
+        self.bot_for_host(strip_port(host))
+    }
+}
+
+fn strip_port(host: &str) -> &str {
+    if host.starts_with('[') {
+        return host
+            .split_once(']')
+            .map_or(host, |(addr, _)| &host[..=addr.len()]);
+    }
+    host.rsplit_once(':').map_or(host, |(name, _)| name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    #[test]
+    fn parses_exact_and_wildcard_mappings() {
+        let map = HostMap::parse(
+            "Chat.Acme.com=acme, *.bots.example.com=*, *.edu.example.com=edu, broken, =x, y=",
+        );
+        assert_eq!(map.exact.len(), 1);
+        assert_eq!(map.wildcard.len(), 2);
+        assert!(HostMap::parse(" , ").is_empty());
+    }
+
+    #[test]
+    fn resolves_bots_for_hosts() {
+        let map = HostMap::parse("chat.acme.com=acme,*.bots.example.com=*,*.edu.example.com=edu");
+        assert_eq!(map.bot_for_host("CHAT.acme.com").as_deref(), Some("acme"));
+        assert_eq!(
+            map.bot_for_host("sales.bots.example.com").as_deref(),
+            Some("sales")
+        );
+        assert_eq!(
+            map.bot_for_host("school.edu.example.com").as_deref(),
+            Some("edu")
+        );
+        assert_eq!(map.bot_for_host("bots.example.com"), None);
+        assert_eq!(map.bot_for_host("a.b.bots.example.com"), None);
+        assert_eq!(map.bot_for_host("evilbots.example.com"), None);
+        assert_eq!(map.bot_for_host("other.com"), None);
+    }
+
+    #[test]
+    fn reads_the_host_header_without_port() {
+        let map = HostMap::parse("chat.acme.com=acme,[::1]=local");
+        let mut headers = HeaderMap::new();
+        headers.insert(header::HOST, HeaderValue::from_static("chat.acme.com:8443"));
+        let uri = Uri::from_static("/chat");
+        assert_eq!(map.bot_for_request(&headers, &uri).as_deref(), Some("acme"));
+        headers.insert(header::HOST, HeaderValue::from_static("[::1]:3000"));
+        assert_eq!(
+            map.bot_for_request(&headers, &uri).as_deref(),
+            Some("local")
+        );
+        assert_eq!(HostMap::default().bot_for_request(&headers, &uri), None);
+        assert_eq!(strip_port("[::1]"), "[::1]");
+        assert_eq!(strip_port("example.com"), "example.com");
+    }
+}
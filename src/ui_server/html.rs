@@ -0,0 +1,252 @@
+use axum::{
+    body::Body,
+    http::{header, StatusCode},
+    response::Response,
+};
+use std::ops::Range;
+
+pub fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+pub fn script_json(value: &serde_json::Value) -> String {
+    value.to_string().replace("</", "<\\/")
+}
+
+pub fn insert_into_head(html: &mut String, snippet: &str) -> bool {
+    match html.find("</head>") {
+        Some(head_end) => {
+            html.insert_str(head_end, snippet);
+            true
+        }
+        None => false,
+    }
+}
+
+pub fn find_tag(html: &str, tag: &str, attr: &str) -> Option<Range<usize>> {
+    let open = format!("<{tag}");
+    let mut pos = 0;
+    while let Some(offset) = html[pos..].find(&open) {
+        let start = pos + offset;
+        let end = start + html[start..].find('>')? + 1;
+        if html[start..end].contains(attr) {
+            return Some(start..end);
+        }
+        pos = end;
+    }
+    None
+}
+
+pub fn upsert_tag(html: &mut String, tag: &str, attr: &str, replacement: &str) {
+    match find_tag(html, tag, attr) {
+        Some(range) => html.replace_range(range, replacement),
+        None => {
+            insert_into_head(html, replacement);
+        }
+    }
+}
+
+pub fn set_title(html: &mut String, title: &str) {
+    let title_tag = format!("<title>{}</title>", escape(title));
+    let existing = html.find("<title>").and_then(|start| {
+        html[start..]
+            .find("</title>")
+            .map(|end| start..start + end + "</title>".len())
+    });
+    match existing {
+        Some(range) => html.replace_range(range, &title_tag),
+        None => {
+            insert_into_head(html, &title_tag);
+        }
+    }
+}
+
+fn tag_end(tag: &str) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in tag.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), c) if c == q => quote = None,
+            (None, '>') => return Some(i + 1),
+            _ => {}
+        }
+    }
+    None
+}
+
+pub fn rewrite_tags(
+    html: &str,
+    tag: &str,
+    mut rewrite: impl FnMut(&str) -> Option<String>,
+) -> String {
+    let open = format!("<{tag}");
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(start) = rest.find(&open) {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let is_tag = rest[open.len()..]
+            .starts_with(|c: char| c.is_ascii_whitespace() || c == '>' || c == '/');
+        let Some(end) = tag_end(rest).filter(|_| is_tag) else {
+            out.push_str(&open);
+            rest = &rest[open.len()..];
+            continue;
+        };
+        match rewrite(&rest[..end]) {
+            Some(replacement) => out.push_str(&replacement),
+            None => out.push_str(&rest[..end]),
+        }
+        rest = &rest[end..];
+    }
+    out.push_str(rest);
+    out
+}
+
+fn attr_range(tag: &str, name: &str) -> Option<Range<usize>> {
+    let mut search = 0;
+    while let Some(offset) = tag[search..].find(name) {
+        let start = search + offset;
+        search = start + name.len();
+        let preceded_by_space = tag[..start].ends_with(|c: char| c.is_ascii_whitespace());
+        let Some(value) = tag[search..].trim_start().strip_prefix('=') else {
+            continue;
+        };
+        if !preceded_by_space {
+            continue;
+        }
+        let value = value.trim_start();
+        let quote = value.chars().next().filter(|q| *q == '"' || *q == '\'')?;
+        let value_start = tag.len() - value.len() + 1;
+        return value[1..]
+            .find(quote)
+            .map(|end| value_start..value_start + end);
+    }
+    None
+}
+
+pub fn attr<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    attr_range(tag, name).map(|range| &tag[range])
+}
+
+pub fn set_attr(tag: &str, tag_name: &str, name: &str, value: &str) -> String {
+    match attr_range(tag, name) {
+        Some(range) => format!(
+            "{}{}{}",
+            &tag[..range.start],
+            escape(value),
+            &tag[range.end..]
+        ),
+        None => with_attrs(tag, tag_name, &format!(r#"{name}="{}""#, escape(value))),
+    }
+}
+
+pub fn with_attrs(tag: &str, name: &str, attrs: &str) -> String {
+    let split = name.len() + 1;
+    format!("{} {attrs}{}", &tag[..split], &tag[split..])
+}
+
+pub fn add_script_nonce(html: &str, nonce: &str) -> String {
+    let nonce_attr = format!(r#"nonce="{}""#, escape(nonce));
+    rewrite_tags(html, "script", |tag| {
+        attr(tag, "nonce")
+            .is_none()
+            .then(|| with_attrs(tag, "script", &nonce_attr))
+    })
+}
+
+pub fn is_rewritable(response: &Response) -> bool {
+    response.status() == StatusCode::OK
+        && !response.headers().contains_key(header::CONTENT_ENCODING)
+        && response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.starts_with("text/html"))
+}
+
+pub async fn rewrite_response(
+    response: Response,
+    rewrite: impl FnOnce(&str) -> String,
+) -> Response {
+    if !is_rewritable(&response) {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let Ok(bytes) = axum::body::to_bytes(body, usize::MAX).await else {
+        return Response::from_parts(parts, Body::empty());
+    };
+    let Ok(text) = std::str::from_utf8(&bytes) else {
+        return Response::from_parts(parts, Body::from(bytes));
+    };
+
+    let rewritten = rewrite(text);
+    for name in [
+        header::CONTENT_LENGTH,
+        header::ETAG,
+        header::LAST_MODIFIED,
+        header::ACCEPT_RANGES,
+    ] {
+        parts.headers.remove(name);
+    }
+    Response::from_parts(parts, Body::from(rewritten))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rewrites_whole_tags_only() {
+        let html = r#"<scripts><script src="/a.js"></script><script>x()</script>"#;
+        let tags = std::cell::RefCell::new(Vec::new());
+        let out = rewrite_tags(html, "script", |tag| {
+            tags.borrow_mut().push(tag.to_string());
+            None
+        });
+        assert_eq!(out, html);
+        assert_eq!(tags.into_inner(), [r#"<script src="/a.js">"#, "<script>"]);
+    }
+
+    #[test]
+    fn quoted_angle_brackets_stay_inside_the_tag() {
+        let html = r#"<script data-x="a>b" nonce="old" src='/c>d.js'></script>"#;
+        let mut seen = String::new();
+        rewrite_tags(html, "script", |tag| {
+            seen = tag.to_string();
+            None
+        });
+        assert_eq!(seen, r#"<script data-x="a>b" nonce="old" src='/c>d.js'>"#);
+        assert_eq!(attr(&seen, "src"), Some("/c>d.js"));
+        assert_eq!(add_script_nonce(html, "n"), html);
+    }
+
+    #[test]
+    fn attributes_match_whole_names() {
+        let tag = r#"<script data-nonce="a" nonce = 'b' src="/x.js">"#;
+        assert_eq!(attr(tag, "nonce"), Some("b"));
+        assert_eq!(attr(tag, "src"), Some("/x.js"));
+        assert_eq!(attr(tag, "integrity"), None);
+        assert_eq!(attr("<script async>", "async"), None);
+    }
+
+    #[test]
+    fn nonces_are_added_once() {
+        let html = r#"<script>a()</script><script nonce="keep">b()</script><script data-nonce="x" src="/c.js"></script>"#;
+        assert_eq!(
+            add_script_nonce(html, "n+1"),
+            r#"<script nonce="n+1">a()</script><script nonce="keep">b()</script><script nonce="n+1" data-nonce="x" src="/c.js"></script>"#
+        );
+    }
+}
@@ -0,0 +1,450 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap, HeaderValue, Method},
+    middleware::Next,
+    response::Response,
+};
+use log::{debug, info, warn};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use super::coalesce::Coalesce;
+use super::{cookies, html};
+use crate::shared::AppState;
+
+pub const LOCALE_COOKIE: &str = "gb_locale";
+const LOCALES_PATH: &str = "/api/i18n/locales";
+const FALLBACK_LOCALES: [&str; 4] = ["en", "pt-BR", "es", "zh-CN"];
+const RTL_LANGUAGES: [&str; 11] = [
+    "ar", "ckb", "dv", "fa", "he", "iw", "ps", "sd", "ug", "ur", "yi",
+];
+const FAILURE_TTL: Duration = Duration::from_secs(30);
+const FETCH_TIMEOUT: Duration = Duration::from_secs(2);
+
+type Bundle = Arc<HashMap<String, String>>;
+type Cached<T> = (Instant, Duration, T);
+
+#[derive(Debug)]
+pub struct I18n {
+    enabled: bool,
+    default_locale: String,
+    ttl: Duration,
+    locales: RwLock<Option<Cached<Arc<Vec<String>>>>>,
+    bundles: RwLock<HashMap<String, Cached<Bundle>>>,
+    locale_fetches: Coalesce<Arc<Vec<String>>>,
+    bundle_fetches: Coalesce<Bundle>,
+}
+
+fn fresh<T: Clone>(entry: Option<&Cached<T>>) -> Option<T> {
+    entry
+        .filter(|(fetched_at, ttl, _)| fetched_at.elapsed() < *ttl)
+        .map(|(_, _, value)| value.clone())
+}
+
+impl I18n {
+    #[must_use]
+    pub fn from_env() -> Self {
+        let enabled = std::env::var("BOTUI_I18N").map_or(true, |v| v != "false");
+        let default_locale = std::env::var("BOTUI_I18N_DEFAULT_LOCALE")
+            .ok()
+            .filter(|l| is_locale_tag(l))
+            .unwrap_or_else(|| "en".to_string());
+        if enabled {
+            info!("Server-side i18n enabled (default locale {default_locale})");
+        }
+        Self {
+            enabled,
+            default_locale,
+            ttl: Duration::from_secs(
+                std::env::var("BOTUI_I18N_CACHE_SECS")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(300),
+            ),
+            locales: RwLock::new(None),
+            bundles: RwLock::new(HashMap::new()),
+            locale_fetches: Coalesce::default(),
+            bundle_fetches: Coalesce::default(),
+        }
+    }
+
+    async fn locales(&self, state: &AppState) -> Arc<Vec<String>> {
+        if let Some(locales) = self.locales.read().ok().and_then(|l| fresh(l.as_ref())) {
+            return locales;
+        }
+        self.locale_fetches
+            .run(LOCALES_PATH, || self.fetch_locales(state))
+            .await
+    }
+
+    async fn fetch_locales(&self, state: &AppState) -> Arc<Vec<String>> {
+        let fetched = fetch_json(state, LOCALES_PATH).await.and_then(|body| {
+            let locales: Vec<String> = body
+                .get("locales")?
+                .as_array()?
+                .iter()
+                .filter_map(|l| l.as_str().filter(|l| is_locale_tag(l)).map(str::to_string))
+                .collect();
+            (!locales.is_empty()).then_some(locales)
+        });
+        let (ttl, locales) = match fetched {
+            Some(locales) => (self.ttl, locales),
+            None => (
+                FAILURE_TTL,
+                FALLBACK_LOCALES.iter().map(|l| (*l).to_string()).collect(),
+            ),
+        };
+        let locales = Arc::new(locales);
+        if let Ok(mut cached) = self.locales.write() {
+            *cached = Some((Instant::now(), ttl, locales.clone()));
+        }
+        locales
+    }
+
+    async fn bundle(&self, state: &AppState, locale: &str) -> Bundle {
+        if let Some(bundle) = self
+            .bundles
+            .read()
+            .ok()
+            .and_then(|bundles| fresh(bundles.get(locale)))
+        {
+            return bundle;
+        }
+        self.bundle_fetches
+            .run(locale, || self.fetch_bundle(state, locale))
+            .await
+    }
+
+    async fn fetch_bundle(&self, state: &AppState, locale: &str) -> Bundle {
+        let fetched = fetch_json(state, &format!("/api/i18n/{locale}"))
+            .await
+            .and_then(|body| {
+                let translations = body.get("translations")?.as_object()?;
+                Some(
+                    translations
+                        .iter()
+                        .filter_map(|(k, v)| Some((k.clone(), v.as_str()?.to_string())))
+                        .collect::<HashMap<_, _>>(),
+                )
+            });
+        let (ttl, bundle) = match fetched {
+            Some(bundle) => {
+                debug!("Loaded {} translations for {locale}", bundle.len());
+                (self.ttl, Arc::new(bundle))
+            }
+            None => {
+                warn!("Translations for {locale} unavailable, serving pages untranslated");
+                (FAILURE_TTL, Bundle::default())
+            }
+        };
+        if let Ok(mut bundles) = self.bundles.write() {
+            bundles.insert(locale.to_string(), (Instant::now(), ttl, bundle.clone()));
+        }
+        bundle
+    }
+
+    fn negotiate(&self, headers: &HeaderMap, supported: &[String]) -> String {
+        let cookie = cookies::get(headers, LOCALE_COOKIE);
+        let mut wanted: Vec<(String, f32)> = headers
+            .get(header::ACCEPT_LANGUAGE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|range| {
+                let mut parts = range.split(';').map(str::trim);
+                let tag = parts.next().filter(|t| !t.is_empty() && *t != "*")?;
+                let q = parts
+                    .find_map(|p| p.strip_prefix("q="))
+                    .map_or(Some(1.0), |q| q.parse::<f32>().ok())?;
+                (q > 0.0).then(|| (tag.to_string(), q))
+            })
+            .collect();
+        // Stable, so equal weights keep the browser's order
+        wanted.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+        cookie
+            .into_iter()
+            .chain(wanted.into_iter().map(|(tag, _)| tag))
+            .find_map(|tag| match_locale(&tag, supported))
+            .unwrap_or_else(|| self.default_locale.clone())
+    }
+}
+
+fn is_locale_tag(tag: &str) -> bool {
+    !tag.is_empty()
+        && tag.len() <= 35
+        && tag
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+fn match_locale(tag: &str, supported: &[String]) -> Option<String> {
+    let tag = tag.replace('_', "-");
+    if let Some(exact) = supported.iter().find(|l| l.eq_ignore_ascii_case(&tag)) {
+        return Some(exact.clone());
+    }
+    let language = tag.split('-').next()?;
+    supported
+        .iter()
+        .find(|l| {
+            l.split('-')
+                .next()
+                .is_some_and(|l| l.eq_ignore_ascii_case(language))
+        })
+        .cloned()
+}
+
+pub fn direction(locale: &str) -> &'static str {
+    let language = locale.split(['-', '_']).next().unwrap_or_default();
+    if RTL_LANGUAGES
+        .iter()
+        .any(|rtl| rtl.eq_ignore_ascii_case(language))
+    {
+        "rtl"
+    } else {
+        "ltr"
+    }
+}
+
+async fn fetch_json(state: &AppState, path: &str) -> Option<serde_json::Value> {
+    let upstream = state.upstreams.select(path, None, None)?;
+    let resp = upstream
+        .client()
+        .get(format!("{}{path}", upstream.base_url()))
+        .timeout(FETCH_TIMEOUT)
+        .header(header::ACCEPT, "application/json")
+        .send()
+        .await
+        .map_err(|e| debug!("Fetching {path} failed: {e}"))
+        .ok()?;
+    if !resp.status().is_success() {
+        debug!("Fetching {path} returned {}", resp.status());
+        return None;
+    }
+    resp.json().await.ok()
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+fn lookup(bundle: &HashMap<String, String>, key: &str, params: Option<&str>) -> Option<String> {
+    let mut text = bundle.get(key)?.clone();
+    let params = params
+        .and_then(|p| serde_json::from_str::<serde_json::Value>(&unescape(p)).ok())
+        .and_then(|p| p.as_object().cloned());
+    for (name, value) in params.iter().flatten() {
+        let value = value
+            .as_str()
+            .map_or_else(|| value.to_string(), str::to_string);
+        for pattern in [
+            format!("{{{name}}}"),
+            format!("{{${name}}}"),
+            format!("{{ {name} }}"),
+            format!("{{ ${name} }}"),
+        ] {
+            text = text.replace(&pattern, &value);
+        }
+    }
+    Some(text)
+}
+
+pub fn translate(page: &str, locale: &str, bundle: &HashMap<String, String>) -> String {
+    let mut out = String::with_capacity(page.len());
+    let mut rest = page;
+    while let Some(start) = rest.find('<') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let name_len = rest[1..]
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '-')
+            .unwrap_or(rest.len() - 1);
+        let name = rest[1..=name_len].to_ascii_lowercase();
+        let end = rest.find('>').map(|e| e + 1);
+        let (Some(end), false) = (end, name.is_empty()) else {
+            out.push('<');
+            rest = &rest[1..];
+            continue;
+        };
+        let mut tag = rest[..end].to_string();
+        rest = &rest[end..];
+
+        if name == "script" || name == "style" {
+            // Raw text: copy through to the closing tag untouched
+            out.push_str(&tag);
+            let close = rest
+                .to_ascii_lowercase()
+                .find(&format!("</{name}"))
+                .unwrap_or(rest.len());
+            out.push_str(&rest[..close]);
+            rest = &rest[close..];
+            continue;
+        }
+
+        // As written in the page, which `html::set_attr` expects
+        let tag_name = &tag[1..=name_len].to_string();
+        if name == "html" {
+            tag = html::set_attr(&tag, tag_name, "lang", locale);
+            tag = html::set_attr(&tag, tag_name, "dir", direction(locale));
+            tag = html::set_attr(&tag, tag_name, "data-locale", locale);
+        }
+        if tag.contains("data-i18n") {
+            for (marker, target) in [
+                ("data-i18n-placeholder", "placeholder"),
+                ("data-i18n-title", "title"),
+                ("data-i18n-aria-label", "aria-label"),
+            ] {
+                let text = html::attr(&tag, marker).and_then(|key| lookup(bundle, key, None));
+                if let Some(text) = text {
+                    tag = html::set_attr(&tag, tag_name, target, &text);
+                }
+            }
+            let text = html::attr(&tag, "data-i18n")
+                .and_then(|key| lookup(bundle, key, html::attr(&tag, "data-i18n-params")));
+            let content_end = rest.find('<').filter(|&i| rest[i..].starts_with("</"));
+            if let (Some(text), Some(content_end)) = (text, content_end) {
+                out.push_str(&tag);
+                out.push_str(&html::escape(&text));
+                rest = &rest[content_end..];
+                continue;
+            }
+        }
+        out.push_str(&tag);
+    }
+    out.push_str(rest);
+    out
+}
+
+pub async fn apply(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let i18n = state.i18n.clone();
+    if !i18n.enabled || request.method() != Method::GET {
+        return next.run(request).await;
+    }
+    let headers = request.headers().clone();
+    let response = next.run(request).await;
+    if !html::is_rewritable(&response) {
+        return response;
+    }
+
+    let supported = i18n.locales(&state).await;
+    let locale = i18n.negotiate(&headers, &supported);
+    let bundle = i18n.bundle(&state, &locale).await;
+    let mut response =
+        html::rewrite_response(response, |page| translate(page, &locale, &bundle)).await;
+
+    let response_headers = response.headers_mut();
+    if let Ok(value) = HeaderValue::from_str(&locale) {
+        response_headers.insert(header::CONTENT_LANGUAGE, value);
+    }
+    response_headers.append(
+        header::VARY,
+        HeaderValue::from_static("Cookie, Accept-Language"),
+    );
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn supported() -> Vec<String> {
+        FALLBACK_LOCALES.iter().map(|l| (*l).to_string()).collect()
+    }
+
+    #[test]
+    fn negotiates_cookie_then_accept_language() {
+        let i18n = I18n {
+            enabled: true,
+            default_locale: "en".to_string(),
+            ttl: Duration::ZERO,
+            locales: RwLock::new(None),
+            bundles: RwLock::new(HashMap::new()),
+            locale_fetches: Coalesce::default(),
+            bundle_fetches: Coalesce::default(),
+        };
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::ACCEPT_LANGUAGE,
+            HeaderValue::from_static("fr;q=0.9, pt;q=0.8, en;q=0.5"),
+        );
+        assert_eq!(i18n.negotiate(&headers, &supported()), "pt-BR");
+
+        headers.insert(header::COOKIE, HeaderValue::from_static("gb_locale=zh-cn"));
+        assert_eq!(i18n.negotiate(&headers, &supported()), "zh-CN");
+
+        headers.insert(header::COOKIE, HeaderValue::from_static("gb_locale=xx"));
+        headers.insert(header::ACCEPT_LANGUAGE, HeaderValue::from_static("de"));
+        assert_eq!(i18n.negotiate(&headers, &supported()), "en");
+    }
+
+    #[tokio::test]
+    async fn concurrent_pages_share_one_bundle_fetch() {
+        use axum::{routing::get, Json, Router};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let app = Router::new().route(
+            "/api/i18n/:locale",
+            get(move || {
+                let counter = counter.clone();
+                async move {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    Json(serde_json::json!({ "translations": { "nav-chat": "Conversa" } }))
+                }
+            }),
+        );
+        let Ok(listener) = tokio::net::TcpListener::bind("127.0.0.1:0").await else {
+            return;
+        };
+        let Ok(addr) = listener.local_addr() else {
+            return;
+        };
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let mut state = AppState::new();
+        state.upstreams = Arc::new(crate::ui_server::upstream::Upstreams::from_env(&format!(
+            "http://{addr}"
+        )));
+        let tasks: Vec<_> = (0..5)
+            .map(|_| {
+                let state = state.clone();
+                tokio::spawn(async move { state.i18n.bundle(&state, "pt-BR").await })
+            })
+            .collect();
+        for task in tasks {
+            let bundle = task.await.unwrap_or_default();
+            assert_eq!(bundle.get("nav-chat").map(String::as_str), Some("Conversa"));
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn translates_marked_elements() {
+        let bundle: HashMap<String, String> = [
+            ("nav-chat", "Conversa"),
+            ("search", "Pesquisar"),
+            ("greeting", "Olá, {name} & bem-vindo"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+        let page = r#"<html lang="en"><body><span data-i18n="nav-chat">Chat</span><input data-i18n-placeholder="search" placeholder="Search"><p data-i18n="greeting" data-i18n-params='{"name":"Ana"}'>Hi</p><b data-i18n="nav-chat">A <i>b</i></b><script>if (a<b) x = "<span data-i18n=\"search\">";</script><em data-i18n="missing">Keep</em></body></html>"#;
+
+        let out = translate(page, "ar", &bundle);
+        let html_tag = &out[..out.find('>').unwrap_or_default()];
+        assert!(html_tag.contains(r#"lang="ar""#) && html_tag.contains(r#"dir="rtl""#));
+        assert!(!html_tag.contains(r#"lang="en""#));
+        assert!(out.contains(r#"<span data-i18n="nav-chat">Conversa</span>"#));
+        assert!(out.contains(r#"placeholder="Pesquisar""#));
+        assert!(out.contains("Olá, Ana &amp; bem-vindo</p>"));
+        assert!(out.contains(r#"<b data-i18n="nav-chat">A <i>b</i></b>"#));
+        assert!(out.contains(r#"<span data-i18n=\"search\">"#));
+        assert!(out.contains("Keep</em>"));
+    }
+}
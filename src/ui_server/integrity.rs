@@ -0,0 +1,217 @@
+use axum::{
+    extract::{Request, State},
+    http::Method,
+    middleware::Next,
+    response::Response,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use log::{debug, info};
+use sha2::{Digest, Sha384};
+use std::collections::HashMap;
+use std::path::Path;
+#[cfg(not(feature = "embed-ui"))]
+use std::path::PathBuf;
+use std::sync::RwLock;
+use std::time::SystemTime;
+
+use super::{html, overlay};
+use crate::shared::AppState;
+
+#[cfg(feature = "embed-ui")]
+use super::Assets;
+
+type Stamp = Option<(SystemTime, u64)>;
+
+#[derive(Debug)]
+pub struct IntegrityIndex {
+    enabled: bool,
+    #[cfg(not(feature = "embed-ui"))]
+    ui_root: PathBuf,
+    digests: RwLock<HashMap<String, (Stamp, String)>>,
+}
+
+fn stamp(path: &Path) -> Stamp {
+    let meta = std::fs::metadata(path).ok()?;
+    Some((meta.modified().ok()?, meta.len()))
+}
+
+fn digest(bytes: &[u8]) -> String {
+    format!("sha384-{}", STANDARD.encode(Sha384::digest(bytes)))
+}
+
+fn ui_relative(url: &str) -> Option<String> {
+    let path = url.split(['?', '#']).next()?;
+    if path.contains("..") {
+        return None;
+    }
+    if let Some(rest) = path.strip_prefix("/suite/") {
+        return Some(format!("suite/{rest}"));
+    }
+    path.strip_prefix("/auth/")
+        .map(|rest| format!("suite/auth/{rest}"))
+}
+
+impl IntegrityIndex {
+    #[must_use]
+    pub fn from_env() -> Self {
+        let enabled = std::env::var("BOTUI_SRI").map_or(true, |v| v != "false");
+        if enabled {
+            info!("Subresource Integrity enabled for /suite scripts and stylesheets");
+        }
+        Self {
+            enabled,
+            #[cfg(not(feature = "embed-ui"))]
+            ui_root: super::get_ui_root(),
+            digests: RwLock::new(HashMap::new()),
+        }
+    }
+
+    fn cached(&self, relative: &str, current: Stamp) -> Option<String> {
+        let digests = self.digests.read().ok()?;
+        digests
+            .get(relative)
+            .filter(|(seen, _)| *seen == current)
+            .map(|(_, digest)| digest.clone())
+    }
+
+    fn store(&self, relative: &str, current: Stamp, value: &str) {
+        if let Ok(mut digests) = self.digests.write() {
+            digests.insert(relative.to_string(), (current, value.to_string()));
+        }
+    }
+
+    fn digest_file(&self, relative: &str, path: &Path) -> Option<String> {
+        let current = stamp(path)?;
+        if let Some(cached) = self.cached(relative, Some(current)) {
+            return Some(cached);
+        }
+        let value = digest(&std::fs::read(path).ok()?);
+        self.store(relative, Some(current), &value);
+        Some(value)
+    }
+
+    fn digest_for(&self, relative: &str) -> Option<String> {
+        if let Some(path) = overlay::resolve(relative) {
+            return self.digest_file(relative, &path);
+        }
+
+        #[cfg(feature = "embed-ui")]
+        {
+            if let Some(cached) = self.cached(relative, None) {
+                return Some(cached);
+            }
+            let value = digest(&Assets::get(relative)?.data);
+            self.store(relative, None, &value);
+            Some(value)
+        }
+
+        #[cfg(not(feature = "embed-ui"))]
+        {
+            self.digest_file(relative, &self.ui_root.join(relative))
+        }
+    }
+
+    fn tag_with_integrity(&self, tag: &str, name: &str, url_attr: &str) -> Option<String> {
+        if html::attr(tag, "integrity").is_some() {
+            return None;
+        }
+        if name == "link"
+            && !html::attr(tag, "rel").is_some_and(|rel| rel.eq_ignore_ascii_case("stylesheet"))
+        {
+            return None;
+        }
+        let relative = ui_relative(html::attr(tag, url_attr)?)?;
+        let Some(integrity) = self.digest_for(&relative) else {
+            debug!("No integrity digest for {relative}");
+            return None;
+        };
+        Some(html::with_attrs(
+            tag,
+            name,
+            &format!(r#"integrity="{integrity}""#),
+        ))
+    }
+
+    pub fn annotate(&self, page: &str) -> String {
+        let page = html::rewrite_tags(page, "script", |tag| {
+            self.tag_with_integrity(tag, "script", "src")
+        });
+        html::rewrite_tags(&page, "link", |tag| {
+            self.tag_with_integrity(tag, "link", "href")
+        })
+    }
+}
+
+pub async fn apply(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let index = state.integrity.clone();
+    let is_head = request.method() == Method::HEAD;
+    let response = next.run(request).await;
+    if !index.enabled || is_head || !html::is_rewritable(&response) {
+        return response;
+    }
+    html::rewrite_response(response, |page| index.annotate(page)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index() -> IntegrityIndex {
+        IntegrityIndex {
+            enabled: true,
+            #[cfg(not(feature = "embed-ui"))]
+            ui_root: PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/ui")),
+            digests: RwLock::new(HashMap::new()),
+        }
+    }
+
+    fn expected(relative: &str) -> String {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("ui")
+            .join(relative);
+        digest(&std::fs::read(path).unwrap_or_default())
+    }
+
+    #[test]
+    fn maps_urls_into_the_ui_tree() {
+        assert_eq!(
+            ui_relative("/suite/js/base.js?v=2").as_deref(),
+            Some("suite/js/base.js")
+        );
+        assert_eq!(
+            ui_relative("/auth/login.js#x").as_deref(),
+            Some("suite/auth/login.js")
+        );
+        assert_eq!(ui_relative("/suite/../Cargo.toml"), None);
+        assert_eq!(ui_relative("js/base.js"), None);
+        assert_eq!(ui_relative("https://cdn.example.com/x.js"), None);
+    }
+
+    #[test]
+    fn digests_are_sha384() {
+        assert_eq!(
+            digest(b"alert('Hello, world.');"),
+            "sha384-H8BRh8j48O9oYatfu5AZzq6A9RINhZO5H16dQZngK7T62em8MUt1FLm52t+eX6xO"
+        );
+    }
+
+    #[test]
+    fn annotates_local_scripts_and_stylesheets() {
+        let page = concat!(
+            r#"<script src="/suite/js/base.js"></script>"#,
+            r#"<link rel="stylesheet" href="/suite/css/app.css">"#,
+            r#"<link rel="preload" href="/suite/css/app.css">"#,
+            r#"<script src="/suite/js/base.js" integrity="sha384-pinned"></script>"#,
+            r#"<script src="https://cdn.example.com/x.js"></script>"#,
+            r#"<script src="/suite/js/missing.js"></script>"#,
+        );
+        let annotated = index().annotate(page);
+
+        let script = format!(r#"integrity="{}""#, expected("suite/js/base.js"));
+        let style = format!(r#"integrity="{}""#, expected("suite/css/app.css"));
+        assert_eq!(annotated.matches(&script).count(), 1);
+        assert_eq!(annotated.matches(&style).count(), 1);
+        assert_eq!(annotated.matches("integrity=").count(), 3);
+        assert!(annotated.contains(r#"integrity="sha384-pinned""#));
+    }
+}
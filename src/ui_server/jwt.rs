@@ -0,0 +1,321 @@
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use log::{error, info, warn};
+use std::path::PathBuf;
+use std::sync::RwLock;
+use std::time::SystemTime;
+
+const HMAC_ALGORITHMS: &[Algorithm] = &[Algorithm::HS256, Algorithm::HS384, Algorithm::HS512];
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Identity {
+    pub subject: Option<String>,
+    pub roles: Vec<String>,
+}
+
+impl Identity {
+    pub fn has_any_role(&self, roles: &[String]) -> bool {
+        roles.iter().any(|role| self.roles.contains(role))
+    }
+}
+
+enum Keys {
+    Secret(DecodingKey),
+    Jwks {
+        path: PathBuf,
+        loaded: RwLock<(Option<SystemTime>, JwkSet)>,
+    },
+}
+
+pub struct JwtVerifier {
+    keys: Keys,
+    token_cookie: Option<String>,
+    issuer: Option<String>,
+    audience: Option<String>,
+    roles_claim: String,
+}
+
+impl std::fmt::Debug for JwtVerifier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let keys = match &self.keys {
+            Keys::Secret(_) => "secret".to_string(),
+            Keys::Jwks { path, .. } => format!("jwks {}", path.display()),
+        };
+        f.debug_struct("JwtVerifier")
+            .field("keys", &keys)
+            .field("token_cookie", &self.token_cookie)
+            .field("issuer", &self.issuer)
+            .field("audience", &self.audience)
+            .field("roles_claim", &self.roles_claim)
+            .finish()
+    }
+}
+
+fn modified(path: &PathBuf) -> Option<SystemTime> {
+    std::fs::metadata(path).ok()?.modified().ok()
+}
+
+fn read_jwks(path: &PathBuf) -> Option<JwkSet> {
+    let raw = std::fs::read_to_string(path)
+        .map_err(|e| error!("Failed to read JWKS file {}: {e}", path.display()))
+        .ok()?;
+    serde_json::from_str(&raw)
+        .map_err(|e| error!("Invalid JWKS file {}: {e}", path.display()))
+        .ok()
+}
+
+fn non_empty_env(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|v| !v.is_empty())
+}
+
+impl JwtVerifier {
+    #[must_use]
+    pub fn from_env() -> Option<Self> {
+        let keys = if let Some(path) = non_empty_env("BOTUI_JWT_JWKS_FILE").map(PathBuf::from) {
+            let set = read_jwks(&path)?;
+            info!(
+                "JWT verification enabled with {} keys from {}",
+                set.keys.len(),
+                path.display()
+            );
+            Keys::Jwks {
+                loaded: RwLock::new((modified(&path), set)),
+                path,
+            }
+        } else {
+            let secret = non_empty_env("BOTUI_JWT_SECRET")?;
+            if secret.len() < 32 {
+                warn!("BOTUI_JWT_SECRET is shorter than 32 characters");
+            }
+            info!("JWT verification enabled with a shared secret");
+            Keys::Secret(DecodingKey::from_secret(secret.as_bytes()))
+        };
+
+        Some(Self {
+            keys,
+            token_cookie: non_empty_env("BOTUI_JWT_COOKIE"),
+            issuer: non_empty_env("BOTUI_JWT_ISSUER"),
+            audience: non_empty_env("BOTUI_JWT_AUDIENCE"),
+            roles_claim: non_empty_env("BOTUI_JWT_ROLES_CLAIM")
+                .unwrap_or_else(|| "roles".to_string()),
+        })
+    }
+
+    pub fn token_cookie(&self) -> Option<&str> {
+        self.token_cookie.as_deref()
+    }
+
+    fn key_for(&self, alg: Algorithm, kid: Option<&str>) -> Result<DecodingKey, String> {
+        match &self.keys {
+            Keys::Secret(key) => {
+                if HMAC_ALGORITHMS.contains(&alg) {
+                    Ok(key.clone())
+                } else {
+                    Err(format!(
+                        "algorithm {alg:?} not allowed with a shared secret"
+                    ))
+                }
+            }
+            Keys::Jwks { path, loaded } => {
+                let current = modified(path);
+                let stale = loaded.read().map_or(true, |l| l.0 != current);
+                if stale {
+                    if let (Some(set), Ok(mut loaded)) = (read_jwks(path), loaded.write()) {
+                        info!(
+                            "Reloaded {} JWKS keys from {}",
+                            set.keys.len(),
+                            path.display()
+                        );
+                        *loaded = (current, set);
+                    }
+                }
+                let loaded = loaded.read().map_err(|_| "JWKS unavailable".to_string())?;
+                let set = &loaded.1;
+                let jwk = match kid {
+                    Some(kid) => set.find(kid),
+                    None if set.keys.len() == 1 => set.keys.first(),
+                    None => None,
+                }
+                .ok_or_else(|| format!("no JWKS key for kid {kid:?}"))?;
+                DecodingKey::from_jwk(jwk).map_err(|e| format!("unusable JWKS key: {e}"))
+            }
+        }
+    }
+
+    pub fn verify(&self, token: &str) -> Result<Identity, String> {
+        let header = decode_header(token).map_err(|e| format!("malformed token: {e}"))?;
+        let key = self.key_for(header.alg, header.kid.as_deref())?;
+
+        let mut validation = Validation::new(header.alg);
+        if let Some(issuer) = &self.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        match &self.audience {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+
+        let claims = decode::<serde_json::Value>(token, &key, &validation)
+            .map_err(|e| format!("rejected token: {e}"))?
+            .claims;
+
+        Ok(Identity {
+            subject: claims
+                .get("sub")
+                .and_then(|v| v.as_str())
+                .map(str::to_string),
+            roles: self.roles(&claims),
+        })
+    }
+
+    fn roles(&self, claims: &serde_json::Value) -> Vec<String> {
+        let value = self
+            .roles_claim
+            .split('.')
+            .try_fold(claims, |value, key| value.get(key));
+        match value {
+            Some(serde_json::Value::Array(items)) => items
+                .iter()
+                .filter_map(|v| v.as_str())
+                .map(str::to_string)
+                .collect(),
+            Some(serde_json::Value::String(list)) => list
+                .split([' ', ','])
+                .filter(|r| !r.is_empty())
+                .map(str::to_string)
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use std::time::Duration;
+
+    const SECRET: &[u8] = b"0123456789abcdef0123456789abcdef";
+
+    fn verifier(keys: Keys) -> JwtVerifier {
+        JwtVerifier {
+            keys,
+            token_cookie: None,
+            issuer: Some("https://id.example".to_string()),
+            audience: Some("botui".to_string()),
+            roles_claim: "realm_access.roles".to_string(),
+        }
+    }
+
+    fn token(kid: Option<&str>, secret: &[u8], claims: &serde_json::Value) -> String {
+        let header = Header {
+            kid: kid.map(str::to_string),
+            ..Header::new(Algorithm::HS256)
+        };
+        encode(&header, claims, &EncodingKey::from_secret(secret)).unwrap_or_default()
+    }
+
+    fn claims(iss: &str, aud: &str) -> serde_json::Value {
+        let exp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs())
+            + 300;
+        serde_json::json!({
+            "sub": "ana",
+            "iss": iss,
+            "aud": aud,
+            "exp": exp,
+            "realm_access": { "roles": ["admin", "editor"] },
+        })
+    }
+
+    fn write_jwks(path: &PathBuf, kids: &[&str], modified: SystemTime) {
+        let keys: Vec<_> = kids
+            .iter()
+            .map(|kid| {
+                serde_json::json!({
+                    "kty": "oct",
+                    "kid": kid,
+                    "alg": "HS256",
+                    "k": base64::Engine::encode(
+                        &base64::engine::general_purpose::URL_SAFE_NO_PAD,
+                        SECRET,
+                    ),
+                })
+            })
+            .collect();
+        let _ = std::fs::write(path, serde_json::json!({ "keys": keys }).to_string());
+        if let Ok(file) = std::fs::File::options().write(true).open(path) {
+            let _ = file.set_modified(modified);
+        }
+    }
+
+    #[test]
+    fn verifies_secret_signed_tokens() {
+        let verifier = verifier(Keys::Secret(DecodingKey::from_secret(SECRET)));
+        let identity =
+            verifier.verify(&token(None, SECRET, &claims("https://id.example", "botui")));
+        assert_eq!(
+            identity,
+            Ok(Identity {
+                subject: Some("ana".to_string()),
+                roles: vec!["admin".to_string(), "editor".to_string()],
+            })
+        );
+
+        for claims in [
+            claims("https://evil.example", "botui"),
+            claims("https://id.example", "other"),
+        ] {
+            assert!(verifier.verify(&token(None, SECRET, &claims)).is_err());
+        }
+        let forged = token(
+            None,
+            b"another-secret",
+            &claims("https://id.example", "botui"),
+        );
+        assert!(verifier.verify(&forged).is_err());
+        assert!(verifier.verify("not.a.token").is_err());
+        assert!(verifier.key_for(Algorithm::RS256, None).is_err());
+    }
+
+    #[test]
+    fn reads_roles_from_arrays_and_strings() {
+        let mut verifier = verifier(Keys::Secret(DecodingKey::from_secret(SECRET)));
+        verifier.roles_claim = "scope".to_string();
+        let claims = serde_json::json!({ "scope": "read write,admin" });
+        assert_eq!(verifier.roles(&claims), vec!["read", "write", "admin"]);
+        assert_eq!(verifier.roles(&serde_json::json!({})), Vec::<String>::new());
+    }
+
+    #[test]
+    fn reloads_rotated_jwks_keys() {
+        let path = std::env::temp_dir().join(format!("botui-jwks-{}.json", std::process::id()));
+        let start = SystemTime::now() - Duration::from_secs(60);
+        write_jwks(&path, &["old"], start);
+        let verifier = verifier(Keys::Jwks {
+            loaded: RwLock::new((
+                modified(&path),
+                read_jwks(&path).unwrap_or(JwkSet { keys: vec![] }),
+            )),
+            path: path.clone(),
+        });
+        let claims = claims("https://id.example", "botui");
+
+        assert!(verifier
+            .verify(&token(Some("old"), SECRET, &claims))
+            .is_ok());
+        assert!(verifier
+            .verify(&token(Some("new"), SECRET, &claims))
+            .is_err());
+
+        write_jwks(&path, &["new"], start + Duration::from_secs(30));
+        assert!(verifier
+            .verify(&token(Some("new"), SECRET, &claims))
+            .is_ok());
+        assert!(verifier
+            .verify(&token(Some("old"), SECRET, &claims))
+            .is_err());
+        let _ = std::fs::remove_file(&path);
+    }
+}
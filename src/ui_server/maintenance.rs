@@ -0,0 +1,549 @@
+use axum::{
+    extract::{
+        ws::{CloseFrame, Message, WebSocketUpgrade},
+        FromRequestParts, Query, Request, State,
+    },
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{Html, IntoResponse, Json, Response},
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::sync::{Arc, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::cookies::{self, CookieOptions};
+use super::errors::{self, ErrorKind};
+use super::forwarded::{self, Network};
+use super::{read_ui_text, route};
+use crate::shared::AppState;
+
+pub const ADMIN_PATH: &str = "/maintenance";
+pub const BYPASS_PATH: &str = "/maintenance/bypass";
+const BYPASS_COOKIE: &str = "gb_maintenance_bypass";
+const BYPASS_COOKIE_MAX_AGE_SECS: u64 = 12 * 3600;
+const PAGE: &str = "suite/maintenance.html";
+const WS_TRY_AGAIN_LATER: u16 = 1013;
+const DEFAULT_MESSAGE: &str =
+    "We are upgrading General Bots and will be back shortly. Thank you for your patience.";
+
+fn is_exempt(path: &str) -> bool {
+    if ["/health", "/api/health", "/favicon.ico", BYPASS_PATH].contains(&path)
+        || path == format!("/api{ADMIN_PATH}")
+    {
+        return true;
+    }
+    let Some(path) = route::normalize(path).map(|p| route::without_bot(&p)) else {
+        return false;
+    };
+    let name = path.rsplit('/').next().unwrap_or_default();
+    (path.starts_with("/suite/") || path.starts_with("/auth/"))
+        && name.rsplit_once('.').is_some_and(|(_, ext)| {
+            !ext.eq_ignore_ascii_case("html") && !ext.eq_ignore_ascii_case("htm")
+        })
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+fn parse_timestamp(raw: &str) -> Option<u64> {
+    let raw = raw.trim();
+    let (date, time) = raw.split_once(['T', 't', ' '])?;
+    let mut date = date.splitn(3, '-').map(str::parse::<i64>);
+    let (year, month, day) = (date.next()?.ok()?, date.next()?.ok()?, date.next()?.ok()?);
+
+    let (clock, offset_secs) = if let Some(clock) = time.strip_suffix(['Z', 'z']) {
+        (clock, 0)
+    } else {
+        let at = time.rfind(['+', '-'])?;
+        let (clock, offset) = time.split_at(at);
+        let sign = if offset.starts_with('-') { -1 } else { 1 };
+        let (hours, minutes) = offset[1..].split_once(':')?;
+        (
+            clock,
+            sign * (hours.parse::<i64>().ok()? * 3600 + minutes.parse::<i64>().ok()? * 60),
+        )
+    };
+    let clock = clock.split('.').next()?;
+    let mut clock = clock.splitn(3, ':').map(str::parse::<i64>);
+    let (hour, minute, second) = (
+        clock.next()?.ok()?,
+        clock.next()?.ok()?,
+        clock.next().unwrap_or(Ok(0)).ok()?,
+    );
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 {
+        return None;
+    }
+
+    let secs = days_from_civil(year, month, day) * 86_400 + hour * 3600 + minute * 60 + second
+        - offset_secs;
+    u64::try_from(secs).ok()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Window {
+    start: u64,
+    end: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Override {
+    enabled: bool,
+    message: Option<String>,
+    until: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Active {
+    message: String,
+    until: Option<u64>,
+}
+
+#[derive(Debug)]
+pub struct Maintenance {
+    windows: Vec<Window>,
+    allow: Vec<Network>,
+    bypass_key: Option<String>,
+    admin_token: Option<String>,
+    message: String,
+    theme: String,
+    retry_secs: u64,
+    cookie_options: CookieOptions,
+    manual: RwLock<Option<Override>>,
+}
+
+fn non_empty_env(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|v| !v.is_empty())
+}
+
+fn same_secret(a: &str, b: &str) -> bool {
+    Sha256::digest(a.as_bytes()) == Sha256::digest(b.as_bytes())
+}
+
+fn bypass_token(key: &str) -> Option<String> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes()).ok()?;
+    mac.update(BYPASS_COOKIE.as_bytes());
+    Some(URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes()))
+}
+
+impl Maintenance {
+    #[must_use]
+    pub fn from_env() -> Self {
+        let mut windows = Vec::new();
+        for entry in std::env::var("BOTUI_MAINTENANCE_WINDOWS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|e| !e.is_empty())
+        {
+            let window = entry.split_once('/').and_then(|(start, end)| {
+                let window = Window {
+                    start: parse_timestamp(start)?,
+                    end: parse_timestamp(end)?,
+                };
+                (window.start < window.end).then_some(window)
+            });
+            match window {
+                Some(window) => windows.push(window),
+                None => warn!("Ignoring invalid maintenance window: {entry}"),
+            }
+        }
+
+        let theme = non_empty_env("BOTUI_MAINTENANCE_THEME")
+            .filter(|t| route::is_valid_name(t))
+            .unwrap_or_else(|| "dark".to_string());
+        let enabled = std::env::var("BOTUI_MAINTENANCE").is_ok_and(|v| v == "true");
+        if enabled || !windows.is_empty() {
+            info!(
+                "Maintenance mode {}, {} scheduled windows",
+                if enabled { "on" } else { "off" },
+                windows.len()
+            );
+        }
+
+        Self {
+            windows,
+            allow: forwarded::networks_from_env("BOTUI_MAINTENANCE_ALLOW"),
+            bypass_key: non_empty_env("BOTUI_MAINTENANCE_BYPASS_KEY"),
+            admin_token: non_empty_env("BOTUI_MAINTENANCE_TOKEN"),
+            message: non_empty_env("BOTUI_MAINTENANCE_MESSAGE")
+                .unwrap_or_else(|| DEFAULT_MESSAGE.to_string()),
+            theme,
+            retry_secs: std::env::var("BOTUI_MAINTENANCE_RETRY_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(300),
+            cookie_options: CookieOptions {
+                http_only: true,
+                secure: std::env::var("BOTUI_COOKIE_SECURE").map_or(true, |v| v != "false"),
+                same_site: "Lax",
+            },
+            manual: RwLock::new(enabled.then_some(Override {
+                enabled: true,
+                message: None,
+                until: None,
+            })),
+        }
+    }
+
+    fn active_at(&self, now: u64) -> Option<Active> {
+        let manual = self
+            .manual
+            .read()
+            .ok()
+            .and_then(|m| m.clone())
+            // An override with an end time lapses back to the schedule
+            .filter(|m| m.until.is_none_or(|until| now < until));
+        if let Some(manual) = manual {
+            return manual.enabled.then(|| Active {
+                message: manual.message.unwrap_or_else(|| self.message.clone()),
+                until: manual.until,
+            });
+        }
+        self.windows
+            .iter()
+            .find(|w| w.start <= now && now < w.end)
+            .map(|w| Active {
+                message: self.message.clone(),
+                until: Some(w.end),
+            })
+    }
+
+    pub fn active(&self) -> Option<Active> {
+        self.active_at(now_secs())
+    }
+
+    fn set(&self, value: Option<Override>) {
+        match &value {
+            Some(o) if o.enabled => warn!("Maintenance mode enabled"),
+            Some(_) => info!("Maintenance mode disabled"),
+            None => info!("Maintenance override cleared, following schedule"),
+        }
+        if let Ok(mut manual) = self.manual.write() {
+            *manual = value;
+        }
+    }
+
+    pub fn toggle(&self) {
+        self.set(Some(Override {
+            enabled: self.active().is_none(),
+            message: None,
+            until: None,
+        }));
+    }
+
+    fn retry_after(&self, active: &Active) -> u64 {
+        active
+            .until
+            .map_or(self.retry_secs, |until| until.saturating_sub(now_secs()))
+            .max(1)
+    }
+
+    fn bypassed(&self, state: &AppState, request: &Request) -> bool {
+        let allowed = forwarded::client_ip(state, request)
+            .is_some_and(|ip| self.allow.iter().any(|n| n.contains(ip)));
+        allowed
+            || self
+                .bypass_key
+                .as_deref()
+                .and_then(bypass_token)
+                .is_some_and(|token| {
+                    cookies::get(request.headers(), BYPASS_COOKIE)
+                        .is_some_and(|value| same_secret(&value, &token))
+                })
+    }
+
+    fn page(&self, active: &Active, retry_after: u64) -> String {
+        let template = read_ui_text(PAGE).unwrap_or_else(|e| {
+            warn!("Maintenance page template unavailable: {e}");
+            "<!doctype html><title>Down for maintenance</title><h1>Down for maintenance</h1><p>{{message}}</p>".to_string()
+        });
+        let theme_css = read_ui_text(&format!("public/themes/{}.css", self.theme))
+            .unwrap_or_default()
+            .replace("</", "<\\/");
+        let until = active.until.map_or_else(String::new, |until| {
+            let minutes = until.saturating_sub(now_secs()).div_ceil(60);
+            format!("Expected back in about {minutes} minute(s).")
+        });
+        template
+            .replace("{{theme_css}}", &theme_css)
+            .replace("{{message}}", &super::html::escape(&active.message))
+            .replace("{{until}}", &until)
+            .replace("{{retry_after}}", &retry_after.to_string())
+    }
+
+    fn is_admin(&self, headers: &HeaderMap) -> bool {
+        let Some(token) = &self.admin_token else {
+            return false;
+        };
+        headers
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .is_some_and(|given| same_secret(given, token))
+    }
+}
+
+pub async fn apply(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let Some(active) = state.maintenance.active() else {
+        return next.run(request).await;
+    };
+    if is_exempt(request.uri().path()) || state.maintenance.bypassed(&state, &request) {
+        return next.run(request).await;
+    }
+    let retry_after = state.maintenance.retry_after(&active);
+
+    let is_upgrade = request
+        .headers()
+        .get(header::UPGRADE)
+        .is_some_and(|v| v.as_bytes().eq_ignore_ascii_case(b"websocket"));
+    if is_upgrade {
+        let (mut parts, _) = request.into_parts();
+        return match WebSocketUpgrade::from_request_parts(&mut parts, &state).await {
+            Ok(ws) => ws
+                .on_upgrade(|mut socket| async move {
+                    let _ = socket
+                        .send(Message::Close(Some(CloseFrame {
+                            code: WS_TRY_AGAIN_LATER,
+                            reason: "Down for maintenance".into(),
+                        })))
+                        .await;
+                })
+                .into_response(),
+            Err(rejection) => rejection.into_response(),
+        };
+    }
+
+    let headers = request.headers();
+    let mut response = if errors::wants_html(headers) && !errors::is_htmx(headers) {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Html(state.maintenance.page(&active, retry_after)),
+        )
+            .into_response()
+    } else {
+        errors::respond(
+            headers,
+            request.method(),
+            request.uri(),
+            ErrorKind::Maintenance,
+            "",
+        )
+    };
+    let response_headers = response.headers_mut();
+    response_headers.insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+    response_headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    response
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BypassQuery {
+    key: Option<String>,
+}
+
+pub async fn bypass(State(state): State<AppState>, Query(query): Query<BypassQuery>) -> Response {
+    let maintenance = &state.maintenance;
+    let valid = match (&maintenance.bypass_key, &query.key) {
+        (Some(expected), Some(given)) => same_secret(given, expected),
+        _ => false,
+    };
+    let token = maintenance.bypass_key.as_deref().and_then(bypass_token);
+    let (true, Some(token)) = (valid, token) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let mut response = (StatusCode::SEE_OTHER, [(header::LOCATION, "/")]).into_response();
+    let cookie = cookies::set(
+        BYPASS_COOKIE,
+        &token,
+        BYPASS_COOKIE_MAX_AGE_SECS,
+        maintenance.cookie_options,
+    );
+    if let Ok(value) = HeaderValue::from_str(&cookie) {
+        response.headers_mut().append(header::SET_COOKIE, value);
+    }
+    response
+}
+
+#[derive(Debug, Serialize)]
+struct Status {
+    active: bool,
+    message: Option<String>,
+    until: Option<u64>,
+}
+
+impl From<Option<Active>> for Status {
+    fn from(active: Option<Active>) -> Self {
+        Self {
+            active: active.is_some(),
+            message: active.as_ref().map(|a| a.message.clone()),
+            until: active.and_then(|a| a.until),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OverrideRequest {
+    enabled: Option<bool>,
+    message: Option<String>,
+    until: Option<String>,
+}
+
+pub async fn status(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    if !state.maintenance.is_admin(&headers) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    Json(Status::from(state.maintenance.active())).into_response()
+}
+
+pub async fn update(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(body): Json<OverrideRequest>,
+) -> Response {
+    let maintenance = &state.maintenance;
+    if !maintenance.is_admin(&headers) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    let until = match body.until.as_deref().map(parse_timestamp) {
+        Some(None) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                "until must be an RFC 3339 timestamp",
+            )
+                .into_response()
+        }
+        Some(Some(until)) => Some(until),
+        None => None,
+    };
+    maintenance.set(body.enabled.map(|enabled| Override {
+        enabled,
+        message: body.message.filter(|m| !m.is_empty()),
+        until,
+    }));
+    Json(Status::from(maintenance.active())).into_response()
+}
+
+pub fn spawn_signal_handler(maintenance: Arc<Maintenance>) {
+    #[cfg(unix)]
+    {
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        runtime.spawn(async move {
+            use tokio::signal::unix::{signal, SignalKind};
+            let mut signals = match signal(SignalKind::user_defined1()) {
+                Ok(signals) => signals,
+                Err(e) => {
+                    warn!("Failed to install SIGUSR1 handler: {e}");
+                    return;
+                }
+            };
+            while signals.recv().await.is_some() {
+                maintenance.toggle();
+            }
+        });
+    }
+    #[cfg(not(unix))]
+    drop(maintenance);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_rfc3339() {
+        assert_eq!(parse_timestamp("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(parse_timestamp("2026-10-20T02:00:00Z"), Some(1_792_461_600));
+        assert_eq!(
+            parse_timestamp("2026-10-19T23:00:00-03:00"),
+            Some(1_792_461_600)
+        );
+        assert_eq!(parse_timestamp("2026-13-01T00:00:00Z"), None);
+        assert_eq!(parse_timestamp("yesterday"), None);
+    }
+
+    #[test]
+    fn windows_and_overrides() {
+        let maintenance = Maintenance {
+            windows: vec![Window {
+                start: 100,
+                end: 200,
+            }],
+            allow: Vec::new(),
+            bypass_key: None,
+            admin_token: None,
+            message: DEFAULT_MESSAGE.to_string(),
+            theme: "dark".to_string(),
+            retry_secs: 300,
+            cookie_options: CookieOptions {
+                http_only: true,
+                secure: true,
+                same_site: "Lax",
+            },
+            manual: RwLock::new(None),
+        };
+        assert!(maintenance.active_at(50).is_none());
+        assert_eq!(maintenance.active_at(150).and_then(|a| a.until), Some(200));
+
+        maintenance.set(Some(Override {
+            enabled: false,
+            message: None,
+            until: None,
+        }));
+        assert!(maintenance.active_at(150).is_none());
+
+        maintenance.set(Some(Override {
+            enabled: true,
+            message: Some("Upgrading".to_string()),
+            until: Some(400),
+        }));
+        assert_eq!(
+            maintenance.active_at(300).map(|a| a.message).as_deref(),
+            Some("Upgrading")
+        );
+        assert!(maintenance.active_at(450).is_none());
+    }
+
+    #[test]
+    fn assets_and_probes_are_exempt() {
+        assert!(is_exempt("/health"));
+        assert!(is_exempt("/api/maintenance"));
+        assert!(is_exempt("/suite/css/base.css"));
+        assert!(is_exempt("/edu/suite/js/base.js"));
+        assert!(is_exempt("/suite/public/themes/dark.css"));
+        assert!(is_exempt("/auth/login.js"));
+        assert!(!is_exempt("/suite/chat/chat.html"));
+        assert!(!is_exempt("/api/tasks"));
+        assert!(!is_exempt("/edu/chat"));
+        assert!(!is_exempt("/api/files/read.json"));
+        assert!(!is_exempt("/ui/chat/panel.js"));
+        assert!(!is_exempt("/apps/crm/export.csv"));
+        assert!(!is_exempt("/suite/../api/tasks.json"));
+    }
+
+    #[test]
+    fn bypass_cookie_holds_a_derived_token() {
+        let token = bypass_token("staff-key").unwrap_or_default();
+        assert!(!token.is_empty());
+        assert_ne!(token, "staff-key");
+        assert_eq!(bypass_token("staff-key").as_deref(), Some(token.as_str()));
+        assert_ne!(bypass_token("other-key").as_deref(), Some(token.as_str()));
+    }
+}
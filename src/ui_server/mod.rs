@@ -1,10 +1,10 @@
 use axum::{
-    body::Body,
+    body::{Body, Bytes},
     extract::{
         ws::{Message as AxumMessage, WebSocket, WebSocketUpgrade},
-        OriginalUri, Query, State,
+        Extension, OriginalUri, Query, State,
     },
-    http::{Request, StatusCode},
+    http::{header, HeaderMap, HeaderName, HeaderValue, Method, Request, StatusCode, Uri},
     response::{Html, IntoResponse, Response},
     routing::{any, get},
     Json, Router,
@@ -14,14 +14,18 @@ use log::{debug, error, info, warn};
 #[cfg(feature = "embed-ui")]
 use rust_embed::RustEmbed;
 use serde::Deserialize;
+#[cfg(not(feature = "embed-ui"))]
+use std::convert::Infallible;
 use std::fs;
 use std::path::{Path, PathBuf};
 use tokio_tungstenite::{
     connect_async_tls_with_config, tungstenite,
-    tungstenite::protocol::Message as TungsteniteMessage,
+    tungstenite::{client::IntoClientRequest, protocol::Message as TungsteniteMessage},
 };
 #[cfg(not(feature = "embed-ui"))]
-use tower_http::services::{ServeDir, ServeFile};
+use tower::ServiceExt;
+#[cfg(not(feature = "embed-ui"))]
+use tower_http::services::{fs::ServeFileSystemResponseBody, ServeDir, ServeFile};
 
 #[cfg(feature = "embed-ui")]
 #[derive(RustEmbed)]
@@ -29,6 +33,39 @@ use tower_http::services::{ServeDir, ServeFile};
 struct Assets;
 
 use crate::shared::AppState;
+use errors::ErrorKind;
+use hosts::BotSource;
+use route::UiRoute;
+
+pub(crate) mod access;
+pub(crate) mod auth_guard;
+pub(crate) mod branding;
+mod coalesce;
+mod cookies;
+pub(crate) mod cors;
+pub(crate) mod csrf;
+pub(crate) mod errors;
+pub(crate) mod forwarded;
+pub(crate) mod hosts;
+mod html;
+pub(crate) mod i18n;
+pub(crate) mod integrity;
+pub(crate) mod jwt;
+pub(crate) mod maintenance;
+pub(crate) mod oidc;
+mod overlay;
+pub(crate) mod proxy_policy;
+pub(crate) mod rate_limit;
+pub(crate) mod resilience;
+pub(crate) mod response_cache;
+pub(crate) mod rewrite;
+pub(crate) mod route;
+pub(crate) mod security_headers;
+pub(crate) mod session;
+mod static_files;
+pub(crate) mod theme_builder;
+pub(crate) mod themes;
+pub(crate) mod upstream;
 
 const SUITE_DIRS: &[&str] = &[
     "js",
@@ -132,124 +169,34 @@ const ROOT_FILES: &[&str] = &[
     "single.gbui",
 ];
 
-pub async fn index(OriginalUri(uri): OriginalUri) -> Response {
+pub async fn index(
+    State(state): State<AppState>,
+    OriginalUri(uri): OriginalUri,
+    headers: HeaderMap,
+) -> Response {
     let path = uri.path();
 
-    // Check if path contains static asset directories - serve them directly
-    let path_lower = path.to_lowercase();
-    if path_lower.contains("/js/")
-        || path_lower.contains("/css/")
-        || path_lower.contains("/vendor/")
-        || path_lower.contains("/assets/")
-        || path_lower.contains("/public/")
-        || path_lower.contains("/partials/")
-        || path_lower.contains("/crm/")
-        || path_lower.contains("/tasks/")
-        || path_lower.contains("/drive/")
-        || path_lower.contains("/terminal/")
-        || path_lower.contains("/browser/")
-        || path_lower.contains("/editor/")
-        || path_lower.ends_with(".js")
-        || path_lower.ends_with(".css")
-        || path_lower.ends_with(".html")
-        || path_lower.ends_with(".png")
-        || path_lower.ends_with(".jpg")
-        || path_lower.ends_with(".jpeg")
-        || path_lower.ends_with(".gif")
-        || path_lower.ends_with(".svg")
-        || path_lower.ends_with(".ico")
-        || path_lower.ends_with(".woff")
-        || path_lower.ends_with(".woff2")
-        || path_lower.ends_with(".ttf")
-        || path_lower.ends_with(".eot")
-        || path_lower.ends_with(".mp4")
-        || path_lower.ends_with(".webm")
-        || path_lower.ends_with(".mp3")
-        || path_lower.ends_with(".wav")
-    {
-        // Remove bot name prefix if present (e.g., /edu/suite/js/file.js -> suite/js/file.js)
-        let path_parts: Vec<&str> = path.split('/').collect();
-        let fs_path = if path_parts.len() > 1 {
-            let mut start_idx = 1;
-            let known_dirs = ["suite", "js", "css", "vendor", "assets", "public", "partials", "settings", "auth", "about", "drive", "chat", "tasks", "admin", "mail", "calendar", "meet", "docs", "sheet", "slides", "paper", "research", "sources", "learn", "analytics", "dashboards", "monitoring", "people", "crm", "tickets", "billing", "products", "video", "player", "canvas", "social", "project", "goals", "workspace", "designer", "vibe"];
-            let suite_dirs = ["drive", "chat", "tasks", "admin", "mail", "calendar", "meet", "docs", "sheet", "slides", "paper", "research", "sources", "learn", "analytics", "dashboards", "monitoring", "people", "crm", "tickets", "billing", "products", "video", "player", "canvas", "social", "project", "goals", "workspace", "designer", "vibe"];
-
-            // If the first segment is already a known dir (suite subdirectory), prepend "suite/"
-            if known_dirs.contains(&path_parts[1]) {
-                if suite_dirs.contains(&path_parts[1]) {
-                    // e.g., /crm/crm.css -> suite/crm/crm.css
-                    format!("suite/{}", path_parts[1..].join("/"))
-                } else {
-                    // e.g., /js/..., /suite/..., /css/...
-                    path_parts[1..].join("/")
-                }
-            } else {
-                // Special case: /auth/suite/* should map to suite/* (auth is a route, not a directory)
-                if path_parts.get(1) == Some(&"auth") && path_parts.get(2) == Some(&"suite") {
-                    start_idx = 2;
-                }
-                // Skip bot name if present (first segment is not a known dir, second segment is)
-                else if path_parts.len() > start_idx + 1
-                    && known_dirs.contains(&path_parts[start_idx + 1])
-                {
-                    start_idx += 1;
-                }
-
-                path_parts[start_idx..].join("/")
-            }
-        } else {
-            path.to_string()
-        };
-
-        let full_path = get_ui_root().join(&fs_path);
-
-        info!("index: Serving static file: {} -> {:?} (fs_path: {})", path, full_path, fs_path);
-
-        #[cfg(feature = "embed-ui")]
-        {
-            let asset_path = fs_path.trim_start_matches('/');
-            if let Some(content) = Assets::get(asset_path) {
-                let mime = mime_guess::from_path(asset_path).first_or_octet_stream();
-                return ([(axum::http::header::CONTENT_TYPE, mime.as_ref())], content.data).into_response();
-            }
-        }
-
-        #[cfg(not(feature = "embed-ui"))]
-        {
-            if let Ok(bytes) = tokio::fs::read(&full_path).await {
-                let mime = mime_guess::from_path(&full_path).first_or_octet_stream();
-                return (StatusCode::OK, [("content-type", mime.as_ref())], bytes).into_response();
-            }
+    if let Some(file) = route::asset_path(path) {
+        if let Some(response) = serve_ui_file(&headers, &file).await {
+            return response;
         }
-
-        warn!("index: Static file not found: {} -> {:?}", path, full_path);
+        warn!("index: Static file not found: {path} -> {file}");
         return StatusCode::NOT_FOUND.into_response();
     }
 
-    let path_parts: Vec<&str> = path.split('/').collect();
-    let bot_name = path_parts
-        .iter()
-        .rev()
-        .find(|part| {
-            !part.is_empty()
-                && **part != "chat"
-                && **part != "app"
-                && **part != "ws"
-                && **part != "ui"
-                && **part != "api"
-                && **part != "auth"
-                && **part != "suite"
-                && !part.ends_with(".js")
-                && !part.ends_with(".css")
-        })
-        .map(|s| s.to_string());
+    let (route, source) = match state.hosts.bot_for_request(&headers, &uri) {
+        Some(bot_name) => (
+            UiRoute {
+                bot: Some(bot_name),
+                ..route::parse_without_bot(path)
+            },
+            BotSource::Host,
+        ),
+        None => (route::parse(path), BotSource::Path),
+    };
 
-    info!(
-        "index: Extracted bot_name: {:?} from path: {}",
-        bot_name,
-        path
-    );
-    serve_suite(bot_name).await.into_response()
+    info!("index: Resolved {route:?} ({source:?}) from path: {path}");
+    serve_suite(&state, &headers, route, source).await
 }
 
 pub fn get_ui_root() -> PathBuf {
@@ -286,30 +233,58 @@ pub fn get_ui_root() -> PathBuf {
     }
 }
 
-pub async fn serve_minimal() -> impl IntoResponse {
-    let html_res = {
-        #[cfg(feature = "embed-ui")]
-        {
-            Assets::get("minimal/index.html")
-                .map(|f| String::from_utf8(f.data.into_owned()).map_err(|e| e.to_string()))
-                .unwrap_or(Err("Asset not found".to_string()))
+async fn serve_ui_file(headers: &HeaderMap, relative: &str) -> Option<Response> {
+    let relative = relative.trim_start_matches('/');
+    if let Some(path) = overlay::resolve(relative) {
+        if let Some(response) = static_files::serve_file(headers, &path).await {
+            return Some(response);
         }
-        #[cfg(not(feature = "embed-ui"))]
-        {
-            let path = get_ui_root().join("minimal/index.html");
-            fs::read_to_string(&path).map_err(|e| {
-                format!(
-                    "Failed to read {:?} (CWD: {:?}): {}",
-                    path,
-                    std::env::current_dir(),
-                    e
-                )
-            })
+    }
+
+    #[cfg(feature = "embed-ui")]
+    {
+        Assets::get(relative)
+            .map(|content| static_files::serve_embedded(headers, relative, content))
+    }
+
+    #[cfg(not(feature = "embed-ui"))]
+    {
+        static_files::serve_file(headers, &get_ui_root().join(relative)).await
+    }
+}
+
+fn read_ui_text(relative: &str) -> Result<String, String> {
+    if let Some(path) = overlay::resolve(relative) {
+        return fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read overlay file {path:?}: {e}"));
+    }
+
+    #[cfg(feature = "embed-ui")]
+    {
+        if let Some(f) = Assets::get(relative) {
+            return String::from_utf8(f.data.into_owned()).map_err(|e| e.to_string());
         }
-    };
+        warn!("Asset {relative} not found in embedded binary, falling back to filesystem");
+    }
 
-    match html_res {
-        Ok(html) => (StatusCode::OK, [("content-type", "text/html; charset=utf-8")], Html(html)),
+    let path = get_ui_root().join(relative);
+    fs::read_to_string(&path).map_err(|e| {
+        format!(
+            "Failed to read {:?} (CWD: {:?}): {}",
+            path,
+            std::env::current_dir(),
+            e
+        )
+    })
+}
+
+pub async fn serve_minimal() -> impl IntoResponse {
+    match read_ui_text("minimal/index.html") {
+        Ok(html) => (
+            StatusCode::OK,
+            [("content-type", "text/html; charset=utf-8")],
+            Html(html),
+        ),
         Err(e) => {
             error!("Failed to load minimal UI: {e}");
             (
@@ -321,85 +296,87 @@ pub async fn serve_minimal() -> impl IntoResponse {
     }
 }
 
-pub async fn serve_suite(bot_name: Option<String>) -> impl IntoResponse {
-    let raw_html_res = {
-        #[cfg(feature = "embed-ui")]
-        {
-            match Assets::get("suite/desktop.html") {
-                Some(f) => String::from_utf8(f.data.into_owned()).map_err(|e| e.to_string()),
-                None => {
-                    let path = get_ui_root().join("suite/desktop.html");
-                    log::warn!("Asset .suite/desktop.html. not found in embedded binary, falling back to filesystem: {:?}", path);
-                    fs::read_to_string(&path).map_err(|e| {
-                        format!(
-                            "Asset not found in binary AND failed to read {:?} (CWD: {:?}): {}",
-                            path,
-                            std::env::current_dir(),
-                            e
-                        )
-                    })
-                }
-            }
-        }
-        #[cfg(not(feature = "embed-ui"))]
-        {
-            let path = get_ui_root().join("suite/desktop.html");
-            fs::read_to_string(&path).map_err(|e| {
-                format!(
-                    "Failed to read {:?} (CWD: {:?}): {}",
-                    path,
-                    std::env::current_dir(),
-                    e
-                )
-            })
+pub async fn serve_suite(
+    state: &AppState,
+    headers: &HeaderMap,
+    route: UiRoute,
+    source: BotSource,
+) -> Response {
+    let upstream = state
+        .upstreams
+        .select(branding::CONFIG_PATH, route.bot.as_deref(), None);
+    let branding = state
+        .branding
+        .resolve(upstream.as_ref(), route.bot.as_deref())
+        .await;
+    let (csrf_token, csrf_cookie) = match &state.csrf {
+        Some(guard) => {
+            let session = state
+                .sessions
+                .as_ref()
+                .and_then(|store| store.session_id(headers));
+            let (token, cookie) = guard.issue(headers, session.as_deref());
+            (Some(token), cookie)
         }
+        None => (None, None),
     };
 
-    match raw_html_res {
+    match read_ui_text("suite/desktop.html") {
         Ok(raw_html) => {
             let mut html = raw_html;
-            let _ = &mut html; // Suppress unused_mut if no features are disabled
 
-            // Inject base tag and bot_name into the page
+            // Inject base tag, bot_name and the requested app into the page
             if let Some(head_end) = html.find("</head>") {
-                // Check if bot_name is actually an auth page (login.html, register.html, etc.)
-                // These are not actual bots, so we should use "/" as base href
-                let is_auth_page = bot_name.as_ref()
-                    .map(|n| n.ends_with(".html") || n == "login" || n == "register" || n == "forgot-password" || n == "reset-password")
-                    .unwrap_or(false);
-
                 // Set base href to include bot context if present (e.g., /edu/)
-                // But NOT for auth pages - those use root
-                let base_href = if is_auth_page {
-                    "/".to_string()
-                } else if let Some(ref name) = bot_name {
-                    format!("/{}/", name)
-                } else {
-                    "/".to_string()
+                // But NOT for host-selected bots - those are served from root
+                let base_href = match (&route.bot, source) {
+                    (Some(name), BotSource::Path) => format!("/{name}/"),
+                    _ => "/".to_string(),
                 };
-                let base_tag = format!(r#"<base href="{}">"#, base_href);
-                html.insert_str(head_end, &base_tag);
-
-                // Only inject bot_name script for actual bots, not auth pages
-                if !is_auth_page {
-                    if let Some(name) = bot_name {
-                        info!("serve_suite: Injecting bot_name '{}' into page with base href='{}'", name, base_href);
-                        let bot_script = format!(
-                            r#"<script>window.__INITIAL_BOT_NAME__ = "{}";</script>"#,
-                            &name
-                        );
-                        html.insert_str(head_end + base_tag.len(), &bot_script);
-                        info!("serve_suite: Successfully injected base tag and bot_name script");
-                    } else {
-                        info!("serve_suite: Successfully injected base tag (no bot_name)");
-                    }
+                let mut injected = format!(r#"<base href="{}">"#, base_href);
+
+                if let Some(name) = &route.bot {
+                    info!(
+                        "serve_suite: Injecting bot_name '{}' into page with base href='{}'",
+                        name, base_href
+                    );
+                    injected.push_str(&format!(
+                        "<script>window.__INITIAL_BOT_NAME__ = {};</script>",
+                        html::script_json(&serde_json::json!(name))
+                    ));
                 } else {
-                    info!("serve_suite: Auth page detected, skipping bot_name injection (base href='{}')", base_href);
+                    info!("serve_suite: Injecting base tag (no bot_name)");
+                }
+
+                if route.app.is_some() {
+                    let route_json = serde_json::to_value(&route).unwrap_or_default();
+                    injected.push_str(&format!(
+                        "<script>window.__INITIAL_ROUTE__ = {};</script>",
+                        html::script_json(&route_json)
+                    ));
                 }
+
+                html.insert_str(head_end, &injected);
             } else {
                 error!("serve_suite: Failed to find </head> tag to inject content");
             }
 
+            if let Some(branding) = &branding {
+                branding.apply(&mut html);
+            }
+
+            if let Some(token) = &csrf_token {
+                html::upsert_tag(
+                    &mut html,
+                    "meta",
+                    r#"name="csrf-token""#,
+                    &format!(
+                        r#"<meta name="csrf-token" content="{}">"#,
+                        html::escape(token)
+                    ),
+                );
+            }
+
             // Core Apps
             #[cfg(not(feature = "chat"))]
             {
@@ -550,7 +527,14 @@ pub async fn serve_suite(bot_name: Option<String>) -> impl IntoResponse {
                 html = remove_section(&html, "settings");
             }
 
-            (StatusCode::OK, [("content-type", "text/html; charset=utf-8")], Html(html))
+            let mut response = (
+                StatusCode::OK,
+                [("content-type", "text/html; charset=utf-8")],
+                Html(html),
+            )
+                .into_response();
+            csrf::attach_cookie(csrf_cookie, &mut response);
+            response
         }
         Err(e) => {
             error!("Failed to load suite UI: {e}");
@@ -559,10 +543,15 @@ pub async fn serve_suite(bot_name: Option<String>) -> impl IntoResponse {
                 [("content-type", "text/plain")],
                 Html("Failed to load suite interface".to_string()),
             )
+                .into_response()
         }
     }
 }
 
+async fn serve_suite_root(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    serve_suite(&state, &headers, UiRoute::default(), BotSource::Path).await
+}
+
 pub fn remove_section(html: &str, section: &str) -> String {
     let start_marker = format!("<!-- SECTION:{} -->", section);
     let end_marker = format!("<!-- ENDSECTION:{} -->", section);
@@ -639,26 +628,12 @@ async fn api_health(State(state): State<AppState>) -> (StatusCode, axum::Json<se
 }
 
 fn extract_app_context(headers: &axum::http::HeaderMap, path: &str) -> Option<String> {
-    if let Some(referer) = headers.get("referer") {
-        if let Ok(referer_str) = referer.to_str() {
-            if let Some(start) = referer_str.find("/apps/") {
-                let after_apps = &referer_str[start + 6..];
-                if let Some(end) = after_apps.find('/') {
-                    return Some(after_apps[..end].to_string());
-                } else if !after_apps.is_empty() {
-                    return Some(after_apps.to_string());
-                }
-            }
-        }
-    }
-
-    if let Some(after_apps) = path.strip_prefix("/apps/") {
-        if let Some(end) = after_apps.find('/') {
-            return Some(after_apps[..end].to_string());
-        }
-    }
-
-    None
+    headers
+        .get("referer")
+        .and_then(|referer| referer.to_str().ok())
+        .and_then(route::parse_apps)
+        .and_then(|route| route.app)
+        .or_else(|| route::parse_apps(path).and_then(|route| route.app))
 }
 
 async fn proxy_api(
@@ -672,67 +647,408 @@ async fn proxy_api(
         .map_or_else(String::new, |q| format!("?{q}"));
     let method = req.method().clone();
     let headers = req.headers().clone();
+    let peer = rate_limit::peer_addr(&req).map(|addr| addr.ip());
+    let client_ip = state.trusted_proxies.client_ip(peer, &headers);
+
+    let session_id = state
+        .sessions
+        .as_ref()
+        .and_then(|store| store.session_id(&headers));
+
+    let host_bot = state.hosts.bot_for_request(&headers, &original_uri);
+    let referer = referer_path(&headers);
+    let page_bot = host_bot.clone().or_else(|| {
+        referer
+            .as_deref()
+            .and_then(|referer| route::parse(referer).bot)
+    });
+
+    let body_limit = match &state.proxy_policy {
+        Some(policy) => match policy
+            .check(&state, &method, path, &headers, page_bot.as_deref())
+            .await
+        {
+            Ok(limit) => limit,
+            Err(rejection) => return rejection.respond(&headers, &method, &original_uri),
+        },
+        None => usize::MAX,
+    };
 
-    let app_context = extract_app_context(&headers, path);
-
-    let target_url = format!("{}{path}{query}", state.client.base_url());
-    debug!("Proxying {method} {path} to {target_url} (app: {app_context:?})");
+    if let Some(guard) = &state.csrf {
+        if let Err(reason) = guard.check(&method, path, &headers, session_id.as_deref()) {
+            return errors::respond(
+                &headers,
+                &method,
+                &original_uri,
+                ErrorKind::CsrfFailed,
+                reason,
+            );
+        }
+    }
 
-    let client = reqwest::Client::builder()
-        .danger_accept_invalid_certs(true)
-        .build()
-        .unwrap_or_else(|_| reqwest::Client::new());
-    let mut proxy_req = client.request(method.clone(), &target_url);
+    let app_context = extract_app_context(&headers, path);
+    debug!("Proxying {method} {path} (app: {app_context:?})");
 
+    let mut upstream_headers = HeaderMap::new();
     for (name, value) in &headers {
-        if name != "host" {
-            if let Ok(v) = value.to_str() {
-                proxy_req = proxy_req.header(name.as_str(), v);
+        // Session cookies stay in botui; the rest of the Cookie header is re-added below.
+        // Content-Length is recomputed from the body actually sent.
+        let skip = name == header::HOST
+            || name == header::CONTENT_LENGTH
+            || forwarded::is_hop_by_hop(name, &headers)
+            || forwarded::is_forwarding_header(name)
+            || (name == header::COOKIE && state.sessions.is_some())
+            || (name == header::AUTHORIZATION && session_id.is_some())
+            || name == hosts::BOT_HEADER
+            || auth_guard::is_challenge_header(name)
+            || state
+                .oidc
+                .as_ref()
+                .is_some_and(|oidc| oidc.is_identity_header(name));
+        if !skip {
+            upstream_headers.append(name.clone(), value.clone());
+        }
+    }
+    state
+        .trusted_proxies
+        .forward(peer, &headers, &mut upstream_headers);
+
+    if let Some(store) = &state.sessions {
+        if let Some(cookie) =
+            cookies::without(&headers, &[store.cookie_name(), session::ACTIVE_COOKIE])
+                .and_then(|cookie| HeaderValue::from_str(&cookie).ok())
+        {
+            upstream_headers.insert(header::COOKIE, cookie);
+        }
+        // A live session outranks any token the page still holds
+        if session_id.is_some() {
+            if let Some((name, value)) =
+                session_credentials(&state, &headers, page_bot.as_deref()).await
+            {
+                upstream_headers.insert(name, value);
             }
         }
     }
 
-    if let Some(app) = app_context {
-        proxy_req = proxy_req.header("X-App-Context", app);
+    if let Some(app) = app_context.and_then(|app| HeaderValue::from_str(&app).ok()) {
+        upstream_headers.insert("X-App-Context", app);
+    }
+
+    if let Some(bot_name) = host_bot
+        .as_deref()
+        .and_then(|bot| HeaderValue::from_str(bot).ok())
+    {
+        upstream_headers.insert(hosts::BOT_HEADER, bot_name);
     }
 
-    let body_bytes = match axum::body::to_bytes(req.into_body(), usize::MAX).await {
+    let body_bytes = match axum::body::to_bytes(req.into_body(), body_limit).await {
         Ok(bytes) => bytes,
+        Err(_) if body_limit < usize::MAX => {
+            return proxy_policy::Rejection::TooLarge(body_limit).respond(
+                &headers,
+                &method,
+                &original_uri,
+            );
+        }
         Err(e) => {
-            error!("Failed to read request body: {e}");
-            return build_error_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Failed to read request body",
+            return errors::respond(
+                &headers,
+                &method,
+                &original_uri,
+                ErrorKind::BadRequest,
+                &format!("failed to read request body: {e}"),
+            );
+        }
+    };
+
+    let auth_attempt = match &state.auth_guard {
+        Some(guard) if method == axum::http::Method::POST && auth_guard::is_guarded(path) => {
+            let keys = auth_guard::attempt_keys(client_ip, &body_bytes);
+            let verdict = guard.check(&keys, &headers);
+            if let Some(response) = auth_guard::rejection(&verdict) {
+                warn!("Held back auth attempt on {path}: {verdict:?}");
+                return response;
+            }
+            if let auth_guard::Verdict::Allow { delay } = verdict {
+                if !delay.is_zero() {
+                    tokio::time::sleep(delay).await;
+                }
+            }
+            Some((guard, keys))
+        }
+        _ => None,
+    };
+
+    let stored_refresh = match (&state.sessions, &session_id) {
+        (Some(store), Some(id)) if path == session::REFRESH_PATH => store.refresh_token(id),
+        _ => None,
+    };
+
+    let body = match stored_refresh {
+        Some(refresh_token) => {
+            upstream_headers.insert(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("application/json"),
             );
+            Bytes::from(serde_json::json!({ "refresh_token": refresh_token }).to_string())
         }
+        None => body_bytes,
     };
 
-    if !body_bytes.is_empty() {
-        proxy_req = proxy_req.body(body_bytes.to_vec());
+    let cache_ttl = if method == Method::GET {
+        state.response_cache.ttl_for(path)
+    } else {
+        None
+    };
+    let (upstream_url, mut response) = if let Some(ttl) = cache_ttl {
+        let key = response_cache::key(
+            &format!("{path}{query}"),
+            &upstream_headers,
+            session_id.as_deref(),
+        );
+        // Validators of the caller's own cache are answered by botui
+        upstream_headers.remove(header::IF_NONE_MATCH);
+        upstream_headers.remove(header::IF_MODIFIED_SINCE);
+        let fetch = {
+            let state = state.clone();
+            let target = (path.to_string(), query.clone(), page_bot.clone());
+            move |validators: HeaderMap| {
+                let state = state.clone();
+                let (path, query, page_bot) = target.clone();
+                let mut upstream_headers = upstream_headers.clone();
+                upstream_headers.extend(validators);
+                async move {
+                    let (upstream, resp) = send_upstream(
+                        &state,
+                        &Method::GET,
+                        &path,
+                        &query,
+                        &upstream_headers,
+                        &Bytes::new(),
+                        page_bot.as_deref(),
+                    )
+                    .await?;
+                    let status = resp.status();
+                    let headers = resp.headers().clone();
+                    let body = resp
+                        .bytes()
+                        .await
+                        .map_err(|e| response_cache::read_failure(&e))?;
+                    Ok(response_cache::Upstreamed {
+                        status,
+                        headers,
+                        body,
+                        upstream: upstream.base_url().to_string(),
+                    })
+                }
+            }
+        };
+        match state
+            .response_cache
+            .get_or_fetch(key, ttl, &headers, fetch)
+            .await
+        {
+            Ok((stored, cache_status)) => (
+                stored.upstream().to_string(),
+                stored.to_response(&headers, cache_status),
+            ),
+            Err(failure) => return failure.respond(&headers, &method, &original_uri),
+        }
+    } else {
+        match send_upstream(
+            &state,
+            &method,
+            path,
+            &query,
+            &upstream_headers,
+            &body,
+            page_bot.as_deref(),
+        )
+        .await
+        {
+            Ok((upstream, resp)) => (
+                upstream.base_url().to_string(),
+                build_proxy_response(resp, &headers, &method, &original_uri).await,
+            ),
+            Err(failure) => {
+                if let Some((guard, keys)) = &auth_attempt {
+                    guard.release(keys);
+                }
+                return failure.respond(&headers, &method, &original_uri);
+            }
+        }
+    };
+    rewrite::Rewriter::new(
+        &upstream_url,
+        state.trusted_proxies.origin(peer, &headers),
+        referer
+            .as_deref()
+            .filter(|_| host_bot.is_none())
+            .and_then(route::bot_prefix),
+    )
+    .apply(response.headers_mut());
+
+    if let Some((guard, keys)) = &auth_attempt {
+        guard.record(keys, response.status());
     }
 
-    match proxy_req.send().await {
-        Ok(resp) => build_proxy_response(resp).await,
-        Err(e) => {
-            error!("Proxy request failed: {e}");
-            build_error_response(StatusCode::BAD_GATEWAY, &format!("Proxy error: {e}"))
+    if let Some(store) = &state.sessions {
+        if session::is_login_path(path) || path == session::REFRESH_PATH {
+            response = store.capture(path, response, session_id.as_deref()).await;
+        } else if path == session::LOGOUT_PATH {
+            store.end(session_id.as_deref(), &mut response);
         }
     }
+
+    if let Some(guard) = &state.csrf {
+        // Sign-in and sign-out change the session the token is bound to
+        let session = match &state.sessions {
+            Some(store) => cookies::set_by(response.headers(), store.cookie_name())
+                .or(session_id)
+                .filter(|id| !id.is_empty()),
+            None => None,
+        };
+        csrf::attach_cookie(guard.issue(&headers, session.as_deref()).1, &mut response);
+    }
+
+    response.extensions_mut().insert(security_headers::Upstream);
+    response
 }
 
-fn build_error_response(status: StatusCode, message: &str) -> Response<Body> {
-    Response::builder()
-        .status(status)
-        .body(Body::from(message.to_string()))
-        .unwrap_or_else(|_| {
-            Response::builder()
-                .status(StatusCode::INTERNAL_SERVER_ERROR)
-                .body(Body::from("Failed to build error response"))
-                .unwrap_or_default()
-        })
+fn referer_path(headers: &HeaderMap) -> Option<String> {
+    let referer = headers.get(header::REFERER)?.to_str().ok()?;
+    let uri = referer.parse::<axum::http::Uri>().ok()?;
+    Some(uri.path().to_string())
 }
 
-async fn build_proxy_response(resp: reqwest::Response) -> Response<Body> {
+async fn session_credentials(
+    state: &AppState,
+    headers: &HeaderMap,
+    bot: Option<&str>,
+) -> Option<(HeaderName, HeaderValue)> {
+    let store = state.sessions.as_ref()?;
+    let id = store.session_id(headers)?;
+    if let Some(identity) = store.identity(&id) {
+        return state.oidc.as_ref()?.identity_header(&identity);
+    }
+    let upstream = state.upstreams.select(session::REFRESH_PATH, bot, None)?;
+    let token = store.access_token(&id, &upstream).await?;
+    let value = HeaderValue::from_str(&format!("Bearer {token}")).ok()?;
+    Some((header::AUTHORIZATION, value))
+}
+
+async fn connect_backend_ws(
+    url: &str,
+    credentials: Option<(HeaderName, HeaderValue)>,
+) -> Result<
+    tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>,
+    tungstenite::Error,
+> {
+    let tls_connector = native_tls::TlsConnector::builder()
+        .danger_accept_invalid_certs(true)
+        .danger_accept_invalid_hostnames(true)
+        .build()
+        .map_err(|e| tungstenite::Error::Tls(e.into()))?;
+    let connector = tokio_tungstenite::Connector::NativeTls(tls_connector);
+
+    let mut request = url.into_client_request()?;
+    if let Some((name, value)) = credentials {
+        request.headers_mut().insert(name, value);
+    }
+
+    connect_async_tls_with_config(request, None, false, Some(connector))
+        .await
+        .map(|(socket, _)| socket)
+}
+
+async fn send_upstream(
+    state: &AppState,
+    method: &Method,
+    path: &str,
+    query: &str,
+    upstream_headers: &HeaderMap,
+    body: &Bytes,
+    bot: Option<&str>,
+) -> Result<(upstream::Upstream, reqwest::Response), errors::Failure> {
+    let resilience = &state.resilience;
+    let timeout = resilience.timeout_for(path);
+    let attempts = resilience.attempts(method);
+
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        let Some(upstream) = state.upstreams.select(path, bot, None) else {
+            return Err(errors::Failure::new(
+                ErrorKind::UpstreamUnreachable,
+                "no upstream available",
+            ));
+        };
+        if let Err(retry_after) = resilience.admit(upstream.base_url()) {
+            // Another replica of the pool may still be reachable
+            if attempt < attempts {
+                continue;
+            }
+            return Err(errors::Failure {
+                retry_after: Some(retry_after),
+                ..errors::Failure::new(
+                    ErrorKind::Unavailable,
+                    format!("circuit open for {}", upstream.base_url()),
+                )
+            });
+        }
+
+        let target_url = format!("{}{path}{query}", upstream.base_url());
+        debug!(
+            "Attempt {attempt}/{attempts} to {target_url} (pool: {})",
+            upstream.pool()
+        );
+        let mut proxy_req = upstream
+            .client()
+            .request(method.clone(), &target_url)
+            .headers(upstream_headers.clone())
+            .timeout(timeout);
+        if !body.is_empty() {
+            proxy_req = proxy_req.body(body.clone());
+        }
+
+        let result = proxy_req.send().await;
+        let (failed, retryable) = match &result {
+            Ok(resp) => {
+                let failed = resilience::is_upstream_failure(resp.status());
+                (failed, failed)
+            }
+            Err(e) => (true, resilience::is_retryable(e)),
+        };
+        resilience.record(upstream.base_url(), !failed);
+        if retryable && attempt < attempts {
+            let delay = resilience.backoff(attempt);
+            warn!("Retrying {method} {path} in {delay:?} after attempt {attempt} failed");
+            tokio::time::sleep(delay).await;
+            continue;
+        }
+        return match result {
+            Ok(resp) => Ok((upstream, resp)),
+            Err(e) => {
+                let kind = if e.is_timeout() {
+                    ErrorKind::UpstreamTimeout
+                } else {
+                    ErrorKind::UpstreamUnreachable
+                };
+                Err(errors::Failure::new(
+                    kind,
+                    format!("proxy request to {} failed: {e}", upstream.base_url()),
+                ))
+            }
+        };
+    }
+}
+
+async fn build_proxy_response(
+    resp: reqwest::Response,
+    request_headers: &HeaderMap,
+    method: &Method,
+    uri: &Uri,
+) -> Response<Body> {
     let status = resp.status();
     let headers = resp.headers().clone();
 
@@ -740,24 +1056,25 @@ async fn build_proxy_response(resp: reqwest::Response) -> Response<Body> {
         Ok(body) => {
             let mut response = Response::builder().status(status);
 
+            // The body is re-framed here, so its original length and
+            // transfer coding no longer apply.
             for (name, value) in &headers {
-                response = response.header(name, value);
+                if name != header::CONTENT_LENGTH && !forwarded::is_hop_by_hop(name, &headers) {
+                    response = response.header(name, value);
+                }
             }
 
-            response.body(Body::from(body)).unwrap_or_else(|_| {
-                build_error_response(
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "Failed to build response",
+            response.body(Body::from(body)).unwrap_or_else(|e| {
+                errors::respond(
+                    request_headers,
+                    method,
+                    uri,
+                    ErrorKind::Internal,
+                    &format!("failed to build response: {e}"),
                 )
             })
         }
-        Err(e) => {
-            error!("Failed to read response body: {e}");
-            build_error_response(
-                StatusCode::BAD_GATEWAY,
-                &format!("Failed to read response: {e}"),
-            )
-        }
+        Err(e) => response_cache::read_failure(&e).respond(request_headers, method, uri),
     }
 }
 
@@ -765,9 +1082,24 @@ fn create_api_router() -> Router<AppState> {
     Router::new()
         .route("/health", get(api_health))
         .route("/client-error", axum::routing::post(handle_client_error))
+        .route("/csp-report", axum::routing::post(security_headers::report))
+        .route("/auth/challenge", get(auth_challenge))
+        .route(
+            maintenance::ADMIN_PATH,
+            get(maintenance::status).post(maintenance::update),
+        )
+        .route("/themes", get(themes::list))
+        .route("/themes/build", axum::routing::post(theme_builder::preview))
         .fallback(any(proxy_api))
 }
 
+async fn auth_challenge(State(state): State<AppState>) -> Response {
+    match &state.auth_guard {
+        Some(guard) => guard.issue_challenge(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
 #[derive(Debug, Deserialize)]
 struct WsQuery {
     #[allow(dead_code)]
@@ -815,45 +1147,63 @@ async fn ws_proxy(
     ws: WebSocketUpgrade,
     State(state): State<AppState>,
     OriginalUri(uri): OriginalUri,
+    headers: HeaderMap,
+    Extension(client): Extension<rate_limit::ClientKey>,
     Query(params): Query<WsQuery>,
-) -> impl IntoResponse {
-    // Extract bot_name from URL path (e.g., /edu, /chat/edu)
-    let path_parts: Vec<&str> = uri.path().split('/').collect();
+) -> Response {
+    // Explicit query parameter, then host mapping, then the /ws/{bot} path
     let bot_name = params
         .bot_name
         .filter(|name| name != "ws" && !name.is_empty())
-        .or_else(|| {
-            // Try to extract from path like /edu or /app/edu
-            path_parts
-                .iter()
-                .find(|part| {
-                    !part.is_empty() && **part != "chat" && **part != "app" && **part != "ws"
-                })
-                .map(|s| s.to_string())
-        })
+        .or_else(|| state.hosts.bot_for_request(&headers, &uri))
+        .or_else(|| route::parse_ws(uri.path()).bot)
         .unwrap_or_else(|| "default".to_string());
 
+    if !state.cors.allows_ws(&headers, Some(&bot_name)) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    let permit = match state.rate_limiter.acquire_ws(&client.0) {
+        Ok(permit) => permit,
+        Err(retry_after) => return rate_limit::too_many_requests(retry_after),
+    };
+
+    // The chat session lives on one replica, so keep reconnects on it
+    let affinity = if params.session_id.is_empty() {
+        &client.0
+    } else {
+        &params.session_id
+    };
+    let Some(upstream) = state
+        .upstreams
+        .select(uri.path(), Some(&bot_name), Some(affinity))
+    else {
+        return StatusCode::BAD_GATEWAY.into_response();
+    };
+
     let params_with_bot = WsQuery {
         bot_name: Some(bot_name),
         ..params
     };
-
-    ws.on_upgrade(move |socket| handle_ws_proxy(socket, state, params_with_bot))
+    let credentials =
+        session_credentials(&state, &headers, params_with_bot.bot_name.as_deref()).await;
+
+    ws.on_upgrade(move |socket| async move {
+        handle_ws_proxy(socket, upstream, params_with_bot, credentials).await;
+        drop(permit);
+    })
+    .into_response()
 }
 
 async fn handle_ws_proxy(
     client_socket: WebSocket,
-    state: AppState,
+    upstream: upstream::Upstream,
     params: WsQuery,
+    credentials: Option<(HeaderName, HeaderValue)>,
 ) {
     let bot_name = params.bot_name.unwrap_or_else(|| "default".to_string());
     let backend_url = format!(
         "{}/ws/{}?session_id={}&user_id={}",
-        state
-            .client
-            .base_url()
-            .replace("https://", "wss://")
-            .replace("http://", "ws://"),
+        upstream.ws_base_url(),
         bot_name,
         params.session_id,
         params.user_id
@@ -861,24 +1211,8 @@ async fn handle_ws_proxy(
 
     info!("Proxying WebSocket to: {backend_url}");
 
-    let Ok(tls_connector) = native_tls::TlsConnector::builder()
-        .danger_accept_invalid_certs(true)
-        .danger_accept_invalid_hostnames(true)
-        .build()
-    else {
-        error!("Failed to build TLS connector for WebSocket proxy");
-        return;
-    };
-
-    let connector = tokio_tungstenite::Connector::NativeTls(tls_connector);
-
-    let backend_result =
-        connect_async_tls_with_config(&backend_url, None, false, Some(connector)).await;
-
-    let backend_socket: tokio_tungstenite::WebSocketStream<
-        tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
-    > = match backend_result {
-        Ok((socket, _)) => socket,
+    let backend_socket = match connect_backend_ws(&backend_url, credentials).await {
+        Ok(socket) => socket,
         Err(e) => {
             error!("Failed to connect to backend WebSocket: {e}");
             return;
@@ -894,22 +1228,38 @@ async fn handle_ws_proxy(
         while let Some(msg) = client_rx.next().await {
             match msg {
                 Ok(AxumMessage::Text(text)) => {
-                    if backend_tx.send(TungsteniteMessage::Text(text)).await.is_err() {
+                    if backend_tx
+                        .send(TungsteniteMessage::Text(text))
+                        .await
+                        .is_err()
+                    {
                         break;
                     }
                 }
                 Ok(AxumMessage::Binary(data)) => {
-                    if backend_tx.send(TungsteniteMessage::Binary(data)).await.is_err() {
+                    if backend_tx
+                        .send(TungsteniteMessage::Binary(data))
+                        .await
+                        .is_err()
+                    {
                         break;
                     }
                 }
                 Ok(AxumMessage::Ping(data)) => {
-                    if backend_tx.send(TungsteniteMessage::Ping(data)).await.is_err() {
+                    if backend_tx
+                        .send(TungsteniteMessage::Ping(data))
+                        .await
+                        .is_err()
+                    {
                         break;
                     }
                 }
                 Ok(AxumMessage::Pong(data)) => {
-                    if backend_tx.send(TungsteniteMessage::Pong(data)).await.is_err() {
+                    if backend_tx
+                        .send(TungsteniteMessage::Pong(data))
+                        .await
+                        .is_err()
+                    {
                         break;
                     }
                 }
@@ -956,24 +1306,44 @@ async fn handle_ws_proxy(
 async fn ws_task_progress_proxy(
     ws: WebSocketUpgrade,
     State(state): State<AppState>,
+    OriginalUri(uri): OriginalUri,
+    headers: HeaderMap,
+    Extension(client): Extension<rate_limit::ClientKey>,
     Query(params): Query<OptionalWsQuery>,
-) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| handle_task_progress_ws_proxy(socket, state, params))
+) -> Response {
+    if !state.cors.allows_ws(
+        &headers,
+        state.hosts.bot_for_request(&headers, &uri).as_deref(),
+    ) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    let permit = match state.rate_limiter.acquire_ws(&client.0) {
+        Ok(permit) => permit,
+        Err(retry_after) => return rate_limit::too_many_requests(retry_after),
+    };
+    let bot = state.hosts.bot_for_request(&headers, &uri);
+    let affinity = params.task_id.as_deref().unwrap_or(&client.0);
+    let Some(upstream) = state
+        .upstreams
+        .select(uri.path(), bot.as_deref(), Some(affinity))
+    else {
+        return StatusCode::BAD_GATEWAY.into_response();
+    };
+    let credentials = session_credentials(&state, &headers, bot.as_deref()).await;
+    ws.on_upgrade(move |socket| async move {
+        handle_task_progress_ws_proxy(socket, upstream, params, credentials).await;
+        drop(permit);
+    })
+    .into_response()
 }
 
 async fn handle_task_progress_ws_proxy(
     client_socket: WebSocket,
-    state: AppState,
+    upstream: upstream::Upstream,
     params: OptionalWsQuery,
+    credentials: Option<(HeaderName, HeaderValue)>,
 ) {
-    let mut backend_url = format!(
-        "{}/ws/task-progress",
-        state
-            .client
-            .base_url()
-            .replace("https://", "wss://")
-            .replace("http://", "ws://"),
-    );
+    let mut backend_url = format!("{}/ws/task-progress", upstream.ws_base_url());
 
     if let Some(task_id) = &params.task_id {
         backend_url = format!("{}/{}", backend_url, task_id);
@@ -981,24 +1351,8 @@ async fn handle_task_progress_ws_proxy(
 
     info!("Proxying task-progress WebSocket to: {backend_url}");
 
-    let Ok(tls_connector) = native_tls::TlsConnector::builder()
-        .danger_accept_invalid_certs(true)
-        .danger_accept_invalid_hostnames(true)
-        .build()
-    else {
-        error!("Failed to build TLS connector for task-progress");
-        return;
-    };
-
-    let connector = tokio_tungstenite::Connector::NativeTls(tls_connector);
-
-    let backend_result =
-        connect_async_tls_with_config(&backend_url, None, false, Some(connector)).await;
-
-    let backend_socket: tokio_tungstenite::WebSocketStream<
-        tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
-    > = match backend_result {
-        Ok((socket, _)) => socket,
+    let backend_socket = match connect_backend_ws(&backend_url, credentials).await {
+        Ok(socket) => socket,
         Err(e) => {
             error!("Failed to connect to backend task-progress WebSocket: {e}");
             return;
@@ -1052,21 +1406,12 @@ async fn handle_task_progress_ws_proxy(
         {
             match msg {
                 Ok(TungsteniteMessage::Text(text)) => {
-                    // Log manifest_update messages for debugging
-                    let is_manifest = text.contains("manifest_update");
-                    if is_manifest {
-                    } else if text.contains("task_progress") {
+                    if text.contains("task_progress") {
                         debug!("[WS_PROXY] Forwarding task_progress to client");
                     }
-                    match client_tx.send(AxumMessage::Text(text)).await {
-                        Ok(()) => {
-                            if is_manifest {
-                            }
-                        }
-                        Err(e) => {
-                            error!("[WS_PROXY] Failed to send message to client: {:?}", e);
-                            break;
-                        }
+                    if let Err(e) = client_tx.send(AxumMessage::Text(text)).await {
+                        error!("[WS_PROXY] Failed to send message to client: {:?}", e);
+                        break;
                     }
                 }
                 Ok(TungsteniteMessage::Binary(data)) => {
@@ -1112,160 +1457,101 @@ fn create_ui_router() -> Router<AppState> {
     Router::new().fallback(any(proxy_api))
 }
 
-async fn serve_favicon() -> impl IntoResponse {
-    #[cfg(feature = "embed-ui")]
-    {
-        match Assets::get("suite/public/favicon.ico") {
-            Some(content) => (
-                StatusCode::OK,
-                [("content-type", "image/x-icon")],
-                content.data,
-            )
-                .into_response(),
-            None => StatusCode::NOT_FOUND.into_response(),
-        }
-    }
-    #[cfg(not(feature = "embed-ui"))]
-    {
-        let favicon_path = get_ui_root().join("suite/public/favicon.ico");
-        match tokio::fs::read(&favicon_path).await {
-            Ok(bytes) => {
-                (StatusCode::OK, [("content-type", "image/x-icon")], bytes).into_response()
-            }
-            Err(_) => StatusCode::NOT_FOUND.into_response(),
-        }
-    }
+async fn serve_favicon(headers: HeaderMap) -> impl IntoResponse {
+    serve_ui_file(&headers, "suite/public/favicon.ico")
+        .await
+        .unwrap_or_else(|| StatusCode::NOT_FOUND.into_response())
 }
 
 #[cfg(feature = "embed-ui")]
 async fn handle_embedded_asset(
     axum::extract::Path((dir, path)): axum::extract::Path<(String, String)>,
+    headers: HeaderMap,
 ) -> impl IntoResponse {
     if !SUITE_DIRS.contains(&dir.as_str()) {
         return StatusCode::NOT_FOUND.into_response();
     }
 
     let asset_path = format!("suite/{}/{}", dir, path);
-    match Assets::get(&asset_path) {
-        Some(content) => {
-            let mime = mime_guess::from_path(&asset_path).first_or_octet_stream();
-            (
-                [(axum::http::header::CONTENT_TYPE, mime.as_ref())],
-                content.data,
-            )
-                .into_response()
-        }
-        None => StatusCode::NOT_FOUND.into_response(),
-    }
+    serve_ui_file(&headers, &asset_path)
+        .await
+        .unwrap_or_else(|| StatusCode::NOT_FOUND.into_response())
 }
 
 #[cfg(feature = "embed-ui")]
 async fn handle_embedded_root_asset(
     axum::extract::Path(filename): axum::extract::Path<String>,
+    headers: HeaderMap,
 ) -> impl IntoResponse {
     if !ROOT_FILES.contains(&filename.as_str()) {
         return StatusCode::NOT_FOUND.into_response();
     }
 
     let asset_path = format!("suite/{}", filename);
-    match Assets::get(&asset_path) {
-        Some(content) => {
-            let mime = mime_guess::from_path(&asset_path).first_or_octet_stream();
-            (
-                [(axum::http::header::CONTENT_TYPE, mime.as_ref())],
-                content.data,
-            )
-                .into_response()
-        }
-        None => StatusCode::NOT_FOUND.into_response(),
-    }
+    serve_ui_file(&headers, &asset_path)
+        .await
+        .unwrap_or_else(|| StatusCode::NOT_FOUND.into_response())
 }
 
 #[cfg(feature = "embed-ui")]
-async fn handle_auth_asset(axum::extract::Path(path): axum::extract::Path<String>) -> impl IntoResponse {
+async fn handle_auth_asset(
+    axum::extract::Path(path): axum::extract::Path<String>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
     let normalized_path = path.strip_prefix('/').unwrap_or(&path);
     let asset_path = format!("suite/auth/{}", normalized_path);
-    match Assets::get(&asset_path) {
-        Some(content) => {
-            let mime = mime_guess::from_path(&asset_path).first_or_octet_stream();
-            (
-                [(axum::http::header::CONTENT_TYPE, mime.as_ref())],
-                content.data,
-            )
-                .into_response()
-        }
-        None => StatusCode::NOT_FOUND.into_response(),
-    }
+    serve_ui_file(&headers, &asset_path)
+        .await
+        .unwrap_or_else(|| StatusCode::NOT_FOUND.into_response())
 }
 
 /// Serve login page at clean /login route (hides physical path /suite/auth/login.html)
-async fn serve_login() -> impl IntoResponse {
-    #[cfg(feature = "embed-ui")]
-    {
-        let asset_path = "suite/auth/login.html";
-        match Assets::get(asset_path) {
-            Some(content) => {
-                let mime = mime_guess::from_path(asset_path).first_or_octet_stream();
-                (
-                    [(axum::http::header::CONTENT_TYPE, mime.as_ref())],
-                    content.data,
-                )
-                    .into_response()
-            }
-            None => StatusCode::NOT_FOUND.into_response(),
-        }
+async fn serve_login(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    let mut response = serve_ui_file(&headers, "suite/auth/login.html")
+        .await
+        .unwrap_or_else(|| StatusCode::NOT_FOUND.into_response());
+    if state.oidc.is_some() {
+        response = html::rewrite_response(response, |page| {
+            let mut page = page.to_string();
+            html::insert_into_head(
+                &mut page,
+                &format!(r#"<meta name="gb-oidc" content="{}">"#, oidc::START_PATH),
+            );
+            page
+        })
+        .await;
     }
-
-    #[cfg(not(feature = "embed-ui"))]
-    {
-        let login_path = get_ui_root().join("suite/auth/login.html");
-        match tokio::fs::read(&login_path).await {
-            Ok(content) => {
-                let mime = mime_guess::from_path(&login_path).first_or_octet_stream();
-                (
-                    [(axum::http::header::CONTENT_TYPE, mime.as_ref())],
-                    content,
-                )
-                    .into_response()
-            }
-            Err(_) => StatusCode::NOT_FOUND.into_response(),
-        }
+    // The login form posts before any suite page has handed out a CSRF token
+    if let Some(guard) = &state.csrf {
+        let session = state
+            .sessions
+            .as_ref()
+            .and_then(|store| store.session_id(&headers));
+        csrf::attach_cookie(guard.issue(&headers, session.as_deref()).1, &mut response);
     }
+    response
 }
 
 /// Serve logout page at clean /logout route (hides physical path /suite/auth/logout.html)
-async fn serve_logout() -> impl IntoResponse {
-    #[cfg(feature = "embed-ui")]
-    {
-        let asset_path = "suite/auth/logout.html";
-        match Assets::get(asset_path) {
-            Some(content) => {
-                let mime = mime_guess::from_path(asset_path).first_or_octet_stream();
-                (
-                    [(axum::http::header::CONTENT_TYPE, mime.as_ref())],
-                    content.data,
-                )
-                    .into_response()
-            }
-            None => StatusCode::NOT_FOUND.into_response(),
-        }
-    }
+async fn serve_logout(headers: HeaderMap) -> impl IntoResponse {
+    serve_ui_file(&headers, "suite/auth/logout.html")
+        .await
+        .unwrap_or_else(|| StatusCode::NOT_FOUND.into_response())
+}
 
-    #[cfg(not(feature = "embed-ui"))]
-    {
-        let logout_path = get_ui_root().join("suite/auth/logout.html");
-        match tokio::fs::read(&logout_path).await {
-            Ok(content) => {
-                let mime = mime_guess::from_path(&logout_path).first_or_octet_stream();
-                (
-                    [(axum::http::header::CONTENT_TYPE, mime.as_ref())],
-                    content,
-                )
-                    .into_response()
-            }
-            Err(_) => StatusCode::NOT_FOUND.into_response(),
-        }
-    }
+#[cfg(not(feature = "embed-ui"))]
+fn overlaid_dir(
+    relative: &str,
+    stock: PathBuf,
+) -> tower::util::BoxCloneService<Request<Body>, Response<ServeFileSystemResponseBody>, Infallible>
+{
+    overlay::matching_dirs(relative).into_iter().rev().fold(
+        ServeDir::new(stock).boxed_clone(),
+        |fallback, dir| {
+            info!("Overlay {:?} layered over /{}", dir, relative);
+            ServeDir::new(dir).fallback(fallback).boxed_clone()
+        },
+    )
 }
 
 fn add_static_routes(router: Router<AppState>, _suite_path: &Path) -> Router<AppState> {
@@ -1287,37 +1573,59 @@ fn add_static_routes(router: Router<AppState>, _suite_path: &Path) -> Router<App
         for dir in SUITE_DIRS {
             let path = _suite_path.join(dir);
             info!("Adding route for /suite/{} -> {:?}", dir, path);
-            r = r.nest_service(&format!("/suite/{dir}"), ServeDir::new(path.clone()));
+            r = r.nest_service(
+                &format!("/suite/{dir}"),
+                overlaid_dir(&format!("suite/{dir}"), path),
+            );
         }
 
         for file in ROOT_FILES {
-            let path = _suite_path.join(file);
+            let path = overlay::resolve(&format!("suite/{file}"))
+                .unwrap_or_else(|| _suite_path.join(file));
             r = r.nest_service(&format!("/suite/{}", file), ServeFile::new(path));
         }
         r
     }
 }
 
+fn with_cors(router: Router<AppState>, state: &AppState, mount: &'static str) -> Router<AppState> {
+    match state.cors.layer(mount, state.hosts.clone()) {
+        Some(layer) => router.layer(layer),
+        None => router,
+    }
+}
+
 pub fn configure_router() -> Router {
-    let suite_path = get_ui_root().join("suite");
+    let ui_root = get_ui_root();
+    let suite_path = ui_root.join("suite");
     let state = AppState::new();
+    state.upstreams.spawn_health_checks();
+    maintenance::spawn_signal_handler(state.maintenance.clone());
+
+    #[cfg(feature = "embed-ui")]
+    overlay::log_report(|file| Assets::get(file).is_some());
+    #[cfg(not(feature = "embed-ui"))]
+    overlay::log_report(|file| ui_root.join(file).is_file());
 
     let mut router = Router::new()
         .route("/health", get(health))
         .route("/favicon.ico", get(serve_favicon))
         .route("/login", get(serve_login))
+        .route(oidc::START_PATH, get(oidc::start))
+        .route(oidc::CALLBACK_PATH, get(oidc::callback))
         .route("/logout", get(serve_logout))
-        .nest("/api", create_api_router())
-        .nest("/ui", create_ui_router())
-        .nest("/ws", create_ws_router())
-        .nest("/apps", create_apps_router())
+        .route(maintenance::BYPASS_PATH, get(maintenance::bypass))
+        .nest("/api", with_cors(create_api_router(), &state, "/api"))
+        .nest("/ui", with_cors(create_ui_router(), &state, "/ui"))
+        .nest("/ws", with_cors(create_ws_router(), &state, "/ws"))
+        .nest("/apps", with_cors(create_apps_router(), &state, "/apps"))
         .route("/", get(index))
         .route("/minimal", get(serve_minimal))
-        .route("/suite", get(serve_suite));
+        .route("/suite", get(serve_suite_root));
 
     #[cfg(not(feature = "embed-ui"))]
     {
-        router = router.nest_service("/auth", ServeDir::new(suite_path.join("auth")));
+        router = router.nest_service("/auth", overlaid_dir("suite/auth", suite_path.join("auth")));
     }
 
     #[cfg(feature = "embed-ui")]
@@ -1327,5 +1635,35 @@ pub fn configure_router() -> Router {
 
     router = add_static_routes(router, &suite_path);
 
-    router.fallback(get(index)).with_state(state)
+    router
+        .fallback(get(index))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            access::apply,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            i18n::apply,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            themes::apply,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            integrity::apply,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            maintenance::apply,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            security_headers::apply,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            rate_limit::apply,
+        ))
+        .with_state(state)
 }
@@ -0,0 +1,908 @@
+use axum::{
+    extract::{ConnectInfo, Query, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode},
+    response::{IntoResponse, Redirect, Response},
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use log::{error, info, warn};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use super::cookies::{self, CookieOptions};
+use super::route;
+use super::upstream::Upstream;
+use crate::shared::AppState;
+
+pub const START_PATH: &str = "/login/oidc";
+pub const CALLBACK_PATH: &str = "/login/oidc/callback";
+
+const STATE_COOKIE: &str = "gb_oidc_state";
+const PENDING_TTL: Duration = Duration::from_secs(600);
+const MAX_PENDING: usize = 10_000;
+const DISCOVERY_TTL: Duration = Duration::from_secs(3600);
+const EXCHANGE_TIMEOUT: Duration = Duration::from_secs(10);
+const TOKEN_EXCHANGE_GRANT: &str = "urn:ietf:params:oauth:grant-type:token-exchange";
+const ID_TOKEN_TYPE: &str = "urn:ietf:params:oauth:token-type:id_token";
+const FORWARDED_CLAIMS: &[&str] = &[
+    "sub",
+    "iss",
+    "email",
+    "email_verified",
+    "name",
+    "preferred_username",
+    "groups",
+    "roles",
+];
+
+#[derive(Debug, Clone, Deserialize)]
+struct Discovery {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Debug)]
+struct Pending {
+    verifier: String,
+    nonce: String,
+    redirect: String,
+    created: Instant,
+}
+
+#[derive(Debug)]
+enum Forward {
+    TokenExchange(String),
+    Header(HeaderName, Option<Vec<u8>>),
+}
+
+pub struct OidcClient {
+    issuer: String,
+    client_id: String,
+    client_secret: Option<String>,
+    redirect_uri: Option<String>,
+    scopes: String,
+    forward: Forward,
+    http: reqwest::Client,
+    discovery: RwLock<Option<(Instant, Discovery)>>,
+    jwks: RwLock<JwkSet>,
+    pending: Mutex<HashMap<String, Pending>>,
+    cookie_options: CookieOptions,
+}
+
+impl std::fmt::Debug for OidcClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OidcClient")
+            .field("issuer", &self.issuer)
+            .field("client_id", &self.client_id)
+            .field("redirect_uri", &self.redirect_uri)
+            .field("scopes", &self.scopes)
+            .field("forward", &self.forward)
+            .finish_non_exhaustive()
+    }
+}
+
+fn non_empty_env(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|v| !v.is_empty())
+}
+
+fn random_token() -> String {
+    URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>())
+}
+
+fn pkce_challenge(verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()))
+}
+
+fn safe_redirect(target: Option<&str>) -> String {
+    target
+        .filter(|t| t.starts_with('/') && !t.starts_with("//") && !t.starts_with("/\\"))
+        .unwrap_or("/")
+        .to_string()
+}
+
+impl OidcClient {
+    #[must_use]
+    pub fn from_env() -> Option<Self> {
+        let issuer = non_empty_env("BOTUI_OIDC_ISSUER")?;
+        let Some(client_id) = non_empty_env("BOTUI_OIDC_CLIENT_ID") else {
+            error!("BOTUI_OIDC_ISSUER is set without BOTUI_OIDC_CLIENT_ID; OIDC disabled");
+            return None;
+        };
+        if non_empty_env("BOTUI_SESSION_KEY").is_none() {
+            error!("OIDC sign-in needs BFF sessions (BOTUI_SESSION_KEY); OIDC disabled");
+            return None;
+        }
+
+        let forward = match non_empty_env("BOTUI_OIDC_FORWARD").as_deref() {
+            Some("header") => {
+                let name = non_empty_env("BOTUI_OIDC_IDENTITY_HEADER")
+                    .unwrap_or_else(|| "x-gb-identity".to_string());
+                let Ok(name) = HeaderName::try_from(name.as_str()) else {
+                    error!("Invalid BOTUI_OIDC_IDENTITY_HEADER {name}; OIDC disabled");
+                    return None;
+                };
+                let key = non_empty_env("BOTUI_OIDC_IDENTITY_SECRET").map(String::into_bytes);
+                if key.is_none() {
+                    warn!("OIDC identity header is unsigned; set BOTUI_OIDC_IDENTITY_SECRET");
+                }
+                Forward::Header(name, key)
+            }
+            _ => Forward::TokenExchange(
+                non_empty_env("BOTUI_OIDC_EXCHANGE_PATH")
+                    .unwrap_or_else(|| "/api/auth/oidc/exchange".to_string()),
+            ),
+        };
+
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .danger_accept_invalid_certs(
+                std::env::var("BOTUI_OIDC_INSECURE_TLS").is_ok_and(|v| v == "true"),
+            )
+            .build()
+            .unwrap_or_else(|_| reqwest::Client::new());
+        let secure = std::env::var("BOTUI_COOKIE_SECURE").map_or(true, |v| v != "false");
+
+        info!("OIDC sign-in enabled (issuer {issuer}, client {client_id}, {forward:?})");
+
+        Some(Self {
+            issuer: issuer.trim_end_matches('/').to_string(),
+            client_id,
+            client_secret: non_empty_env("BOTUI_OIDC_CLIENT_SECRET"),
+            redirect_uri: non_empty_env("BOTUI_OIDC_REDIRECT_URI"),
+            scopes: non_empty_env("BOTUI_OIDC_SCOPES")
+                .unwrap_or_else(|| "openid profile email".to_string()),
+            forward,
+            http,
+            discovery: RwLock::new(None),
+            jwks: RwLock::new(JwkSet { keys: Vec::new() }),
+            pending: Mutex::new(HashMap::new()),
+            cookie_options: CookieOptions {
+                http_only: true,
+                secure,
+                same_site: "Lax",
+            },
+        })
+    }
+
+    async fn discovery(&self) -> Result<Discovery, String> {
+        if let Ok(cached) = self.discovery.read() {
+            if let Some((at, discovery)) = cached.as_ref() {
+                if at.elapsed() < DISCOVERY_TTL {
+                    return Ok(discovery.clone());
+                }
+            }
+        }
+
+        let url = format!("{}/.well-known/openid-configuration", self.issuer);
+        let discovery: Discovery = self
+            .http
+            .get(&url)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(|e| format!("discovery request failed: {e}"))?
+            .json()
+            .await
+            .map_err(|e| format!("invalid discovery document: {e}"))?;
+        if discovery.issuer.trim_end_matches('/') != self.issuer {
+            return Err(format!(
+                "discovery issuer {} does not match {}",
+                discovery.issuer, self.issuer
+            ));
+        }
+        if let Ok(mut cached) = self.discovery.write() {
+            *cached = Some((Instant::now(), discovery.clone()));
+        }
+        Ok(discovery)
+    }
+
+    async fn refresh_jwks(&self, jwks_uri: &str) -> Result<(), String> {
+        let set: JwkSet = self
+            .http
+            .get(jwks_uri)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(|e| format!("JWKS request failed: {e}"))?
+            .json()
+            .await
+            .map_err(|e| format!("invalid JWKS: {e}"))?;
+        if let Ok(mut jwks) = self.jwks.write() {
+            *jwks = set;
+        }
+        Ok(())
+    }
+
+    fn cached_key(&self, kid: Option<&str>) -> Option<DecodingKey> {
+        let jwks = self.jwks.read().ok()?;
+        let jwk = match kid {
+            Some(kid) => jwks.find(kid),
+            None if jwks.keys.len() == 1 => jwks.keys.first(),
+            None => None,
+        }?;
+        DecodingKey::from_jwk(jwk).ok()
+    }
+
+    async fn decoding_key(
+        &self,
+        alg: Algorithm,
+        kid: Option<&str>,
+        discovery: &Discovery,
+    ) -> Result<DecodingKey, String> {
+        if matches!(alg, Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512) {
+            return self
+                .client_secret
+                .as_ref()
+                .map(|secret| DecodingKey::from_secret(secret.as_bytes()))
+                .ok_or_else(|| "HMAC-signed ID token without a client secret".to_string());
+        }
+        if let Some(key) = self.cached_key(kid) {
+            return Ok(key);
+        }
+        // Unknown key id: the IdP may have rotated its keys
+        self.refresh_jwks(&discovery.jwks_uri).await?;
+        self.cached_key(kid)
+            .ok_or_else(|| format!("no IdP signing key for kid {kid:?}"))
+    }
+
+    async fn verify_id_token(
+        &self,
+        token: &str,
+        nonce: &str,
+        discovery: &Discovery,
+    ) -> Result<serde_json::Value, String> {
+        let header = decode_header(token).map_err(|e| format!("malformed ID token: {e}"))?;
+        let key = self
+            .decoding_key(header.alg, header.kid.as_deref(), discovery)
+            .await?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&discovery.issuer]);
+        validation.set_audience(&[&self.client_id]);
+        let claims = decode::<serde_json::Value>(token, &key, &validation)
+            .map_err(|e| format!("rejected ID token: {e}"))?
+            .claims;
+
+        if claims.get("nonce").and_then(|v| v.as_str()) != Some(nonce) {
+            return Err("ID token nonce mismatch".to_string());
+        }
+        Ok(claims)
+    }
+
+    fn redirect_uri(&self, origin: Option<String>) -> String {
+        if let Some(uri) = &self.redirect_uri {
+            return uri.clone();
+        }
+        let origin = origin.unwrap_or_else(|| "http://localhost".to_string());
+        format!("{origin}{CALLBACK_PATH}")
+    }
+
+    fn remember(&self, state: &str, pending: Pending) {
+        if let Ok(mut map) = self.pending.lock() {
+            map.retain(|_, p| p.created.elapsed() < PENDING_TTL);
+            if map.len() >= MAX_PENDING {
+                warn!("Too many pending OIDC sign-ins; dropping the oldest");
+                if let Some(oldest) = map
+                    .iter()
+                    .min_by_key(|(_, p)| p.created)
+                    .map(|(k, _)| k.clone())
+                {
+                    map.remove(&oldest);
+                }
+            }
+            map.insert(state.to_string(), pending);
+        }
+    }
+
+    fn take(&self, state: &str) -> Option<Pending> {
+        self.pending
+            .lock()
+            .ok()?
+            .remove(state)
+            .filter(|p| p.created.elapsed() < PENDING_TTL)
+    }
+
+    async fn redeem_code(
+        &self,
+        code: &str,
+        verifier: &str,
+        redirect_uri: &str,
+        discovery: &Discovery,
+    ) -> Result<serde_json::Value, String> {
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", redirect_uri),
+            ("code_verifier", verifier),
+        ];
+        let mut request = self.http.post(&discovery.token_endpoint);
+        match &self.client_secret {
+            Some(secret) => request = request.basic_auth(&self.client_id, Some(secret)),
+            None => form.push(("client_id", &self.client_id)),
+        }
+        let body = form
+            .iter()
+            .map(|(k, v)| format!("{k}={}", urlencoding::encode(v)))
+            .collect::<Vec<_>>()
+            .join("&");
+
+        let response = request
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .header(header::ACCEPT, "application/json")
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| format!("token request failed: {e}"))?;
+        let status = response.status();
+        let value: serde_json::Value = response
+            .json()
+            .await
+            .map_err(|e| format!("invalid token response: {e}"))?;
+        if !status.is_success() {
+            return Err(format!("token endpoint returned {status}: {value}"));
+        }
+        Ok(value)
+    }
+
+    async fn exchange(
+        &self,
+        upstream: &Upstream,
+        path: &str,
+        id_token: &str,
+    ) -> Result<serde_json::Value, String> {
+        let response = upstream
+            .client()
+            .post(format!("{}{path}", upstream.base_url()))
+            .timeout(EXCHANGE_TIMEOUT)
+            .json(&serde_json::json!({
+                "grant_type": TOKEN_EXCHANGE_GRANT,
+                "subject_token": id_token,
+                "subject_token_type": ID_TOKEN_TYPE,
+                "issuer": self.issuer,
+                "client_id": self.client_id,
+            }))
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(|e| format!("token exchange with botserver failed: {e}"))?;
+        response
+            .json()
+            .await
+            .map_err(|e| format!("invalid token exchange response: {e}"))
+    }
+
+    pub fn is_identity_header(&self, name: &HeaderName) -> bool {
+        matches!(&self.forward, Forward::Header(header, _) if header == name)
+    }
+
+    pub fn identity_header(
+        &self,
+        identity: &serde_json::Value,
+    ) -> Option<(HeaderName, HeaderValue)> {
+        let Forward::Header(name, key) = &self.forward else {
+            return None;
+        };
+        let payload = URL_SAFE_NO_PAD.encode(identity.to_string());
+        let value = match key {
+            Some(key) => {
+                let mut mac = Hmac::<Sha256>::new_from_slice(key).ok()?;
+                mac.update(payload.as_bytes());
+                format!(
+                    "{payload}.{}",
+                    URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes())
+                )
+            }
+            None => payload,
+        };
+        Some((name.clone(), HeaderValue::from_str(&value).ok()?))
+    }
+
+    fn state_cookie(&self, value: &str, max_age: u64) -> Option<HeaderValue> {
+        HeaderValue::from_str(&cookies::set(
+            STATE_COOKIE,
+            value,
+            max_age,
+            self.cookie_options,
+        ))
+        .ok()
+    }
+}
+
+fn failed(reason: &str) -> Response {
+    error!("OIDC sign-in failed: {reason}");
+    Redirect::to("/login?error=sso").into_response()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StartParams {
+    redirect: Option<String>,
+}
+
+pub async fn start(
+    State(state): State<AppState>,
+    connect: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    Query(params): Query<StartParams>,
+) -> Response {
+    let Some(oidc) = state.oidc.as_ref() else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let discovery = match oidc.discovery().await {
+        Ok(discovery) => discovery,
+        Err(e) => return failed(&e),
+    };
+
+    let flow_state = random_token();
+    let nonce = random_token();
+    let verifier = random_token();
+    let challenge = pkce_challenge(&verifier);
+    let peer = connect.map(|ConnectInfo(addr)| addr.ip());
+    let redirect_uri = oidc.redirect_uri(state.trusted_proxies.origin(peer, &headers));
+
+    let query = [
+        ("response_type", "code"),
+        ("client_id", oidc.client_id.as_str()),
+        ("redirect_uri", redirect_uri.as_str()),
+        ("scope", oidc.scopes.as_str()),
+        ("state", flow_state.as_str()),
+        ("nonce", nonce.as_str()),
+        ("code_challenge", challenge.as_str()),
+        ("code_challenge_method", "S256"),
+    ]
+    .iter()
+    .map(|(k, v)| format!("{k}={}", urlencoding::encode(v)))
+    .collect::<Vec<_>>()
+    .join("&");
+    let separator = if discovery.authorization_endpoint.contains('?') {
+        '&'
+    } else {
+        '?'
+    };
+
+    oidc.remember(
+        &flow_state,
+        Pending {
+            verifier,
+            nonce,
+            redirect: safe_redirect(params.redirect.as_deref()),
+            created: Instant::now(),
+        },
+    );
+
+    let mut response = Redirect::to(&format!(
+        "{}{separator}{query}",
+        discovery.authorization_endpoint
+    ))
+    .into_response();
+    if let Some(cookie) = oidc.state_cookie(&flow_state, PENDING_TTL.as_secs()) {
+        response.headers_mut().append(header::SET_COOKIE, cookie);
+    }
+    response
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CallbackParams {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+    error_description: Option<String>,
+}
+
+pub async fn callback(
+    State(state): State<AppState>,
+    connect: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    Query(params): Query<CallbackParams>,
+) -> Response {
+    let (Some(oidc), Some(store)) = (state.oidc.as_ref(), state.sessions.as_ref()) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    if let Some(error) = &params.error {
+        return failed(&format!(
+            "IdP returned {error}: {}",
+            params.error_description.as_deref().unwrap_or_default()
+        ));
+    }
+    let (Some(code), Some(flow_state)) = (&params.code, &params.state) else {
+        return failed("callback without code or state");
+    };
+    if cookies::get(&headers, STATE_COOKIE).as_deref() != Some(flow_state.as_str()) {
+        return failed("state does not match this browser");
+    }
+    let Some(pending) = oidc.take(flow_state) else {
+        return failed("unknown or expired state");
+    };
+
+    let peer = connect.map(|ConnectInfo(addr)| addr.ip());
+    let redirect_uri = oidc.redirect_uri(state.trusted_proxies.origin(peer, &headers));
+    let result = async {
+        let discovery = oidc.discovery().await?;
+        let grant = oidc
+            .redeem_code(code, &pending.verifier, &redirect_uri, &discovery)
+            .await?;
+        let id_token = grant
+            .get("id_token")
+            .and_then(|v| v.as_str())
+            .ok_or("token response without id_token")?;
+        let claims = oidc
+            .verify_id_token(id_token, &pending.nonce, &discovery)
+            .await?;
+        Ok::<_, String>((id_token.to_string(), claims))
+    }
+    .await;
+    let (id_token, claims) = match result {
+        Ok(verified) => verified,
+        Err(e) => return failed(&e),
+    };
+
+    let mut response = Redirect::to(&pending.redirect).into_response();
+    let previous = store.session_id(&headers);
+    let started = match &oidc.forward {
+        Forward::TokenExchange(path) => {
+            let bot = route::parse(&pending.redirect).bot;
+            let Some(upstream) = state.upstreams.select(path, bot.as_deref(), None) else {
+                return failed("no upstream available for the token exchange");
+            };
+            match oidc.exchange(&upstream, path, &id_token).await {
+                Ok(grant) => store.start(
+                    previous.as_deref(),
+                    Some(&grant),
+                    None,
+                    response.headers_mut(),
+                ),
+                Err(e) => return failed(&e),
+            }
+        }
+        Forward::Header(..) => {
+            let identity: serde_json::Map<String, serde_json::Value> = FORWARDED_CLAIMS
+                .iter()
+                .filter_map(|claim| Some(((*claim).to_string(), claims.get(*claim)?.clone())))
+                .collect();
+            store.start(
+                previous.as_deref(),
+                None,
+                Some(identity.into()),
+                response.headers_mut(),
+            )
+        }
+    };
+    if !started {
+        return failed("botserver did not return session tokens");
+    }
+
+    if let Some(cookie) = oidc.state_cookie("", 0) {
+        response.headers_mut().append(header::SET_COOKIE, cookie);
+    }
+    info!(
+        "OIDC sign-in for {}",
+        claims.get("sub").and_then(|v| v.as_str()).unwrap_or("?")
+    );
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{extract::Form, routing::get, routing::post, Json, Router};
+    use base64::engine::general_purpose::STANDARD;
+    use jsonwebtoken::{encode, EncodingKey};
+    use std::sync::Arc;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    const ED25519_PKCS8: &str = "MC4CAQAwBQYDK2VwBCIEIJ1hsZ3v/VpguoRK9JLsLMREScVpezJpGXA7rAMcrn9g";
+    const ED25519_X: &str = "11qYAYKxCrfVS_7TyWQHOg7hcvPapiMlrwIaaPcHURo";
+
+    struct Idp {
+        issuer: String,
+        kids: RwLock<Vec<String>>,
+        challenge: RwLock<String>,
+        id_token: RwLock<String>,
+    }
+
+    async fn idp() -> Option<Arc<Idp>> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.ok()?;
+        let idp = Arc::new(Idp {
+            issuer: format!("http://{}", listener.local_addr().ok()?),
+            kids: RwLock::new(vec!["k1".to_string()]),
+            challenge: RwLock::new(String::new()),
+            id_token: RwLock::new(String::new()),
+        });
+        let (discovery, jwks, token) = (idp.clone(), idp.clone(), idp.clone());
+        let app = Router::new()
+            .route(
+                "/.well-known/openid-configuration",
+                get(move || async move {
+                    Json(serde_json::json!({
+                        "issuer": discovery.issuer,
+                        "authorization_endpoint": format!("{}/authorize", discovery.issuer),
+                        "token_endpoint": format!("{}/token", discovery.issuer),
+                        "jwks_uri": format!("{}/jwks", discovery.issuer),
+                    }))
+                }),
+            )
+            .route(
+                "/jwks",
+                get(move || async move {
+                    let kids = jwks.kids.read().map(|k| k.clone()).unwrap_or_default();
+                    let keys: Vec<_> = kids
+                        .iter()
+                        .map(|kid| {
+                            serde_json::json!({
+                                "kty": "OKP", "crv": "Ed25519", "alg": "EdDSA",
+                                "kid": kid, "x": ED25519_X,
+                            })
+                        })
+                        .collect();
+                    Json(serde_json::json!({ "keys": keys }))
+                }),
+            )
+            .route(
+                "/token",
+                post(
+                    move |Form(form): Form<HashMap<String, String>>| async move {
+                        let challenge = token
+                            .challenge
+                            .read()
+                            .map(|c| c.clone())
+                            .unwrap_or_default();
+                        let verified = form.get("grant_type").map(String::as_str)
+                            == Some("authorization_code")
+                            && form.get("code").map(String::as_str) == Some("code-1")
+                            && form
+                                .get("code_verifier")
+                                .is_some_and(|v| pkce_challenge(v) == challenge);
+                        if !verified {
+                            return (
+                                StatusCode::BAD_REQUEST,
+                                Json(serde_json::json!({ "error": "invalid_grant" })),
+                            );
+                        }
+                        let id_token = token.id_token.read().map(|t| t.clone()).unwrap_or_default();
+                        (
+                            StatusCode::OK,
+                            Json(serde_json::json!({ "id_token": id_token })),
+                        )
+                    },
+                ),
+            );
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        Some(idp)
+    }
+
+    fn client(issuer: &str, forward: Forward) -> OidcClient {
+        OidcClient {
+            issuer: issuer.to_string(),
+            client_id: "botui".to_string(),
+            client_secret: None,
+            redirect_uri: None,
+            scopes: "openid".to_string(),
+            forward,
+            http: reqwest::Client::new(),
+            discovery: RwLock::new(None),
+            jwks: RwLock::new(JwkSet { keys: Vec::new() }),
+            pending: Mutex::new(HashMap::new()),
+            cookie_options: CookieOptions {
+                http_only: true,
+                secure: true,
+                same_site: "Lax",
+            },
+        }
+    }
+
+    fn id_token(kid: &str, claims: serde_json::Value) -> String {
+        let der = STANDARD.decode(ED25519_PKCS8).unwrap_or_default();
+        let header = jsonwebtoken::Header {
+            kid: Some(kid.to_string()),
+            ..jsonwebtoken::Header::new(Algorithm::EdDSA)
+        };
+        encode(&header, &claims, &EncodingKey::from_ed_der(&der)).unwrap_or_default()
+    }
+
+    fn claims(issuer: &str, aud: &str, nonce: &str) -> serde_json::Value {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        serde_json::json!({
+            "iss": issuer, "aud": aud, "sub": "ana", "nonce": nonce,
+            "exp": now + 300, "iat": now,
+        })
+    }
+
+    #[tokio::test]
+    async fn verifies_id_tokens() {
+        let Some(idp) = idp().await else {
+            return;
+        };
+        let oidc = client(&idp.issuer, Forward::TokenExchange(String::new()));
+        let discovery = oidc.discovery().await;
+        assert!(discovery.is_ok(), "{discovery:?}");
+        let Ok(discovery) = discovery else {
+            return;
+        };
+        let verify = |token: String| {
+            let (oidc, discovery) = (&oidc, &discovery);
+            async move { oidc.verify_id_token(&token, "n-1", discovery).await }
+        };
+
+        let valid = verify(id_token("k1", claims(&idp.issuer, "botui", "n-1"))).await;
+        assert_eq!(
+            valid
+                .ok()
+                .and_then(|c| c["sub"].as_str().map(str::to_string)),
+            Some("ana".to_string())
+        );
+        for claims in [
+            claims("http://evil.example", "botui", "n-1"),
+            claims(&idp.issuer, "other-client", "n-1"),
+            claims(&idp.issuer, "botui", "n-2"),
+        ] {
+            assert!(verify(id_token("k1", claims)).await.is_err());
+        }
+
+        // Tokens signed with a shared secret need one configured
+        let hmac = encode(
+            &jsonwebtoken::Header::new(Algorithm::HS256),
+            &claims(&idp.issuer, "botui", "n-1"),
+            &EncodingKey::from_secret(b"guess"),
+        )
+        .unwrap_or_default();
+        assert!(verify(hmac).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn refetches_keys_when_the_idp_rotates() {
+        let Some(idp) = idp().await else {
+            return;
+        };
+        let oidc = client(&idp.issuer, Forward::TokenExchange(String::new()));
+        let discovery = oidc.discovery().await;
+        assert!(discovery.is_ok(), "{discovery:?}");
+        let Ok(discovery) = discovery else {
+            return;
+        };
+        let claims = claims(&idp.issuer, "botui", "n-1");
+
+        assert!(oidc
+            .verify_id_token(&id_token("k1", claims.clone()), "n-1", &discovery)
+            .await
+            .is_ok());
+        if let Ok(mut kids) = idp.kids.write() {
+            *kids = vec!["k2".to_string()];
+        }
+        assert!(oidc
+            .verify_id_token(&id_token("k2", claims.clone()), "n-1", &discovery)
+            .await
+            .is_ok());
+        assert!(oidc
+            .verify_id_token(&id_token("k3", claims), "n-1", &discovery)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn redeems_codes_with_the_pkce_verifier() {
+        let Some(idp) = idp().await else {
+            return;
+        };
+        let oidc = client(&idp.issuer, Forward::TokenExchange(String::new()));
+        let discovery = oidc.discovery().await;
+        assert!(discovery.is_ok(), "{discovery:?}");
+        let Ok(discovery) = discovery else {
+            return;
+        };
+
+        let verifier = random_token();
+        oidc.remember(
+            "state-1",
+            Pending {
+                verifier: verifier.clone(),
+                nonce: "n-1".to_string(),
+                redirect: "/edu".to_string(),
+                created: Instant::now(),
+            },
+        );
+        if let Ok(mut challenge) = idp.challenge.write() {
+            *challenge = pkce_challenge(&verifier);
+        }
+        if let Ok(mut token) = idp.id_token.write() {
+            *token = id_token("k1", claims(&idp.issuer, "botui", "n-1"));
+        }
+
+        assert!(oidc.take("state-2").is_none());
+        let pending = oidc.take("state-1");
+        assert!(pending.is_some());
+        let Some(pending) = pending else {
+            return;
+        };
+        assert!(oidc.take("state-1").is_none());
+
+        let redirect_uri = oidc.redirect_uri(None);
+        let grant = oidc
+            .redeem_code("code-1", &pending.verifier, &redirect_uri, &discovery)
+            .await;
+        let id_token = grant
+            .ok()
+            .and_then(|g| g["id_token"].as_str().map(str::to_string))
+            .unwrap_or_default();
+        assert!(oidc
+            .verify_id_token(&id_token, &pending.nonce, &discovery)
+            .await
+            .is_ok());
+
+        let wrong = oidc
+            .redeem_code("code-1", &random_token(), &redirect_uri, &discovery)
+            .await;
+        assert!(wrong.is_err());
+    }
+
+    #[test]
+    fn redirects_stay_on_this_site() {
+        assert_eq!(safe_redirect(Some("/edu/chat?x=1")), "/edu/chat?x=1");
+        assert_eq!(safe_redirect(Some("//evil.example")), "/");
+        assert_eq!(safe_redirect(Some("/\\evil.example")), "/");
+        assert_eq!(safe_redirect(Some("https://evil.example")), "/");
+        assert_eq!(safe_redirect(None), "/");
+    }
+
+    #[test]
+    fn callback_url_follows_the_public_origin() {
+        let mut oidc = client("https://id.example", Forward::TokenExchange(String::new()));
+        assert_eq!(
+            oidc.redirect_uri(Some("https://bots.example.com".to_string())),
+            "https://bots.example.com/login/oidc/callback"
+        );
+        assert_eq!(
+            oidc.redirect_uri(None),
+            "http://localhost/login/oidc/callback"
+        );
+        oidc.redirect_uri = Some("https://fixed.example/cb".to_string());
+        assert_eq!(
+            oidc.redirect_uri(Some("https://evil.example".to_string())),
+            "https://fixed.example/cb"
+        );
+    }
+
+    #[test]
+    fn signs_the_identity_header() {
+        let identity = serde_json::json!({ "sub": "ana" });
+        let name = HeaderName::from_static("x-gb-identity");
+        let signed = client(
+            "https://id.example",
+            Forward::Header(name.clone(), Some(b"secret".to_vec())),
+        );
+        let (header, value) = signed.identity_header(&identity).unwrap_or((
+            HeaderName::from_static("missing"),
+            HeaderValue::from_static(""),
+        ));
+        assert_eq!(header, name);
+        assert!(signed.is_identity_header(&name));
+
+        let value = value.to_str().unwrap_or_default();
+        let (payload, signature) = value.split_once('.').unwrap_or_default();
+        assert_eq!(
+            URL_SAFE_NO_PAD.decode(payload).ok(),
+            Some(identity.to_string().into_bytes())
+        );
+        let valid = Hmac::<Sha256>::new_from_slice(b"secret").is_ok_and(|mut mac| {
+            mac.update(payload.as_bytes());
+            URL_SAFE_NO_PAD
+                .decode(signature)
+                .is_ok_and(|sig| mac.verify_slice(&sig).is_ok())
+        });
+        assert!(valid);
+
+        let unsigned = client("https://id.example", Forward::Header(name, None));
+        let value = unsigned.identity_header(&identity).map(|(_, v)| v);
+        assert!(value.is_some_and(|v| !v.as_bytes().contains(&b'.')));
+        let exchange = client("https://id.example", Forward::TokenExchange(String::new()));
+        assert!(exchange.identity_header(&identity).is_none());
+    }
+}
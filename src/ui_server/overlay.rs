@@ -0,0 +1,187 @@
+use log::{info, warn};
+use std::path::{Component, Path, PathBuf};
+use std::sync::OnceLock;
+
+pub fn overlay_dirs() -> &'static [PathBuf] {
+    static DIRS: OnceLock<Vec<PathBuf>> = OnceLock::new();
+    DIRS.get_or_init(|| {
+        let Some(raw) = std::env::var_os("BOTUI_OVERLAY_DIRS") else {
+            return Vec::new();
+        };
+        std::env::split_paths(&raw)
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .filter(|dir| {
+                let exists = dir.is_dir();
+                if !exists {
+                    warn!("Ignoring UI overlay {dir:?}: not a directory");
+                }
+                exists
+            })
+            .collect()
+    })
+}
+
+fn is_safe_relative(relative: &str) -> bool {
+    !relative.is_empty()
+        && !relative.contains('\\')
+        && Path::new(relative)
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+}
+
+pub fn resolve(relative: &str) -> Option<PathBuf> {
+    resolve_in(overlay_dirs(), relative)
+}
+
+fn resolve_in(dirs: &[PathBuf], relative: &str) -> Option<PathBuf> {
+    let relative = relative.trim_start_matches('/');
+    if !is_safe_relative(relative) {
+        return None;
+    }
+    dirs.iter()
+        .map(|dir| dir.join(relative))
+        .find(|path| path.is_file())
+}
+
+#[cfg(not(feature = "embed-ui"))]
+pub fn matching_dirs(relative: &str) -> Vec<PathBuf> {
+    matching_dirs_in(overlay_dirs(), relative)
+}
+
+#[cfg(not(feature = "embed-ui"))]
+fn matching_dirs_in(dirs: &[PathBuf], relative: &str) -> Vec<PathBuf> {
+    dirs.iter()
+        .map(|dir| dir.join(relative))
+        .filter(|path| path.is_dir())
+        .collect()
+}
+
+fn collect_files(root: &Path, dir: &Path, out: &mut Vec<String>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            collect_files(root, &path, out);
+        } else if let Ok(relative) = path.strip_prefix(root) {
+            out.push(relative.to_string_lossy().replace('\\', "/"));
+        }
+    }
+}
+
+pub fn log_report(stock_has: impl Fn(&str) -> bool) {
+    let dirs = overlay_dirs();
+    if dirs.is_empty() {
+        return;
+    }
+
+    let mut seen: Vec<String> = Vec::new();
+    for dir in dirs {
+        let mut files = Vec::new();
+        collect_files(dir, dir, &mut files);
+        files.sort();
+
+        info!("UI overlay {dir:?}: {} file(s)", files.len());
+        for file in files {
+            if seen.contains(&file) {
+                info!("  shadowed  {file} (earlier overlay wins)");
+                continue;
+            }
+            if stock_has(&file) {
+                info!("  overrides {file}");
+            } else {
+                info!("  adds      {file}");
+            }
+            seen.push(file);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn overlays(name: &str, files: &[(&str, &str)]) -> Vec<PathBuf> {
+        let root =
+            std::env::temp_dir().join(format!("botui-overlay-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        for (dir, file) in files {
+            let path = root.join(dir).join(file);
+            if let Some(parent) = path.parent() {
+                let _ = std::fs::create_dir_all(parent);
+            }
+            let _ = std::fs::write(&path, dir);
+        }
+        ["first", "second"]
+            .iter()
+            .map(|dir| root.join(dir))
+            .collect()
+    }
+
+    #[test]
+    fn rejects_unsafe_relative_paths() {
+        assert!(is_safe_relative("suite/chat/chat.html"));
+        for path in [
+            "",
+            "../etc/passwd",
+            "suite/../../etc/passwd",
+            "/etc/passwd",
+            "./suite/index.html",
+            "suite\\..\\x.html",
+            "suite\\index.html",
+        ] {
+            assert!(!is_safe_relative(path), "{path}");
+        }
+    }
+
+    #[test]
+    fn earlier_overlays_win() {
+        let dirs = overlays(
+            "resolve",
+            &[
+                ("first", "suite/index.html"),
+                ("second", "suite/index.html"),
+                ("second", "suite/extra.css"),
+            ],
+        );
+        let content =
+            |path: Option<PathBuf>| path.and_then(|path| std::fs::read_to_string(path).ok());
+
+        assert_eq!(
+            content(resolve_in(&dirs, "/suite/index.html")).as_deref(),
+            Some("first")
+        );
+        assert_eq!(
+            content(resolve_in(&dirs, "suite/extra.css")).as_deref(),
+            Some("second")
+        );
+        assert_eq!(resolve_in(&dirs, "suite/missing.js"), None);
+        assert_eq!(resolve_in(&dirs, "suite"), None);
+        assert_eq!(resolve_in(&dirs, "suite/../suite/index.html"), None);
+    }
+
+    #[cfg(not(feature = "embed-ui"))]
+    #[test]
+    fn matching_dirs_follow_overlay_order() {
+        let dirs = overlays(
+            "dirs",
+            &[
+                ("first", "suite/chat/chat.html"),
+                ("second", "suite/chat/chat.css"),
+                ("second", "suite/mail/mail.html"),
+            ],
+        );
+        assert_eq!(
+            matching_dirs_in(&dirs, "suite/chat"),
+            dirs.iter()
+                .map(|dir| dir.join("suite/chat"))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            matching_dirs_in(&dirs, "suite/mail"),
+            [dirs[1].join("suite/mail")]
+        );
+        assert!(matching_dirs_in(&dirs, "suite/chat/chat.html").is_empty());
+    }
+}
@@ -0,0 +1,264 @@
+use axum::{
+    http::{header, HeaderMap, HeaderValue, Method, Uri},
+    response::Response,
+};
+use log::{error, info, warn};
+use serde::Deserialize;
+
+use super::{
+    access,
+    errors::{self, ErrorKind},
+    route,
+};
+use crate::shared::AppState;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+struct ProxyRoute {
+    path: String,
+    methods: Vec<String>,
+    max_body: Option<usize>,
+    auth: bool,
+    roles: Vec<String>,
+}
+
+pub fn path_matches(pattern: &str, path: &str) -> bool {
+    let mut pattern = pattern.split('/').peekable();
+    let mut path = path.split('/');
+    while let Some(expected) = pattern.next() {
+        let last = pattern.peek().is_none();
+        if last && expected == "*" {
+            return true;
+        }
+        let Some(actual) = path.next() else {
+            return false;
+        };
+        let matched = match expected.strip_suffix('*') {
+            Some(prefix) if last => actual.starts_with(prefix),
+            _ => expected == "*" || expected == actual,
+        };
+        if !matched {
+            return false;
+        }
+        if last && expected.ends_with('*') {
+            return true;
+        }
+    }
+    path.next().is_none()
+}
+
+impl ProxyRoute {
+    fn allows_method(&self, method: &Method) -> bool {
+        self.methods.is_empty()
+            || self
+                .methods
+                .iter()
+                .any(|m| m == "*" || m.eq_ignore_ascii_case(method.as_str()))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rejection {
+    BadPath,
+    NotAllowed,
+    Method(Vec<String>),
+    TooLarge(usize),
+    Unauthenticated,
+    Forbidden(Vec<String>),
+}
+
+impl Rejection {
+    pub fn reason(&self) -> String {
+        match self {
+            Self::BadPath => "path with dot segments or bad encoding".to_string(),
+            Self::NotAllowed => "path not in proxy allow-list".to_string(),
+            Self::Method(allowed) => format!("method not allowed (allowed: {allowed:?})"),
+            Self::TooLarge(limit) => format!("body larger than {limit} bytes"),
+            Self::Unauthenticated => "authentication required".to_string(),
+            Self::Forbidden(roles) => format!("needs one of roles {roles:?}"),
+        }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::BadPath => ErrorKind::BadRequest,
+            Self::NotAllowed => ErrorKind::NotFound,
+            Self::Method(_) => ErrorKind::MethodNotAllowed,
+            Self::TooLarge(_) => ErrorKind::PayloadTooLarge,
+            Self::Unauthenticated => ErrorKind::Unauthenticated,
+            Self::Forbidden(_) => ErrorKind::Forbidden,
+        }
+    }
+
+    pub fn respond(&self, headers: &HeaderMap, method: &Method, uri: &Uri) -> Response {
+        let mut response = errors::respond(headers, method, uri, self.kind(), &self.reason());
+        if let Self::Method(allowed) = self {
+            if let Ok(value) = HeaderValue::from_str(&allowed.join(", ")) {
+                response.headers_mut().insert(header::ALLOW, value);
+            }
+        }
+        response
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ProxyPolicy {
+    routes: Vec<ProxyRoute>,
+    max_body: Option<usize>,
+}
+
+impl ProxyPolicy {
+    #[must_use]
+    pub fn from_env() -> Option<Self> {
+        let path = std::env::var("BOTUI_PROXY_POLICY_FILE").ok()?;
+        let raw = std::fs::read_to_string(&path)
+            .map_err(|e| error!("Failed to read proxy policy {path}: {e}"))
+            .ok()?;
+        let policy = serde_json::from_str::<Self>(&raw)
+            .map_err(|e| error!("Invalid proxy policy {path}: {e}"))
+            .ok()?;
+        info!("Proxy allow-list enabled ({} routes)", policy.routes.len());
+
+        let verifies_locally = [
+            "BOTUI_JWT_SECRET",
+            "BOTUI_JWT_JWKS_FILE",
+            "BOTUI_SESSION_KEY",
+        ]
+        .iter()
+        .any(|name| std::env::var(name).is_ok_and(|v| !v.is_empty()));
+        if !verifies_locally && policy.routes.iter().any(|r| r.auth || !r.roles.is_empty()) {
+            warn!(
+                "Proxy routes with auth or roles need BOTUI_JWT_* or BOTUI_SESSION_KEY; \
+                 until one is set they refuse every request"
+            );
+        }
+        Some(policy)
+    }
+
+    pub async fn check(
+        &self,
+        state: &AppState,
+        method: &Method,
+        path: &str,
+        headers: &HeaderMap,
+        bot: Option<&str>,
+    ) -> Result<usize, Rejection> {
+        let path = route::normalize(path).ok_or(Rejection::BadPath)?;
+        let route = self
+            .routes
+            .iter()
+            .find(|route| path_matches(&route.path, &path))
+            .ok_or(Rejection::NotAllowed)?;
+
+        if !route.allows_method(method) {
+            return Err(Rejection::Method(route.methods.clone()));
+        }
+
+        let limit = route.max_body.or(self.max_body).unwrap_or(usize::MAX);
+        let declared = headers
+            .get(header::CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<usize>().ok());
+        if declared.is_some_and(|len| len > limit) {
+            return Err(Rejection::TooLarge(limit));
+        }
+
+        if route.auth || !route.roles.is_empty() {
+            match access::identify(state, headers, bot).await {
+                Some(identity) if route.roles.is_empty() || identity.has_any_role(&route.roles) => {
+                }
+                Some(_) => return Err(Rejection::Forbidden(route.roles.clone())),
+                None => return Err(Rejection::Unauthenticated),
+            }
+        }
+
+        Ok(limit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trailing_star_matches_remainder() {
+        assert!(path_matches("/api/*", "/api/tasks/42"));
+        assert!(path_matches("/api/*", "/api"));
+        assert!(!path_matches("/api/*", "/apps/x"));
+    }
+
+    #[test]
+    fn inner_star_matches_one_segment() {
+        assert!(path_matches("/api/bots/*/config", "/api/bots/edu/config"));
+        assert!(!path_matches(
+            "/api/bots/*/config",
+            "/api/bots/edu/x/config"
+        ));
+    }
+
+    #[test]
+    fn exact_and_prefix_segments() {
+        assert!(path_matches("/api/health", "/api/health"));
+        assert!(!path_matches("/api/health", "/api/health/deep"));
+        assert!(path_matches("/api/task*", "/api/tasks/stats"));
+        assert!(!path_matches("/api/task*", "/api/mail"));
+    }
+
+    #[tokio::test]
+    async fn matches_the_decoded_path() {
+        let policy: ProxyPolicy = serde_json::from_str(
+            r#"{ "routes": [{ "path": "/api/public/*" }, { "path": "/api/files/*", "methods": ["GET"] }] }"#,
+        )
+        .unwrap_or_default();
+        let state = AppState::new();
+        let headers = HeaderMap::new();
+        let check = |path: &'static str| policy.check(&state, &Method::GET, path, &headers, None);
+
+        assert!(check("/api/public/x").await.is_ok());
+        assert!(check("/api/public//./x").await.is_ok());
+        assert!(check("/api/files/.well-known").await.is_ok());
+        for path in [
+            "/api/public/../admin",
+            "/api/public/%2E%2e/admin",
+            "/api/public/..%2Fadmin",
+            "/api/public/x%5C..",
+        ] {
+            assert_eq!(check(path).await, Err(Rejection::BadPath), "{path}");
+        }
+        assert_eq!(check("/api/%61dmin").await, Err(Rejection::NotAllowed));
+        assert_eq!(check("/api/p%75blic/x").await.ok(), Some(usize::MAX));
+    }
+
+    #[test]
+    fn rejections_are_negotiated() {
+        let uri = Uri::from_static("/api/files/x");
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT, HeaderValue::from_static("*/*"));
+        let response =
+            Rejection::Method(vec!["GET".to_string()]).respond(&headers, &Method::POST, &uri);
+        assert_eq!(
+            response.status(),
+            axum::http::StatusCode::METHOD_NOT_ALLOWED
+        );
+        assert_eq!(
+            response
+                .headers()
+                .get(header::ALLOW)
+                .map(HeaderValue::as_bytes),
+            Some(&b"GET"[..])
+        );
+        assert_eq!(
+            response
+                .headers()
+                .get(header::CONTENT_TYPE)
+                .map(HeaderValue::as_bytes),
+            Some(&b"application/json"[..])
+        );
+
+        headers.insert("hx-request", HeaderValue::from_static("true"));
+        let response = Rejection::Unauthenticated.respond(&headers, &Method::GET, &uri);
+        assert_eq!(response.status(), axum::http::StatusCode::UNAUTHORIZED);
+        assert!(response.headers().contains_key("x-gb-error"));
+    }
+}
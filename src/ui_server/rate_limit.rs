@@ -0,0 +1,382 @@
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use log::{info, warn};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::forwarded;
+use crate::shared::AppState;
+
+const MAX_TRACKED_BUCKETS: usize = 50_000;
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+const OVERFLOW_CLIENT: &str = "overflow";
+const WS_RETRY_AFTER_SECS: u64 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RouteGroup {
+    Api,
+    Ws,
+    Auth,
+    Static,
+}
+
+impl RouteGroup {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "api" => Some(Self::Api),
+            "ws" => Some(Self::Ws),
+            "auth" => Some(Self::Auth),
+            "static" => Some(Self::Static),
+            _ => None,
+        }
+    }
+
+    pub fn for_path(path: &str) -> Self {
+        if path.starts_with("/api/auth/") || path == "/login" || path == "/logout" {
+            Self::Auth
+        } else if path == "/ws" || path.starts_with("/ws/") {
+            Self::Ws
+        } else if ["/api/", "/ui/", "/apps/"]
+            .iter()
+            .any(|p| path.starts_with(p))
+        {
+            Self::Api
+        } else {
+            Self::Static
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct BucketConfig {
+    per_sec: f64,
+    burst: f64,
+}
+
+impl BucketConfig {
+    fn parse(spec: &str) -> Option<Self> {
+        let (rate, burst) = match spec.split_once(':') {
+            Some((rate, burst)) => (rate, Some(burst.trim().parse::<f64>().ok()?)),
+            None => (spec, None),
+        };
+        let (count, unit) = rate.split_once('/')?;
+        let count: f64 = count.trim().parse().ok()?;
+        let period = match unit.trim() {
+            "s" | "sec" => 1.0,
+            "m" | "min" => 60.0,
+            "h" | "hour" => 3600.0,
+            _ => return None,
+        };
+        (count > 0.0).then(|| Self {
+            per_sec: count / period,
+            burst: burst.unwrap_or(count).max(1.0),
+        })
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+#[derive(Debug)]
+struct Buckets {
+    tracked: HashMap<(RouteGroup, String), Bucket>,
+    swept: Instant,
+}
+
+#[derive(Debug)]
+pub struct RateLimiter {
+    limits: HashMap<RouteGroup, BucketConfig>,
+    buckets: Mutex<Buckets>,
+    ws_max_total: usize,
+    ws_max_per_client: usize,
+    ws_total: AtomicUsize,
+    ws_clients: Mutex<HashMap<String, usize>>,
+}
+
+fn env_usize(name: &str, default: usize) -> usize {
+    std::env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+fn hashed(value: &str) -> String {
+    URL_SAFE_NO_PAD.encode(&Sha256::digest(value.as_bytes())[..12])
+}
+
+impl RateLimiter {
+    #[must_use]
+    pub fn from_env() -> Self {
+        let mut limits = HashMap::new();
+        for entry in std::env::var("BOTUI_RATE_LIMITS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|e| !e.is_empty())
+        {
+            let parsed = entry.split_once('=').and_then(|(group, spec)| {
+                Some((RouteGroup::parse(group.trim())?, BucketConfig::parse(spec)?))
+            });
+            match parsed {
+                Some((group, config)) => {
+                    limits.insert(group, config);
+                }
+                None => warn!("Ignoring invalid rate limit: {entry}"),
+            }
+        }
+
+        let ws_max_total = env_usize("BOTUI_WS_MAX_CONNECTIONS", 10_000);
+        let ws_max_per_client = env_usize("BOTUI_WS_MAX_PER_CLIENT", 20);
+
+        if !limits.is_empty() {
+            info!("Rate limits: {limits:?}");
+        }
+        info!("WebSocket caps: {ws_max_total} total, {ws_max_per_client} per client");
+
+        Self {
+            limits,
+            buckets: Mutex::new(Buckets {
+                tracked: HashMap::new(),
+                swept: Instant::now(),
+            }),
+            ws_max_total,
+            ws_max_per_client,
+            ws_total: AtomicUsize::new(0),
+            ws_clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn take(&self, group: RouteGroup, client: &str) -> Result<(), u64> {
+        let Some(config) = self.limits.get(&group) else {
+            return Ok(());
+        };
+        let Ok(mut buckets) = self.buckets.lock() else {
+            return Ok(());
+        };
+
+        let now = Instant::now();
+        let mut key = (group, client.to_string());
+        if buckets.tracked.len() >= MAX_TRACKED_BUCKETS && !buckets.tracked.contains_key(&key) {
+            if now.duration_since(buckets.swept) >= SWEEP_INTERVAL {
+                // Idle buckets have refilled completely and carry no state worth keeping
+                let limits = &self.limits;
+                buckets.tracked.retain(|(group, _), bucket| {
+                    limits.get(group).is_some_and(|c| {
+                        bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * c.per_sec
+                            < c.burst
+                    })
+                });
+                buckets.swept = now;
+            }
+            if buckets.tracked.len() >= MAX_TRACKED_BUCKETS {
+                key = (group, OVERFLOW_CLIENT.to_string());
+            }
+        }
+
+        let bucket = buckets.tracked.entry(key).or_insert(Bucket {
+            tokens: config.burst,
+            updated: now,
+        });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * config.per_sec).min(config.burst);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(((1.0 - bucket.tokens) / config.per_sec).ceil().max(1.0) as u64)
+        }
+    }
+
+    pub fn acquire_ws(self: &Arc<Self>, client: &str) -> Result<WsPermit, u64> {
+        let total = self.ws_total.fetch_add(1, Ordering::SeqCst) + 1;
+        let mut permit = WsPermit {
+            limiter: Arc::clone(self),
+            client: None,
+        };
+        if self.ws_max_total > 0 && total > self.ws_max_total {
+            warn!("WebSocket limit reached ({} open)", self.ws_max_total);
+            return Err(WS_RETRY_AFTER_SECS);
+        }
+
+        let Ok(mut clients) = self.ws_clients.lock() else {
+            return Ok(permit);
+        };
+        let open = clients.entry(client.to_string()).or_insert(0);
+        if self.ws_max_per_client > 0 && *open >= self.ws_max_per_client {
+            warn!("WebSocket limit reached for client {client}");
+            return Err(WS_RETRY_AFTER_SECS);
+        }
+        *open += 1;
+        permit.client = Some(client.to_string());
+        Ok(permit)
+    }
+}
+
+fn client_key(state: &AppState, headers: &HeaderMap, peer: Option<IpAddr>) -> String {
+    if let Some(session) = state
+        .sessions
+        .as_ref()
+        .and_then(|store| store.session_id(headers))
+    {
+        return format!("session:{}", hashed(&session));
+    }
+    let subject = state.jwt.as_ref().and_then(|verifier| {
+        let token = headers
+            .get(header::AUTHORIZATION)?
+            .to_str()
+            .ok()?
+            .strip_prefix("Bearer ")?;
+        verifier.verify(token).ok()?.subject
+    });
+    if let Some(subject) = subject {
+        return format!("user:{}", hashed(&subject));
+    }
+    peer.map_or_else(|| "ip:unknown".to_string(), |ip| format!("ip:{ip}"))
+}
+
+#[derive(Debug)]
+pub struct WsPermit {
+    limiter: Arc<RateLimiter>,
+    client: Option<String>,
+}
+
+impl Drop for WsPermit {
+    fn drop(&mut self) {
+        self.limiter.ws_total.fetch_sub(1, Ordering::SeqCst);
+        let Some(client) = &self.client else {
+            return;
+        };
+        if let Ok(mut clients) = self.limiter.ws_clients.lock() {
+            if let Some(open) = clients.get_mut(client) {
+                *open = open.saturating_sub(1);
+                if *open == 0 {
+                    clients.remove(client);
+                }
+            }
+        }
+    }
+}
+
+pub fn too_many_requests(retry_after_secs: u64) -> Response {
+    let mut response = (StatusCode::TOO_MANY_REQUESTS, "Too many requests").into_response();
+    response
+        .headers_mut()
+        .insert(header::RETRY_AFTER, HeaderValue::from(retry_after_secs));
+    response
+}
+
+#[derive(Debug, Clone)]
+pub struct ClientKey(pub String);
+
+pub fn peer_addr(request: &Request) -> Option<SocketAddr> {
+    request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| *addr)
+}
+
+pub async fn apply(State(state): State<AppState>, mut request: Request, next: Next) -> Response {
+    let group = RouteGroup::for_path(request.uri().path());
+    let client = client_key(
+        &state,
+        request.headers(),
+        forwarded::client_ip(&state, &request),
+    );
+
+    if let Err(retry_after) = state.rate_limiter.take(group, &client) {
+        warn!(
+            "Rate limited {client} on {group:?}: {} {}",
+            request.method(),
+            request.uri().path()
+        );
+        return too_many_requests(retry_after);
+    }
+
+    request.extensions_mut().insert(ClientKey(client));
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(spec: &str) -> RateLimiter {
+        let mut limits = HashMap::new();
+        if let Some(config) = BucketConfig::parse(spec) {
+            limits.insert(RouteGroup::Api, config);
+        }
+        RateLimiter {
+            limits,
+            buckets: Mutex::new(Buckets {
+                tracked: HashMap::new(),
+                swept: Instant::now(),
+            }),
+            ws_max_total: 2,
+            ws_max_per_client: 1,
+            ws_total: AtomicUsize::new(0),
+            ws_clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    #[test]
+    fn parses_bucket_specs() {
+        assert_eq!(
+            BucketConfig::parse("10/m:20"),
+            Some(BucketConfig {
+                per_sec: 10.0 / 60.0,
+                burst: 20.0
+            })
+        );
+        assert_eq!(BucketConfig::parse("0/s"), None);
+        assert_eq!(BucketConfig::parse("5/day"), None);
+    }
+
+    #[test]
+    fn limits_each_client_separately() {
+        let limiter = limiter("1/h:2");
+        assert_eq!(limiter.take(RouteGroup::Api, "a"), Ok(()));
+        assert_eq!(limiter.take(RouteGroup::Api, "a"), Ok(()));
+        assert_eq!(limiter.take(RouteGroup::Api, "a"), Err(3600));
+        assert_eq!(limiter.take(RouteGroup::Api, "b"), Ok(()));
+        assert_eq!(limiter.take(RouteGroup::Static, "a"), Ok(()));
+    }
+
+    #[test]
+    fn clients_beyond_the_cap_share_a_bucket() {
+        let limiter = limiter("1/h:1");
+        for i in 0..MAX_TRACKED_BUCKETS {
+            assert_eq!(
+                limiter.take(RouteGroup::Api, &format!("client-{i}")),
+                Ok(())
+            );
+        }
+        assert_eq!(limiter.take(RouteGroup::Api, "late-1"), Ok(()));
+        assert!(limiter.take(RouteGroup::Api, "late-2").is_err());
+        let tracked = limiter.buckets.lock().map_or(0, |b| b.tracked.len());
+        assert_eq!(tracked, MAX_TRACKED_BUCKETS + 1);
+    }
+
+    #[test]
+    fn websocket_permits_are_released_on_drop() {
+        let limiter = Arc::new(limiter("1/s"));
+        let first = limiter.acquire_ws("a");
+        assert!(first.is_ok());
+        assert_eq!(limiter.acquire_ws("a").err(), Some(WS_RETRY_AFTER_SECS));
+        drop(first);
+        assert!(limiter.acquire_ws("a").is_ok());
+    }
+}
@@ -0,0 +1,244 @@
+use axum::http::{Method, StatusCode};
+use log::{info, warn};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::proxy_policy::path_matches;
+
+const MAX_BACKOFF: Duration = Duration::from_secs(2);
+const STREAMING_TIMEOUTS: &[(&str, u64)] = &[
+    ("/api/llm/*", 600),
+    ("/api/chat/*", 600),
+    ("/api/drive/*", 600),
+    ("/api/files/*", 600),
+    ("/api/video/*", 600),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Circuit {
+    Closed { failures: u32 },
+    Open { until: Instant },
+    HalfOpen { since: Instant },
+}
+
+#[derive(Debug)]
+pub struct Resilience {
+    timeout: Duration,
+    route_timeouts: Vec<(String, Duration)>,
+    retries: u32,
+    backoff: Duration,
+    failure_threshold: u32,
+    open_for: Duration,
+    circuits: Mutex<HashMap<String, Circuit>>,
+}
+
+fn env_u64(name: &str, default: u64) -> u64 {
+    std::env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+pub fn is_idempotent(method: &Method) -> bool {
+    [
+        Method::GET,
+        Method::HEAD,
+        Method::OPTIONS,
+        Method::TRACE,
+        Method::PUT,
+        Method::DELETE,
+    ]
+    .contains(method)
+}
+
+pub fn is_upstream_failure(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+pub fn is_retryable(error: &reqwest::Error) -> bool {
+    !error.is_timeout() && (error.is_connect() || error.is_request())
+}
+
+impl Resilience {
+    #[must_use]
+    pub fn from_env() -> Self {
+        let mut route_timeouts = Vec::new();
+        for entry in std::env::var("BOTUI_PROXY_TIMEOUTS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|e| !e.is_empty())
+        {
+            match entry
+                .split_once('=')
+                .and_then(|(path, secs)| Some((path.trim(), secs.trim().parse::<u64>().ok()?)))
+            {
+                Some((path, secs)) => {
+                    route_timeouts.push((path.to_string(), Duration::from_secs(secs)));
+                }
+                None => warn!("Ignoring invalid proxy timeout: {entry}"),
+            }
+        }
+        if !route_timeouts.is_empty() {
+            info!("Proxy timeouts for {} routes", route_timeouts.len());
+        }
+        route_timeouts.extend(
+            STREAMING_TIMEOUTS
+                .iter()
+                .map(|(path, secs)| ((*path).to_string(), Duration::from_secs(*secs))),
+        );
+
+        Self {
+            timeout: Duration::from_secs(env_u64("BOTUI_PROXY_TIMEOUT_SECS", 30).max(1)),
+            route_timeouts,
+            retries: u32::try_from(env_u64("BOTUI_PROXY_RETRIES", 2)).unwrap_or(2),
+            backoff: Duration::from_millis(env_u64("BOTUI_PROXY_RETRY_BACKOFF_MS", 100)),
+            failure_threshold: u32::try_from(env_u64("BOTUI_BREAKER_FAILURES", 5))
+                .unwrap_or(5)
+                .max(1),
+            open_for: Duration::from_secs(env_u64("BOTUI_BREAKER_OPEN_SECS", 30).max(1)),
+            circuits: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn timeout_for(&self, path: &str) -> Duration {
+        self.route_timeouts
+            .iter()
+            .find(|(pattern, _)| path_matches(pattern, path))
+            .map_or(self.timeout, |(_, timeout)| *timeout)
+    }
+
+    pub fn attempts(&self, method: &Method) -> u32 {
+        if is_idempotent(method) {
+            self.retries + 1
+        } else {
+            1
+        }
+    }
+
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let ceiling = self
+            .backoff
+            .saturating_mul(1 << attempt.saturating_sub(1).min(16))
+            .min(MAX_BACKOFF);
+        ceiling.mul_f64(rand::random::<f64>())
+    }
+
+    pub fn admit(&self, upstream: &str) -> Result<(), u64> {
+        let Ok(mut circuits) = self.circuits.lock() else {
+            return Ok(());
+        };
+        let now = Instant::now();
+        let circuit = circuits
+            .entry(upstream.to_string())
+            .or_insert(Circuit::Closed { failures: 0 });
+        match *circuit {
+            Circuit::Closed { .. } => Ok(()),
+            Circuit::Open { until } if now >= until => {
+                info!("Circuit for {upstream} half-open, probing");
+                *circuit = Circuit::HalfOpen { since: now };
+                Ok(())
+            }
+            Circuit::Open { until } => Err(until.duration_since(now).as_secs().max(1)),
+            // A probe whose request was abandoned must not block forever
+            Circuit::HalfOpen { since } if now.duration_since(since) >= self.open_for => {
+                *circuit = Circuit::HalfOpen { since: now };
+                Ok(())
+            }
+            Circuit::HalfOpen { .. } => Err(1),
+        }
+    }
+
+    pub fn record(&self, upstream: &str, success: bool) {
+        let Ok(mut circuits) = self.circuits.lock() else {
+            return;
+        };
+        let circuit = circuits
+            .entry(upstream.to_string())
+            .or_insert(Circuit::Closed { failures: 0 });
+        let next = match (*circuit, success) {
+            (Circuit::HalfOpen { .. }, true) => {
+                info!("Circuit for {upstream} closed");
+                Circuit::Closed { failures: 0 }
+            }
+            (_, true) => Circuit::Closed { failures: 0 },
+            (Circuit::Closed { failures }, false) if failures + 1 < self.failure_threshold => {
+                Circuit::Closed {
+                    failures: failures + 1,
+                }
+            }
+            (Circuit::Open { until }, false) => Circuit::Open { until },
+            (_, false) => {
+                warn!(
+                    "Circuit for {upstream} open for {}s",
+                    self.open_for.as_secs()
+                );
+                Circuit::Open {
+                    until: Instant::now() + self.open_for,
+                }
+            }
+        };
+        *circuit = next;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resilience(threshold: u32, open_for: Duration) -> Resilience {
+        Resilience {
+            timeout: Duration::from_secs(30),
+            route_timeouts: vec![("/api/llm/*".to_string(), Duration::from_secs(300))],
+            retries: 2,
+            backoff: Duration::from_millis(100),
+            failure_threshold: threshold,
+            open_for,
+            circuits: Mutex::new(HashMap::new()),
+        }
+    }
+
+    #[test]
+    fn timeouts_and_attempts_follow_route_and_method() {
+        let r = resilience(5, Duration::from_secs(30));
+        assert_eq!(r.timeout_for("/api/llm/stream"), Duration::from_secs(300));
+        assert_eq!(r.timeout_for("/api/tasks"), Duration::from_secs(30));
+        assert_eq!(r.attempts(&Method::GET), 3);
+        assert_eq!(r.attempts(&Method::POST), 1);
+        assert!(r.backoff(3) <= Duration::from_millis(400));
+
+        let defaults = Resilience::from_env();
+        assert_eq!(
+            defaults.timeout_for("/api/llm/stream"),
+            Duration::from_secs(600)
+        );
+        assert_eq!(
+            defaults.timeout_for("/api/drive/download"),
+            Duration::from_secs(600)
+        );
+    }
+
+    #[test]
+    fn circuit_opens_and_recovers_through_a_probe() {
+        let r = resilience(2, Duration::ZERO);
+        let up = "http://bs:8080";
+        r.record(up, false);
+        assert!(r.admit(up).is_ok());
+        r.record(up, false);
+        // Open with a zero period: the next caller becomes the probe
+        assert!(r.admit(up).is_ok());
+        r.record(up, true);
+        assert_eq!(
+            r.circuits.lock().ok().and_then(|c| c.get(up).copied()),
+            Some(Circuit::Closed { failures: 0 })
+        );
+
+        let r = resilience(1, Duration::from_secs(60));
+        r.record(up, false);
+        assert!(r.admit(up).is_err());
+    }
+}
@@ -0,0 +1,551 @@
+use axum::{
+    body::{Body, Bytes},
+    http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use log::{debug, info, warn};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::OnceCell;
+
+use super::errors::{ErrorKind, Failure};
+use super::{forwarded, proxy_policy};
+
+const X_CACHE: HeaderName = HeaderName::from_static("x-cache");
+const DEFAULT_ROUTES: &str =
+    "/api/ui/monitoring*=5,/api/ui/analytics*=30,/api/i18n/*=300,/api/tasks/stats=5";
+const KEYED_HEADERS: [&str; 6] = [
+    "accept",
+    "accept-language",
+    "hx-request",
+    "hx-target",
+    "x-bot-name",
+    "x-app-context",
+];
+
+type Inflight = Arc<OnceCell<Result<Arc<Stored>, Failure>>>;
+
+#[derive(Debug)]
+pub struct Upstreamed {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+    pub upstream: String,
+}
+
+#[derive(Debug)]
+pub struct Stored {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+    upstream: String,
+    stored_at: Instant,
+    fresh_for: Duration,
+    stale_for: Duration,
+    storable: bool,
+    vary: Vec<(HeaderName, Option<HeaderValue>)>,
+}
+
+impl Stored {
+    pub fn upstream(&self) -> &str {
+        &self.upstream
+    }
+
+    fn age(&self) -> Duration {
+        self.stored_at.elapsed()
+    }
+
+    fn matches(&self, request_headers: &HeaderMap) -> bool {
+        self.vary
+            .iter()
+            .all(|(name, value)| request_headers.get(name) == value.as_ref())
+    }
+
+    pub fn to_response(&self, request_headers: &HeaderMap, cache_status: &'static str) -> Response {
+        let etag = self.headers.get(header::ETAG);
+        let not_modified = self.status == StatusCode::OK
+            && etag.is_some_and(|etag| {
+                request_headers
+                    .get(header::IF_NONE_MATCH)
+                    .and_then(|v| v.to_str().ok())
+                    .is_some_and(|given| {
+                        given.split(',').any(|tag| {
+                            tag.trim() == "*" || tag.trim().as_bytes() == etag.as_bytes()
+                        })
+                    })
+            });
+
+        let mut response = if not_modified {
+            StatusCode::NOT_MODIFIED.into_response()
+        } else {
+            let mut response = Response::new(Body::from(self.body.clone()));
+            *response.status_mut() = self.status;
+            response
+        };
+        let headers = response.headers_mut();
+        for (name, value) in &self.headers {
+            if !not_modified || name != header::CONTENT_TYPE {
+                headers.append(name.clone(), value.clone());
+            }
+        }
+        headers.insert(X_CACHE, HeaderValue::from_static(cache_status));
+        if cache_status != "MISS" {
+            headers.insert(header::AGE, HeaderValue::from(self.age().as_secs()));
+        }
+        response
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Directives {
+    no_store: bool,
+    no_cache: bool,
+    private: bool,
+    must_revalidate: bool,
+    max_age: Option<u64>,
+    stale_while_revalidate: Option<u64>,
+}
+
+impl Directives {
+    fn parse(headers: &HeaderMap) -> Self {
+        let mut directives = Self::default();
+        let mut s_maxage = None;
+        for value in headers.get_all(header::CACHE_CONTROL) {
+            let Ok(value) = value.to_str() else {
+                continue;
+            };
+            for directive in value.split(',') {
+                let (name, arg) = directive
+                    .split_once('=')
+                    .map_or((directive, None), |(n, a)| (n, Some(a)));
+                let secs = arg.and_then(|a| a.trim().trim_matches('"').parse::<u64>().ok());
+                match name.trim().to_ascii_lowercase().as_str() {
+                    "no-store" => directives.no_store = true,
+                    "no-cache" => directives.no_cache = true,
+                    "private" => directives.private = true,
+                    "must-revalidate" | "proxy-revalidate" => directives.must_revalidate = true,
+                    "max-age" => directives.max_age = secs,
+                    "s-maxage" => s_maxage = secs,
+                    "stale-while-revalidate" => directives.stale_while_revalidate = secs,
+                    _ => {}
+                }
+            }
+        }
+        // botui is a shared cache, so s-maxage wins over max-age
+        directives.max_age = s_maxage.or(directives.max_age);
+        directives
+    }
+}
+
+#[derive(Debug)]
+pub struct ResponseCache {
+    routes: Vec<(String, Duration)>,
+    stale_for: Duration,
+    max_entries: usize,
+    max_body: usize,
+    entries: Mutex<HashMap<String, Arc<Stored>>>,
+    inflight: Mutex<HashMap<String, Inflight>>,
+}
+
+fn env_u64(name: &str, default: u64) -> u64 {
+    std::env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+impl ResponseCache {
+    #[must_use]
+    pub fn from_env() -> Self {
+        let mut routes = Vec::new();
+        for entry in std::env::var("BOTUI_CACHE_ROUTES")
+            .unwrap_or_else(|_| DEFAULT_ROUTES.to_string())
+            .split(',')
+            .map(str::trim)
+            .filter(|e| !e.is_empty())
+        {
+            match entry
+                .split_once('=')
+                .and_then(|(path, secs)| Some((path.trim(), secs.trim().parse::<u64>().ok()?)))
+            {
+                Some((path, secs)) => routes.push((path.to_string(), Duration::from_secs(secs))),
+                None => warn!("Ignoring invalid cache route: {entry}"),
+            }
+        }
+        if !routes.is_empty() {
+            info!("Caching responses for {} routes", routes.len());
+        }
+
+        Self {
+            routes,
+            stale_for: Duration::from_secs(env_u64("BOTUI_CACHE_STALE_SECS", 30)),
+            max_entries: usize::try_from(env_u64("BOTUI_CACHE_MAX_ENTRIES", 1024))
+                .unwrap_or(1024)
+                .max(1),
+            max_body: usize::try_from(env_u64("BOTUI_CACHE_MAX_BODY_BYTES", 1024 * 1024))
+                .unwrap_or(1024 * 1024),
+            entries: Mutex::new(HashMap::new()),
+            inflight: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn ttl_for(&self, path: &str) -> Option<Duration> {
+        self.routes
+            .iter()
+            .find(|(pattern, _)| proxy_policy::path_matches(pattern, path))
+            .map(|(_, ttl)| *ttl)
+    }
+
+    pub async fn get_or_fetch<F, Fut>(
+        self: &Arc<Self>,
+        key: String,
+        ttl: Duration,
+        request_headers: &HeaderMap,
+        fetch: F,
+    ) -> Result<(Arc<Stored>, &'static str), Failure>
+    where
+        F: Fn(HeaderMap) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Upstreamed, Failure>> + Send + 'static,
+    {
+        let shared = !key.starts_with("user:");
+        let reload = request_headers
+            .get(header::CACHE_CONTROL)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.contains("no-cache"));
+        let cached = self
+            .entries
+            .lock()
+            .ok()
+            .and_then(|entries| entries.get(&key).cloned())
+            .filter(|stored| stored.matches(request_headers));
+
+        if let Some(stored) = cached.as_ref().filter(|_| !reload) {
+            let age = stored.age();
+            if age < stored.fresh_for {
+                return Ok((stored.clone(), "HIT"));
+            }
+            if age < stored.fresh_for + stored.stale_for {
+                let cache = self.clone();
+                let previous = stored.clone();
+                let request_headers = request_headers.clone();
+                tokio::spawn(async move {
+                    let result = cache
+                        .coalesced(key, ttl, Some(previous), shared, &request_headers, fetch)
+                        .await;
+                    if let Err(failure) = result {
+                        debug!("Background revalidation failed: {}", failure.detail);
+                    }
+                });
+                return Ok((stored.clone(), "STALE"));
+            }
+        }
+
+        self.coalesced(key, ttl, cached, shared, request_headers, fetch)
+            .await
+            .map(|stored| (stored, "MISS"))
+    }
+
+    async fn coalesced<F, Fut>(
+        &self,
+        key: String,
+        ttl: Duration,
+        previous: Option<Arc<Stored>>,
+        shared: bool,
+        request_headers: &HeaderMap,
+        fetch: F,
+    ) -> Result<Arc<Stored>, Failure>
+    where
+        F: Fn(HeaderMap) -> Fut,
+        Fut: Future<Output = Result<Upstreamed, Failure>>,
+    {
+        let cell = match self.inflight.lock() {
+            Ok(mut inflight) => inflight.entry(key.clone()).or_default().clone(),
+            Err(_) => Arc::new(OnceCell::new()),
+        };
+        let result = cell
+            .get_or_init(|| async {
+                let validators = previous.as_deref().map(validators).unwrap_or_default();
+                let fetched = fetch(validators).await?;
+                let stored = match previous.filter(|_| fetched.status == StatusCode::NOT_MODIFIED) {
+                    Some(previous) => self.revalidated(&previous, &fetched.headers),
+                    None => self.to_stored(fetched, ttl, shared, request_headers),
+                };
+                let stored = Arc::new(stored);
+                if stored.storable {
+                    self.insert(key.clone(), stored.clone());
+                }
+                Ok(stored)
+            })
+            .await
+            .clone();
+
+        if let Ok(mut inflight) = self.inflight.lock() {
+            if inflight.get(&key).is_some_and(|c| Arc::ptr_eq(c, &cell)) {
+                inflight.remove(&key);
+            }
+        }
+        result
+    }
+
+    fn to_stored(
+        &self,
+        fetched: Upstreamed,
+        ttl: Duration,
+        shared: bool,
+        request_headers: &HeaderMap,
+    ) -> Stored {
+        let directives = Directives::parse(&fetched.headers);
+        let mut vary = Vec::new();
+        let mut vary_any = false;
+        for value in fetched.headers.get_all(header::VARY) {
+            for name in value.to_str().unwrap_or("*").split(',').map(str::trim) {
+                match HeaderName::from_bytes(name.as_bytes()) {
+                    Ok(name) => {
+                        let value = request_headers.get(&name).cloned();
+                        vary.push((name, value));
+                    }
+                    Err(_) => vary_any = true,
+                }
+            }
+        }
+
+        let mut headers = HeaderMap::new();
+        for (name, value) in &fetched.headers {
+            let skip = name == header::CONTENT_LENGTH
+                || forwarded::is_hop_by_hop(name, &fetched.headers)
+                // Anonymous callers must not pick up each other's cookies
+                || (shared && name == header::SET_COOKIE);
+            if !skip {
+                headers.append(name.clone(), value.clone());
+            }
+        }
+
+        let unstorable = directives.no_store
+            || (directives.private && shared)
+            || vary_any
+            || fetched.headers.contains_key(header::SET_COOKIE)
+            || fetched.body.len() > self.max_body;
+        let storable = fetched.status == StatusCode::OK && !unstorable;
+        let fresh_for = if directives.no_cache {
+            Duration::ZERO
+        } else {
+            directives.max_age.map_or(ttl, Duration::from_secs)
+        };
+        let stale_for = if directives.no_cache || directives.must_revalidate {
+            Duration::ZERO
+        } else {
+            directives
+                .stale_while_revalidate
+                .map_or(self.stale_for, Duration::from_secs)
+        };
+
+        Stored {
+            status: fetched.status,
+            headers,
+            body: fetched.body,
+            upstream: fetched.upstream,
+            stored_at: Instant::now(),
+            fresh_for,
+            stale_for,
+            storable,
+            vary,
+        }
+    }
+
+    fn revalidated(&self, previous: &Stored, headers: &HeaderMap) -> Stored {
+        let mut merged = previous.headers.clone();
+        for name in headers.keys() {
+            let keep = [
+                header::CACHE_CONTROL,
+                header::ETAG,
+                header::EXPIRES,
+                header::LAST_MODIFIED,
+            ]
+            .contains(name);
+            if keep {
+                merged.remove(name);
+                for value in headers.get_all(name) {
+                    merged.append(name.clone(), value.clone());
+                }
+            }
+        }
+        let directives = Directives::parse(&merged);
+        Stored {
+            status: previous.status,
+            headers: merged,
+            body: previous.body.clone(),
+            upstream: previous.upstream.clone(),
+            stored_at: Instant::now(),
+            fresh_for: directives
+                .max_age
+                .map_or(previous.fresh_for, Duration::from_secs),
+            stale_for: previous.stale_for,
+            storable: true,
+            vary: previous.vary.clone(),
+        }
+    }
+
+    fn insert(&self, key: String, stored: Arc<Stored>) {
+        let Ok(mut entries) = self.entries.lock() else {
+            return;
+        };
+        if entries.len() >= self.max_entries && !entries.contains_key(&key) {
+            entries.retain(|_, s| s.age() < s.fresh_for + s.stale_for);
+            if entries.len() >= self.max_entries {
+                let oldest = entries
+                    .iter()
+                    .min_by_key(|(_, s)| s.stored_at)
+                    .map(|(k, _)| k.clone());
+                if let Some(oldest) = oldest {
+                    entries.remove(&oldest);
+                }
+            }
+        }
+        entries.insert(key, stored);
+    }
+}
+
+fn validators(stored: &Stored) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    if let Some(etag) = stored.headers.get(header::ETAG) {
+        headers.insert(header::IF_NONE_MATCH, etag.clone());
+    }
+    if let Some(modified) = stored.headers.get(header::LAST_MODIFIED) {
+        headers.insert(header::IF_MODIFIED_SINCE, modified.clone());
+    }
+    headers
+}
+
+pub fn key(path_and_query: &str, upstream_headers: &HeaderMap, session_id: Option<&str>) -> String {
+    let mut credentials = Sha256::new();
+    let mut authenticated = false;
+    for name in [header::AUTHORIZATION, header::COOKIE] {
+        for value in upstream_headers.get_all(&name) {
+            credentials.update(name.as_str().as_bytes());
+            credentials.update(value.as_bytes());
+            authenticated = true;
+        }
+    }
+    if let Some(id) = session_id {
+        credentials.update(b"session");
+        credentials.update(id.as_bytes());
+        authenticated = true;
+    }
+
+    let mut key = if authenticated {
+        let digest: String = credentials
+            .finalize()
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+        format!("user:{digest}")
+    } else {
+        "shared".to_string()
+    };
+    key.push(' ');
+    key.push_str(path_and_query);
+    for name in KEYED_HEADERS {
+        if let Some(value) = upstream_headers.get(name) {
+            key.push('\n');
+            key.push_str(name);
+            key.push(':');
+            key.push_str(&String::from_utf8_lossy(value.as_bytes()));
+        }
+    }
+    key
+}
+
+pub fn read_failure(e: &reqwest::Error) -> Failure {
+    let kind = if e.is_timeout() {
+        ErrorKind::UpstreamTimeout
+    } else {
+        ErrorKind::UpstreamUnreachable
+    };
+    Failure::new(kind, format!("failed to read response: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_cache_control() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::CACHE_CONTROL,
+            HeaderValue::from_static("public, max-age=10, s-maxage=60, stale-while-revalidate=5"),
+        );
+        let directives = Directives::parse(&headers);
+        assert_eq!(directives.max_age, Some(60));
+        assert_eq!(directives.stale_while_revalidate, Some(5));
+        assert!(!directives.no_store);
+
+        headers.insert(
+            header::CACHE_CONTROL,
+            HeaderValue::from_static("private, no-cache"),
+        );
+        let directives = Directives::parse(&headers);
+        assert!(directives.private && directives.no_cache);
+    }
+
+    #[test]
+    fn keys_are_per_user_when_authenticated() {
+        let mut headers = HeaderMap::new();
+        headers.insert("hx-request", HeaderValue::from_static("true"));
+        let anonymous = key("/api/tasks/stats", &headers, None);
+        assert!(anonymous.starts_with("shared "));
+
+        headers.insert(header::AUTHORIZATION, HeaderValue::from_static("Bearer a"));
+        let alice = key("/api/tasks/stats", &headers, None);
+        headers.insert(header::AUTHORIZATION, HeaderValue::from_static("Bearer b"));
+        let bob = key("/api/tasks/stats", &headers, None);
+        assert!(alice.starts_with("user:"));
+        assert_ne!(alice, bob);
+        assert!(!alice.contains("Bearer"));
+    }
+
+    #[tokio::test]
+    async fn coalesces_concurrent_misses() {
+        let cache = Arc::new(ResponseCache {
+            routes: Vec::new(),
+            stale_for: Duration::ZERO,
+            max_entries: 8,
+            max_body: 1024,
+            entries: Mutex::new(HashMap::new()),
+            inflight: Mutex::new(HashMap::new()),
+        });
+        let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let fetch = {
+            let calls = calls.clone();
+            move |_: HeaderMap| {
+                let calls = calls.clone();
+                async move {
+                    calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    Ok(Upstreamed {
+                        status: StatusCode::OK,
+                        headers: HeaderMap::new(),
+                        body: Bytes::from_static(b"{}"),
+                        upstream: "http://upstream".to_string(),
+                    })
+                }
+            }
+        };
+
+        let headers = HeaderMap::new();
+        let ttl = Duration::from_secs(60);
+        let (a, b) = tokio::join!(
+            cache.get_or_fetch("k".to_string(), ttl, &headers, fetch.clone()),
+            cache.get_or_fetch("k".to_string(), ttl, &headers, fetch.clone()),
+        );
+        assert!(a.is_ok() && b.is_ok());
+        let hit = cache
+            .get_or_fetch("k".to_string(), ttl, &headers, fetch)
+            .await;
+        assert_eq!(hit.map(|(_, status)| status), Ok("HIT"));
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+}
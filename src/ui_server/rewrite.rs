@@ -0,0 +1,187 @@
+use axum::http::{header, HeaderMap, HeaderValue};
+
+use super::route;
+
+fn authority(url: &str) -> Option<String> {
+    let (_, rest) = url.split_once("://")?;
+    let end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+    Some(rest[..end].to_ascii_lowercase())
+}
+
+fn upstream_path<'a>(url: &'a str, upstream: &str) -> Option<&'a str> {
+    if authority(url)? != authority(upstream)? {
+        return None;
+    }
+    let (_, rest) = url.split_once("://")?;
+    let start = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+    Some(&rest[start..])
+}
+
+fn host_without_port(authority: &str) -> &str {
+    if authority.starts_with('[') {
+        return authority
+            .split_once(']')
+            .map_or(authority, |(addr, _)| &authority[..=addr.len()]);
+    }
+    authority
+        .rsplit_once(':')
+        .map_or(authority, |(name, _)| name)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rewriter {
+    upstream: String,
+    origin: Option<String>,
+    bot_prefix: Option<String>,
+}
+
+impl Rewriter {
+    pub fn new(upstream: &str, origin: Option<String>, bot_prefix: Option<String>) -> Self {
+        Self {
+            upstream: upstream.to_string(),
+            origin,
+            bot_prefix,
+        }
+    }
+
+    fn public_path(&self, path: &str) -> String {
+        let Some(prefix) = &self.bot_prefix else {
+            return path.to_string();
+        };
+        let page = route::parse(path);
+        let is_root = path.split(['?', '#']).next() == Some("/");
+        if page.bot.is_none() && (page.app.is_some() || is_root) {
+            let path = if is_root { &path[1..] } else { path };
+            format!("{prefix}{path}")
+        } else {
+            path.to_string()
+        }
+    }
+
+    fn location(&self, value: &str) -> Option<String> {
+        if let Some(path) = upstream_path(value, &self.upstream) {
+            let path = if path.is_empty() { "/" } else { path };
+            let path = self.public_path(path);
+            return Some(match &self.origin {
+                Some(origin) => format!("{origin}{path}"),
+                None => path,
+            });
+        }
+        if value.starts_with('/') && !value.starts_with("//") {
+            let path = self.public_path(value);
+            return (path != value).then_some(path);
+        }
+        None
+    }
+
+    fn set_cookie(&self, value: &str) -> String {
+        let public_host = self
+            .origin
+            .as_deref()
+            .and_then(authority)
+            .map(|a| host_without_port(&a).to_string());
+        let mut parts = value.split(';');
+        let mut cookie = parts.next().unwrap_or_default().trim().to_string();
+        for attribute in parts.map(str::trim).filter(|a| !a.is_empty()) {
+            let (name, attr_value) = attribute.split_once('=').unwrap_or((attribute, ""));
+            let rewritten = if name.trim().eq_ignore_ascii_case("domain") {
+                // A domain the public host is not in would make the browser
+                // drop the cookie; host-only scoping is what was meant.
+                let domain = attr_value
+                    .trim()
+                    .trim_start_matches('.')
+                    .to_ascii_lowercase();
+                public_host
+                    .as_deref()
+                    .filter(|host| *host == domain || host.ends_with(&format!(".{domain}")))
+                    .map(|_| attribute.to_string())
+            } else if name.trim().eq_ignore_ascii_case("path") {
+                Some(format!("Path={}", self.public_path(attr_value.trim())))
+            } else {
+                Some(attribute.to_string())
+            };
+            if let Some(attribute) = rewritten {
+                cookie.push_str("; ");
+                cookie.push_str(&attribute);
+            }
+        }
+        cookie
+    }
+
+    pub fn apply(&self, headers: &mut HeaderMap) {
+        for name in [header::LOCATION, header::CONTENT_LOCATION] {
+            let rewritten = headers
+                .get(&name)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| self.location(v))
+                .and_then(|v| HeaderValue::from_str(&v).ok());
+            if let Some(value) = rewritten {
+                headers.insert(name, value);
+            }
+        }
+
+        let cookies: Vec<HeaderValue> = headers
+            .get_all(header::SET_COOKIE)
+            .iter()
+            .map(|value| {
+                value
+                    .to_str()
+                    .ok()
+                    .and_then(|v| HeaderValue::from_str(&self.set_cookie(v)).ok())
+                    .unwrap_or_else(|| value.clone())
+            })
+            .collect();
+        if !cookies.is_empty() {
+            headers.remove(header::SET_COOKIE);
+            for cookie in cookies {
+                headers.append(header::SET_COOKIE, cookie);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Rewriter;
+
+    fn rewriter() -> Rewriter {
+        Rewriter::new(
+            "http://botserver:8080",
+            Some("https://bots.example.com".to_string()),
+            Some("/edu".to_string()),
+        )
+    }
+
+    #[test]
+    fn upstream_locations_map_to_public_origin() {
+        let r = rewriter();
+        assert_eq!(
+            r.location("http://BotServer:8080/chat/x?y=1").as_deref(),
+            Some("https://bots.example.com/edu/chat/x?y=1")
+        );
+        assert_eq!(
+            r.location("http://botserver:8080/api/files/1").as_deref(),
+            Some("https://bots.example.com/api/files/1")
+        );
+        assert_eq!(
+            r.location("http://botserver:8080").as_deref(),
+            Some("https://bots.example.com/edu")
+        );
+        assert_eq!(r.location("/drive").as_deref(), Some("/edu/drive"));
+        assert_eq!(r.location("/login"), None);
+        assert_eq!(r.location("https://idp.example.org/authorize"), None);
+    }
+
+    #[test]
+    fn cookie_domain_and_path_are_adjusted() {
+        let r = rewriter();
+        assert_eq!(
+            r.set_cookie("a=1; Domain=botserver; Path=/chat; HttpOnly"),
+            "a=1; Path=/edu/chat; HttpOnly"
+        );
+        assert_eq!(
+            r.set_cookie("a=1; Domain=.example.com; Path=/api"),
+            "a=1; Domain=.example.com; Path=/api"
+        );
+    }
+}
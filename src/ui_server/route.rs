@@ -0,0 +1,436 @@
+use serde::Serialize;
+
+const RESERVED_ROOTS: &[&str] = &[
+    "suite",
+    "shared",
+    "embedded",
+    "api",
+    "ui",
+    "ws",
+    "auth",
+    "apps",
+    "js",
+    "css",
+    "public",
+    "assets",
+    "partials",
+    "vendor",
+    "static",
+    "webfonts",
+    "health",
+    "minimal",
+    "login",
+    "logout",
+    "register",
+    "forgot-password",
+    "reset-password",
+];
+
+const BOT_PREFIXES: &[&str] = &["bot", "app"];
+
+const APPS: &[&str] = &[
+    "about",
+    "admin",
+    "analytics",
+    "attendant",
+    "bas-editor",
+    "billing",
+    "browser",
+    "calendar",
+    "campaigns",
+    "canvas",
+    "chat",
+    "crm",
+    "dashboards",
+    "designer",
+    "docs",
+    "drive",
+    "editor",
+    "goals",
+    "learn",
+    "lists",
+    "mail",
+    "meet",
+    "monitoring",
+    "paper",
+    "people",
+    "player",
+    "products",
+    "project",
+    "research",
+    "security",
+    "settings",
+    "sheet",
+    "slides",
+    "social",
+    "sources",
+    "tasks",
+    "templates",
+    "terminal",
+    "tickets",
+    "tools",
+    "vibe",
+    "video",
+    "workspace",
+];
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct UiRoute {
+    pub bot: Option<String>,
+    pub app: Option<String>,
+    pub resource: Vec<String>,
+}
+
+pub fn is_valid_name(segment: &str) -> bool {
+    !segment.is_empty()
+        && segment
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+fn is_bot_segment(segment: &str) -> bool {
+    is_valid_name(segment) && !RESERVED_ROOTS.contains(&segment) && !APPS.contains(&segment)
+}
+
+pub fn normalize(path: &str) -> Option<String> {
+    let decoded = urlencoding::decode(path).ok()?;
+    let mut parts = Vec::new();
+    for segment in decoded.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => return None,
+            s if s.contains(['\\', '\0']) => return None,
+            s => parts.push(s),
+        }
+    }
+    Some(format!("/{}", parts.join("/")))
+}
+
+fn segments(path: &str) -> Vec<&str> {
+    path.split(['?', '#'])
+        .next()
+        .unwrap_or_default()
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+fn with_app(bot: Option<String>, rest: &[&str]) -> UiRoute {
+    let (app, resource) = match rest.split_first() {
+        Some((app, resource)) if is_valid_name(app) => (
+            Some((*app).to_string()),
+            resource.iter().map(|s| (*s).to_string()).collect(),
+        ),
+        _ => (None, Vec::new()),
+    };
+    UiRoute { bot, app, resource }
+}
+
+pub fn parse(path: &str) -> UiRoute {
+    let parts = segments(path);
+    match parts.split_first() {
+        Some((first, rest)) if BOT_PREFIXES.contains(first) => match rest.split_first() {
+            Some((bot, rest)) if is_valid_name(bot) => with_app(Some((*bot).to_string()), rest),
+            _ => UiRoute::default(),
+        },
+        Some((first, rest)) if is_bot_segment(first) => with_app(Some((*first).to_string()), rest),
+        Some((first, _)) if APPS.contains(first) => with_app(None, &parts),
+        _ => UiRoute::default(),
+    }
+}
+
+pub fn parse_without_bot(path: &str) -> UiRoute {
+    let parts = segments(path);
+    match parts.first() {
+        Some(first) if RESERVED_ROOTS.contains(first) => UiRoute::default(),
+        _ => with_app(None, &parts),
+    }
+}
+
+pub fn without_bot(path: &str) -> String {
+    let parts = segments(path);
+    let skip = match parts.first() {
+        Some(first) if BOT_PREFIXES.contains(first) => 2,
+        Some(first) if is_bot_segment(first) => 1,
+        _ => 0,
+    };
+    format!("/{}", parts.get(skip..).unwrap_or_default().join("/"))
+}
+
+pub fn bot_prefix(path: &str) -> Option<String> {
+    let parts = segments(path);
+    match parts.as_slice() {
+        [prefix, bot, ..] if BOT_PREFIXES.contains(prefix) && is_valid_name(bot) => {
+            Some(format!("/{prefix}/{bot}"))
+        }
+        [first, ..] if is_bot_segment(first) => Some(format!("/{first}")),
+        _ => None,
+    }
+}
+
+pub fn asset_path(path: &str) -> Option<String> {
+    let path = without_bot(path);
+    let parts = segments(&path);
+    let file = parts.last()?;
+    if !file.contains('.') || parts.iter().any(|p| *p == "." || *p == "..") {
+        return None;
+    }
+    let relative = parts.join("/");
+    Some(match parts.as_slice() {
+        ["auth", "suite", rest @ ..] => format!("suite/{}", rest.join("/")),
+        [first, _, ..] if APPS.contains(first) => format!("suite/{relative}"),
+        _ => relative,
+    })
+}
+
+pub fn parse_ws(path: &str) -> UiRoute {
+    let parts = segments(path);
+    match parts.split_first() {
+        Some((&"ws", rest)) => match rest.first() {
+            Some(bot) if is_bot_segment(bot) => UiRoute {
+                bot: Some((*bot).to_string()),
+                ..UiRoute::default()
+            },
+            _ => UiRoute::default(),
+        },
+        _ => UiRoute::default(),
+    }
+}
+
+pub fn parse_apps(url: &str) -> Option<UiRoute> {
+    let path = match url.split_once("://") {
+        Some((_, rest)) => rest.find('/').map_or("/", |start| &rest[start..]),
+        None => url,
+    };
+    match segments(path).split_first() {
+        Some((&"apps", rest)) if !rest.is_empty() => Some(with_app(None, rest)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn route(bot: Option<&str>, app: Option<&str>, resource: &[&str]) -> UiRoute {
+        UiRoute {
+            bot: bot.map(str::to_string),
+            app: app.map(str::to_string),
+            resource: resource.iter().map(|s| (*s).to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn root_has_no_bot() {
+        assert_eq!(parse("/"), UiRoute::default());
+        assert_eq!(parse(""), UiRoute::default());
+    }
+
+    #[test]
+    fn bot_only() {
+        assert_eq!(parse("/edu"), route(Some("edu"), None, &[]));
+        assert_eq!(parse("/edu/"), route(Some("edu"), None, &[]));
+    }
+
+    #[test]
+    fn bot_and_app() {
+        assert_eq!(parse("/edu/chat"), route(Some("edu"), Some("chat"), &[]));
+    }
+
+    #[test]
+    fn bot_app_and_resource() {
+        assert_eq!(
+            parse("/edu/drive/reports/2024"),
+            route(Some("edu"), Some("drive"), &["reports", "2024"])
+        );
+    }
+
+    #[test]
+    fn unknown_app_after_bot_is_still_an_app() {
+        assert_eq!(
+            parse("/edu/custom-app/42"),
+            route(Some("edu"), Some("custom-app"), &["42"])
+        );
+    }
+
+    #[test]
+    fn app_first_has_no_bot() {
+        assert_eq!(parse("/chat/edu"), route(None, Some("chat"), &["edu"]));
+        assert_eq!(parse("/tasks"), route(None, Some("tasks"), &[]));
+    }
+
+    #[test]
+    fn explicit_bot_prefixes() {
+        assert_eq!(parse("/bot/cristo"), route(Some("cristo"), None, &[]));
+        assert_eq!(
+            parse("/bot/cristo/mail/inbox"),
+            route(Some("cristo"), Some("mail"), &["inbox"])
+        );
+        assert_eq!(parse("/app/edu"), route(Some("edu"), None, &[]));
+        assert_eq!(parse("/bot"), UiRoute::default());
+    }
+
+    #[test]
+    fn reserved_roots_have_no_bot() {
+        for path in [
+            "/suite",
+            "/api/x",
+            "/auth/login.html",
+            "/login",
+            "/register",
+            "/ws",
+        ] {
+            assert_eq!(parse(path), UiRoute::default(), "{path}");
+        }
+    }
+
+    #[test]
+    fn invalid_bot_names_are_rejected() {
+        assert_eq!(parse("/login.html"), UiRoute::default());
+        assert_eq!(parse("/<script>"), UiRoute::default());
+        assert_eq!(parse("/edu/<x>"), route(Some("edu"), None, &[]));
+    }
+
+    #[test]
+    fn query_and_fragment_are_ignored() {
+        assert_eq!(
+            parse("/edu/chat?x=1#top"),
+            route(Some("edu"), Some("chat"), &[])
+        );
+    }
+
+    #[test]
+    fn without_bot_starts_with_app() {
+        assert_eq!(
+            parse_without_bot("/drive/reports"),
+            route(None, Some("drive"), &["reports"])
+        );
+        assert_eq!(parse_without_bot("/"), UiRoute::default());
+        assert_eq!(parse_without_bot("/suite/chat"), UiRoute::default());
+    }
+
+    #[test]
+    fn bot_prefix_is_stripped() {
+        assert_eq!(without_bot("/edu/admin/users.html"), "/admin/users.html");
+        assert_eq!(without_bot("/bot/edu/chat"), "/chat");
+        assert_eq!(without_bot("/edu"), "/");
+        assert_eq!(without_bot("/admin/users.html"), "/admin/users.html");
+        assert_eq!(without_bot("/suite/admin/"), "/suite/admin");
+    }
+
+    #[test]
+    fn ui_directories_are_not_bots() {
+        assert_eq!(
+            without_bot("/shared/messageTypes.js"),
+            "/shared/messageTypes.js"
+        );
+        assert_eq!(
+            without_bot("/edu/shared/messageTypes.js"),
+            "/shared/messageTypes.js"
+        );
+        assert_eq!(
+            asset_path("/shared/messageTypes.js").as_deref(),
+            Some("shared/messageTypes.js")
+        );
+        assert_eq!(
+            asset_path("/edu/shared/messageTypes.js").as_deref(),
+            Some("shared/messageTypes.js")
+        );
+        assert_eq!(
+            asset_path("/embedded/index.html").as_deref(),
+            Some("embedded/index.html")
+        );
+        assert_eq!(bot_prefix("/shared/messageTypes.js"), None);
+        assert_eq!(parse("/embedded"), UiRoute::default());
+    }
+
+    #[test]
+    fn bot_prefix_as_written() {
+        assert_eq!(bot_prefix("/edu/chat").as_deref(), Some("/edu"));
+        assert_eq!(bot_prefix("/bot/edu/chat").as_deref(), Some("/bot/edu"));
+        assert_eq!(bot_prefix("/chat/edu"), None);
+        assert_eq!(bot_prefix("/api/x"), None);
+    }
+
+    #[test]
+    fn normalizes_paths() {
+        assert_eq!(
+            normalize("/admin/users.html").as_deref(),
+            Some("/admin/users.html")
+        );
+        assert_eq!(
+            normalize("/%61dmin/./users.html").as_deref(),
+            Some("/admin/users.html")
+        );
+        assert_eq!(
+            normalize("//suite//admin/").as_deref(),
+            Some("/suite/admin")
+        );
+        assert_eq!(
+            normalize("/admin%2Fusers.html").as_deref(),
+            Some("/admin/users.html")
+        );
+        assert_eq!(normalize("/").as_deref(), Some("/"));
+        assert_eq!(normalize("/suite/../admin"), None);
+        assert_eq!(normalize("/suite/%2e%2e/admin"), None);
+        assert_eq!(normalize("/admin%5cusers.html"), None);
+        assert_eq!(normalize("/admin/%ff.html"), None);
+    }
+
+    #[test]
+    fn asset_paths() {
+        assert_eq!(
+            asset_path("/suite/js/app.js").as_deref(),
+            Some("suite/js/app.js")
+        );
+        assert_eq!(
+            asset_path("/edu/suite/js/app.js").as_deref(),
+            Some("suite/js/app.js")
+        );
+        assert_eq!(
+            asset_path("/auth/suite/css/login.css").as_deref(),
+            Some("suite/css/login.css")
+        );
+        assert_eq!(
+            asset_path("/edu/drive/drive.html").as_deref(),
+            Some("suite/drive/drive.html")
+        );
+        assert_eq!(
+            asset_path("/crm/crm.css").as_deref(),
+            Some("suite/crm/crm.css")
+        );
+        assert_eq!(
+            asset_path("/public/themes/x.css?v=1").as_deref(),
+            Some("public/themes/x.css")
+        );
+        assert_eq!(asset_path("/edu/drive/reports"), None);
+        assert_eq!(asset_path("/edu"), None);
+        assert_eq!(asset_path("/suite/../../etc/passwd.txt"), None);
+    }
+
+    #[test]
+    fn ws_bot() {
+        assert_eq!(parse_ws("/ws"), UiRoute::default());
+        assert_eq!(parse_ws("/ws/edu"), route(Some("edu"), None, &[]));
+        assert_eq!(parse_ws("/ws/chat"), UiRoute::default());
+        assert_eq!(parse_ws("/edu"), UiRoute::default());
+    }
+
+    #[test]
+    fn apps_mount() {
+        assert_eq!(
+            parse_apps("/apps/crm-lite"),
+            Some(route(None, Some("crm-lite"), &[]))
+        );
+        assert_eq!(
+            parse_apps("/apps/crm-lite/api/items"),
+            Some(route(None, Some("crm-lite"), &["api", "items"]))
+        );
+        assert_eq!(
+            parse_apps("https://bots.example.com/apps/crm-lite/index.html?x=1"),
+            Some(route(None, Some("crm-lite"), &["index.html"]))
+        );
+        assert_eq!(parse_apps("/apps"), None);
+        assert_eq!(parse_apps("https://bots.example.com/edu/chat"), None);
+    }
+}
@@ -0,0 +1,356 @@
+use axum::{
+    body::Bytes,
+    extract::{Request, State},
+    http::{header, HeaderName, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use log::{info, warn};
+
+use super::html;
+use crate::shared::AppState;
+
+pub const REPORT_PATH: &str = "/api/csp-report";
+
+const DEFAULT_CSP: &str = "default-src 'self'; \
+    script-src 'nonce-{nonce}' 'strict-dynamic' 'self'; \
+    style-src 'self' 'unsafe-inline'; \
+    img-src 'self' data: blob: https:; \
+    font-src 'self' data:; \
+    connect-src 'self' ws: wss:; \
+    media-src 'self' blob: https:; \
+    worker-src 'self' blob:; \
+    object-src 'none'; \
+    base-uri 'self'; \
+    form-action 'self'; \
+    frame-ancestors {frame_ancestors}";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CspMode {
+    Enforce,
+    ReportOnly,
+    Off,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Upstream;
+
+#[derive(Debug)]
+pub struct SecurityHeaders {
+    enabled: bool,
+    csp_mode: CspMode,
+    csp: String,
+    static_headers: Vec<(HeaderName, HeaderValue)>,
+}
+
+fn env_or(name: &str, default: &str) -> String {
+    std::env::var(name)
+        .ok()
+        .filter(|v| !v.trim().is_empty())
+        .unwrap_or_else(|| default.to_string())
+}
+
+impl SecurityHeaders {
+    #[must_use]
+    pub fn from_env() -> Self {
+        let enabled = std::env::var("BOTUI_SECURITY_HEADERS").map_or(true, |v| v != "false");
+        let csp_mode = match std::env::var("BOTUI_CSP_MODE").as_deref() {
+            Ok("enforce") => CspMode::Enforce,
+            Ok("off") => CspMode::Off,
+            _ => CspMode::ReportOnly,
+        };
+
+        let frame_ancestors = env_or("BOTUI_FRAME_ANCESTORS", "'self'");
+        let report_uri = env_or("BOTUI_CSP_REPORT_URI", REPORT_PATH);
+        let mut csp = std::env::var("BOTUI_CSP")
+            .ok()
+            .filter(|v| !v.trim().is_empty())
+            .unwrap_or_else(|| DEFAULT_CSP.replace("{frame_ancestors}", &frame_ancestors));
+        if !csp.contains("report-uri") {
+            csp = format!(
+                "{}; report-uri {report_uri}",
+                csp.trim_end_matches([';', ' '])
+            );
+        }
+
+        let mut static_headers = vec![
+            ("x-content-type-options".to_string(), "nosniff".to_string()),
+            (
+                "referrer-policy".to_string(),
+                env_or("BOTUI_REFERRER_POLICY", "strict-origin-when-cross-origin"),
+            ),
+            (
+                "permissions-policy".to_string(),
+                env_or(
+                    "BOTUI_PERMISSIONS_POLICY",
+                    "camera=(self), microphone=(self), display-capture=(self), geolocation=(), payment=(), usb=()",
+                ),
+            ),
+        ];
+        let hsts = env_or("BOTUI_HSTS", "max-age=31536000; includeSubDomains");
+        if hsts != "off" {
+            static_headers.push(("strict-transport-security".to_string(), hsts));
+        }
+        // frame-ancestors is ignored in report-only policies and by old browsers
+        match frame_ancestors.as_str() {
+            "'self'" => {
+                static_headers.push(("x-frame-options".to_string(), "SAMEORIGIN".to_string()))
+            }
+            "'none'" => static_headers.push(("x-frame-options".to_string(), "DENY".to_string())),
+            _ => {}
+        }
+
+        let static_headers = static_headers
+            .into_iter()
+            .filter_map(|(name, value)| {
+                match (
+                    HeaderName::try_from(name.as_str()),
+                    HeaderValue::from_str(&value),
+                ) {
+                    (Ok(name), Ok(value)) => Some((name, value)),
+                    _ => {
+                        warn!("Ignoring invalid security header {name}: {value}");
+                        None
+                    }
+                }
+            })
+            .collect();
+
+        if enabled {
+            info!("Security headers enabled (CSP: {csp_mode:?})");
+        }
+
+        Self {
+            enabled,
+            csp_mode,
+            csp,
+            static_headers,
+        }
+    }
+
+    fn csp_header(&self) -> Option<HeaderName> {
+        match self.csp_mode {
+            CspMode::Enforce => Some(header::CONTENT_SECURITY_POLICY),
+            CspMode::ReportOnly => Some(header::CONTENT_SECURITY_POLICY_REPORT_ONLY),
+            CspMode::Off => None,
+        }
+    }
+}
+
+pub async fn apply(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let config = &state.security_headers;
+    if !config.enabled {
+        return next.run(request).await;
+    }
+
+    let is_head = request.method() == axum::http::Method::HEAD;
+    let mut response = next.run(request).await;
+    let is_upstream = response.extensions().get::<Upstream>().is_some();
+
+    if let Some(csp_header) = config.csp_header().filter(|_| !is_upstream) {
+        if !response.headers().contains_key(&csp_header) {
+            let nonce = STANDARD.encode(rand::random::<[u8; 16]>());
+            if !is_head && html::is_rewritable(&response) {
+                response =
+                    html::rewrite_response(response, |text| html::add_script_nonce(text, &nonce))
+                        .await;
+                // Each page carries its own nonce, so it must never be served from cache
+                response
+                    .headers_mut()
+                    .insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+            }
+            if let Ok(value) = HeaderValue::from_str(&config.csp.replace("{nonce}", &nonce)) {
+                response.headers_mut().insert(csp_header, value);
+            }
+        }
+    }
+
+    for (name, value) in &config.static_headers {
+        if !response.headers().contains_key(name) {
+            response.headers_mut().insert(name.clone(), value.clone());
+        }
+    }
+
+    response
+}
+
+fn violations(value: &serde_json::Value) -> Vec<[String; 3]> {
+    let reports = match value {
+        serde_json::Value::Array(batch) => batch.iter().filter_map(|r| r.get("body")).collect(),
+        other => vec![other.get("csp-report").unwrap_or(other)],
+    };
+
+    reports
+        .into_iter()
+        .take(20)
+        .map(|report| {
+            let field = |keys: &[&str]| {
+                keys.iter()
+                    .find_map(|key| report.get(*key).and_then(|v| v.as_str()))
+                    .unwrap_or("-")
+                    .to_string()
+            };
+            [
+                field(&["violated-directive", "effectiveDirective"]),
+                field(&["blocked-uri", "blockedURL"]),
+                field(&["document-uri", "documentURL"]),
+            ]
+        })
+        .collect()
+}
+
+pub async fn report(body: Bytes) -> impl IntoResponse {
+    let Ok(value) = serde_json::from_slice::<serde_json::Value>(&body) else {
+        return StatusCode::BAD_REQUEST;
+    };
+
+    for [directive, blocked, document] in violations(&value) {
+        warn!("CSP:VIOLATION: {directive} blocked {blocked} on {document}");
+    }
+
+    StatusCode::NO_CONTENT
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, middleware, response::Html, routing::get, Router};
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    const PAGE: &str = r#"<html><head><script src="/a.js"></script></head></html>"#;
+
+    async fn get_page(mode: CspMode, path: &str) -> (Response, String) {
+        let mut state = AppState::new();
+        state.security_headers = Arc::new(SecurityHeaders {
+            enabled: true,
+            csp_mode: mode,
+            csp: "script-src 'nonce-{nonce}'".to_string(),
+            static_headers: vec![(
+                HeaderName::from_static("x-content-type-options"),
+                HeaderValue::from_static("nosniff"),
+            )],
+        });
+        let app = Router::new()
+            .route("/page", get(|| async { Html(PAGE) }))
+            .route(
+                "/proxied",
+                get(|| async {
+                    let mut response = Html(PAGE).into_response();
+                    response.extensions_mut().insert(Upstream);
+                    response
+                }),
+            )
+            .layer(middleware::from_fn_with_state(state.clone(), apply))
+            .with_state(state);
+
+        let request = Request::get(path).body(Body::empty()).unwrap_or_default();
+        let Ok(response) = app.oneshot(request).await;
+        let (parts, body) = response.into_parts();
+        let bytes = axum::body::to_bytes(body, usize::MAX)
+            .await
+            .unwrap_or_default();
+        let text = String::from_utf8_lossy(&bytes).into_owned();
+        (Response::from_parts(parts, Body::empty()), text)
+    }
+
+    fn header_str(response: &Response, name: HeaderName) -> Option<&str> {
+        response.headers().get(name).and_then(|v| v.to_str().ok())
+    }
+
+    #[tokio::test]
+    async fn enforce_injects_the_header_nonce() {
+        let (response, body) = get_page(CspMode::Enforce, "/page").await;
+        let csp = header_str(&response, header::CONTENT_SECURITY_POLICY).unwrap_or_default();
+        let nonce = csp
+            .strip_prefix("script-src 'nonce-")
+            .and_then(|rest| rest.strip_suffix('\''))
+            .unwrap_or_default();
+        assert_eq!(nonce.len(), 24);
+        assert!(body.contains(&format!(r#"<script nonce="{nonce}" src="/a.js">"#)));
+        assert_eq!(
+            header_str(&response, header::CACHE_CONTROL),
+            Some("no-store")
+        );
+        assert_eq!(
+            header_str(&response, header::X_CONTENT_TYPE_OPTIONS),
+            Some("nosniff")
+        );
+
+        let (again, _) = get_page(CspMode::Enforce, "/page").await;
+        assert_ne!(
+            header_str(&again, header::CONTENT_SECURITY_POLICY),
+            Some(csp)
+        );
+    }
+
+    #[tokio::test]
+    async fn report_only_uses_its_own_header() {
+        let (response, body) = get_page(CspMode::ReportOnly, "/page").await;
+        assert!(
+            header_str(&response, header::CONTENT_SECURITY_POLICY_REPORT_ONLY)
+                .is_some_and(|csp| csp.starts_with("script-src 'nonce-"))
+        );
+        assert!(!response
+            .headers()
+            .contains_key(header::CONTENT_SECURITY_POLICY));
+        assert!(body.contains("<script nonce=\""));
+    }
+
+    #[tokio::test]
+    async fn off_leaves_pages_alone() {
+        let (response, body) = get_page(CspMode::Off, "/page").await;
+        assert!(!response
+            .headers()
+            .contains_key(header::CONTENT_SECURITY_POLICY));
+        assert!(!response
+            .headers()
+            .contains_key(header::CONTENT_SECURITY_POLICY_REPORT_ONLY));
+        assert_eq!(body, PAGE);
+        assert_eq!(
+            header_str(&response, header::X_CONTENT_TYPE_OPTIONS),
+            Some("nosniff")
+        );
+    }
+
+    #[tokio::test]
+    async fn proxied_pages_are_not_nonced() {
+        let (response, body) = get_page(CspMode::Enforce, "/proxied").await;
+        assert!(!response
+            .headers()
+            .contains_key(header::CONTENT_SECURITY_POLICY));
+        assert_eq!(body, PAGE);
+    }
+
+    #[tokio::test]
+    async fn reports_are_parsed_in_both_formats() {
+        let legacy = serde_json::json!({ "csp-report": {
+            "violated-directive": "script-src-elem",
+            "blocked-uri": "inline",
+            "document-uri": "https://bots.example.com/edu",
+        }});
+        assert_eq!(
+            violations(&legacy),
+            [["script-src-elem", "inline", "https://bots.example.com/edu"].map(String::from)]
+        );
+
+        let batch = serde_json::json!([
+            { "type": "csp-violation", "body": { "effectiveDirective": "img-src", "blockedURL": "https://x.test/a.png" } },
+            { "type": "deprecation" },
+        ]);
+        assert_eq!(
+            violations(&batch),
+            [["img-src", "https://x.test/a.png", "-"].map(String::from)]
+        );
+
+        let flood = serde_json::Value::Array(vec![serde_json::json!({ "body": {} }); 50]);
+        assert_eq!(violations(&flood).len(), 20);
+
+        let status = |body: &'static str| async move {
+            report(Bytes::from(body)).await.into_response().status()
+        };
+        assert_eq!(status("{}").await, StatusCode::NO_CONTENT);
+        assert_eq!(status("not json").await, StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,634 @@
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use axum::{
+    body::Body,
+    http::{header, HeaderMap, HeaderValue, Response},
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::coalesce::Coalesce;
+use super::cookies::{self, CookieOptions};
+use super::upstream::Upstream;
+
+pub const ACTIVE_COOKIE: &str = "gb_session_active";
+
+const LOGIN_PATHS: &[&str] = &["/api/auth/login", "/api/auth/2fa/verify"];
+pub const REFRESH_PATH: &str = "/api/auth/refresh";
+pub const LOGOUT_PATH: &str = "/api/auth/logout";
+
+const NONCE_LEN: usize = 12;
+const REFRESH_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Tokens {
+    access_token: String,
+    refresh_token: Option<String>,
+    expires_at: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    identity: Option<serde_json::Value>,
+}
+
+impl Tokens {
+    fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|at| at <= now_secs() + 5)
+    }
+}
+
+#[derive(Debug)]
+struct Session {
+    sealed: Vec<u8>,
+    expires_at: u64,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+fn hash_id(id: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(id.as_bytes()))
+}
+
+pub fn is_login_path(path: &str) -> bool {
+    LOGIN_PATHS.contains(&path)
+}
+
+pub struct SessionStore {
+    cipher: Aes256Gcm,
+    sessions: RwLock<HashMap<String, Session>>,
+    cookie_name: String,
+    ttl: Duration,
+    cookie_options: CookieOptions,
+    refreshes: Coalesce<Option<String>>,
+}
+
+impl std::fmt::Debug for SessionStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SessionStore")
+            .field("cookie_name", &self.cookie_name)
+            .field("ttl", &self.ttl)
+            .field("cookie_options", &self.cookie_options)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SessionStore {
+    #[must_use]
+    pub fn from_env() -> Option<Self> {
+        let secret = std::env::var("BOTUI_SESSION_KEY")
+            .ok()
+            .filter(|k| !k.is_empty())?;
+        if secret.len() < 32 {
+            warn!("BOTUI_SESSION_KEY is shorter than 32 characters; use a longer random secret");
+        }
+        let key = Sha256::digest(secret.as_bytes());
+
+        let cookie_name =
+            std::env::var("BOTUI_SESSION_COOKIE").unwrap_or_else(|_| "gb_session".to_string());
+        let ttl = std::env::var("BOTUI_SESSION_TTL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(86_400);
+        let same_site = match std::env::var("BOTUI_SESSION_SAMESITE").as_deref() {
+            Ok("Strict" | "strict") => "Strict",
+            _ => "Lax",
+        };
+        let secure = std::env::var("BOTUI_COOKIE_SECURE").map_or(true, |v| v != "false");
+
+        info!("BFF sessions enabled (cookie '{cookie_name}', ttl {ttl}s, SameSite={same_site})");
+
+        Some(Self {
+            cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)),
+            sessions: RwLock::new(HashMap::new()),
+            cookie_name,
+            ttl: Duration::from_secs(ttl),
+            cookie_options: CookieOptions {
+                http_only: true,
+                secure,
+                same_site,
+            },
+            refreshes: Coalesce::default(),
+        })
+    }
+
+    pub fn cookie_name(&self) -> &str {
+        &self.cookie_name
+    }
+
+    pub fn session_id(&self, headers: &HeaderMap) -> Option<String> {
+        let id = cookies::get(headers, &self.cookie_name)?;
+        let sessions = self.sessions.read().ok()?;
+        sessions
+            .get(&hash_id(&id))
+            .filter(|s| s.expires_at > now_secs())
+            .map(|_| id)
+    }
+
+    fn seal(&self, id: &str, tokens: &Tokens) -> Option<Vec<u8>> {
+        let plain = serde_json::to_vec(tokens).ok()?;
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let aad = hash_id(id);
+        let mut sealed = nonce.to_vec();
+        sealed.extend(
+            self.cipher
+                .encrypt(
+                    &nonce,
+                    Payload {
+                        msg: &plain,
+                        aad: aad.as_bytes(),
+                    },
+                )
+                .ok()?,
+        );
+        Some(sealed)
+    }
+
+    fn open(&self, id: &str) -> Option<Tokens> {
+        let key = hash_id(id);
+        let sessions = self.sessions.read().ok()?;
+        let session = sessions.get(&key).filter(|s| s.expires_at > now_secs())?;
+        if session.sealed.len() <= NONCE_LEN {
+            return None;
+        }
+        let (nonce, ciphertext) = session.sealed.split_at(NONCE_LEN);
+        let plain = self
+            .cipher
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: key.as_bytes(),
+                },
+            )
+            .ok()?;
+        serde_json::from_slice(&plain).ok()
+    }
+
+    fn put(&self, id: &str, tokens: &Tokens, expires_at: u64) {
+        let Some(sealed) = self.seal(id, tokens) else {
+            error!("Failed to seal session tokens");
+            return;
+        };
+        if let Ok(mut sessions) = self.sessions.write() {
+            let now = now_secs();
+            sessions.retain(|_, s| s.expires_at > now);
+            sessions.insert(hash_id(id), Session { sealed, expires_at });
+        }
+    }
+
+    pub fn remove(&self, id: &str) {
+        if let Ok(mut sessions) = self.sessions.write() {
+            sessions.remove(&hash_id(id));
+        }
+    }
+
+    pub fn refresh_token(&self, id: &str) -> Option<String> {
+        self.open(id)?.refresh_token
+    }
+
+    pub fn identity(&self, id: &str) -> Option<serde_json::Value> {
+        self.open(id)?.identity
+    }
+
+    pub async fn access_token(&self, id: &str, upstream: &Upstream) -> Option<String> {
+        let tokens = self.open(id)?;
+        if tokens.access_token.is_empty() {
+            return None;
+        }
+        if !tokens.is_expired() {
+            return Some(tokens.access_token);
+        }
+        // Concurrent requests of one session share a single refresh
+        self.refreshes
+            .run(&hash_id(id), || self.refresh(id, upstream))
+            .await
+    }
+
+    async fn refresh(&self, id: &str, upstream: &Upstream) -> Option<String> {
+        let tokens = self.open(id)?;
+        if !tokens.is_expired() {
+            return Some(tokens.access_token);
+        }
+
+        let refresh_token = tokens.refresh_token.clone()?;
+        match Self::refresh_upstream(upstream, &refresh_token).await {
+            Some(value) => {
+                let refreshed = Self::tokens_from(&value, Some(refresh_token))?;
+                let expires_at = self.session_expiry(id);
+                self.put(id, &refreshed, expires_at);
+                debug!("Refreshed access token for BFF session");
+                Some(refreshed.access_token)
+            }
+            None => Some(tokens.access_token),
+        }
+    }
+
+    fn session_expiry(&self, id: &str) -> u64 {
+        self.sessions
+            .read()
+            .ok()
+            .and_then(|s| s.get(&hash_id(id)).map(|s| s.expires_at))
+            .unwrap_or_else(|| now_secs() + self.ttl.as_secs())
+    }
+
+    async fn refresh_upstream(
+        upstream: &Upstream,
+        refresh_token: &str,
+    ) -> Option<serde_json::Value> {
+        let response = upstream
+            .client()
+            .post(format!("{}{REFRESH_PATH}", upstream.base_url()))
+            .timeout(REFRESH_TIMEOUT)
+            .json(&serde_json::json!({ "refresh_token": refresh_token }))
+            .send()
+            .await
+            .map_err(|e| warn!("Session token refresh failed: {e}"))
+            .ok()?;
+        if !response.status().is_success() {
+            warn!("Session token refresh rejected: {}", response.status());
+            return None;
+        }
+        response.json().await.ok()
+    }
+
+    fn tokens_from(value: &serde_json::Value, previous_refresh: Option<String>) -> Option<Tokens> {
+        let access_token = value.get("access_token")?.as_str()?.to_string();
+        let refresh_token = value
+            .get("refresh_token")
+            .and_then(|v| v.as_str())
+            .map(str::to_string)
+            .or(previous_refresh);
+        let expires_at = value
+            .get("expires_in")
+            .and_then(serde_json::Value::as_u64)
+            .map(|secs| now_secs() + secs);
+        Some(Tokens {
+            access_token,
+            refresh_token,
+            expires_at,
+            identity: None,
+        })
+    }
+
+    fn new_id(&self, previous: Option<&str>) -> String {
+        if let Some(old) = previous {
+            self.remove(old);
+        }
+        URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>())
+    }
+
+    fn set_cookies(&self, id: &str, expires_at: u64, headers: &mut HeaderMap) {
+        let max_age = expires_at.saturating_sub(now_secs());
+        let session_cookie = cookies::set(&self.cookie_name, id, max_age, self.cookie_options);
+        let active_cookie = cookies::set(
+            ACTIVE_COOKIE,
+            "1",
+            max_age,
+            CookieOptions {
+                http_only: false,
+                ..self.cookie_options
+            },
+        );
+        for cookie in [session_cookie, active_cookie] {
+            if let Ok(value) = HeaderValue::from_str(&cookie) {
+                headers.append(header::SET_COOKIE, value);
+            }
+        }
+    }
+
+    pub fn start(
+        &self,
+        previous: Option<&str>,
+        grant: Option<&serde_json::Value>,
+        identity: Option<serde_json::Value>,
+        headers: &mut HeaderMap,
+    ) -> bool {
+        let tokens = match grant {
+            Some(grant) => Self::tokens_from(grant, None),
+            None => Some(Tokens {
+                access_token: String::new(),
+                refresh_token: None,
+                expires_at: None,
+                identity: None,
+            }),
+        };
+        let Some(mut tokens) = tokens else {
+            return false;
+        };
+        tokens.identity = identity;
+
+        let id = self.new_id(previous);
+        let expires_at = now_secs() + self.ttl.as_secs();
+        self.put(&id, &tokens, expires_at);
+        self.set_cookies(&id, expires_at, headers);
+        true
+    }
+
+    pub async fn capture(
+        &self,
+        path: &str,
+        response: Response<Body>,
+        previous: Option<&str>,
+    ) -> Response<Body> {
+        if !response.status().is_success() {
+            return response;
+        }
+
+        let (mut parts, body) = response.into_parts();
+        let Ok(bytes) = axum::body::to_bytes(body, usize::MAX).await else {
+            error!("Failed to read auth response body");
+            return Response::from_parts(parts, Body::empty());
+        };
+
+        let Ok(mut value) = serde_json::from_slice::<serde_json::Value>(&bytes) else {
+            return Response::from_parts(parts, Body::from(bytes));
+        };
+
+        // A refresh keeps its session; a login always gets a fresh id.
+        let is_refresh = path == REFRESH_PATH && previous.is_some();
+        let previous_refresh = previous
+            .filter(|_| is_refresh)
+            .and_then(|id| self.refresh_token(id));
+        let Some(tokens) = Self::tokens_from(&value, previous_refresh) else {
+            return Response::from_parts(parts, Body::from(bytes));
+        };
+        let id = match previous {
+            Some(id) if is_refresh => id.to_string(),
+            _ => self.new_id(previous),
+        };
+        let expires_at = if is_refresh {
+            self.session_expiry(&id)
+        } else {
+            now_secs() + self.ttl.as_secs()
+        };
+        self.put(&id, &tokens, expires_at);
+
+        if let Some(object) = value.as_object_mut() {
+            object.remove("access_token");
+            object.remove("refresh_token");
+            object.insert("session".to_string(), serde_json::json!("cookie"));
+        }
+
+        self.set_cookies(&id, expires_at, &mut parts.headers);
+
+        parts.headers.remove(header::CONTENT_LENGTH);
+        parts.headers.remove(header::TRANSFER_ENCODING);
+        Response::from_parts(parts, Body::from(value.to_string()))
+    }
+
+    pub fn end(&self, id: Option<&str>, response: &mut Response<Body>) {
+        if let Some(id) = id {
+            self.remove(id);
+        }
+        let inactive = CookieOptions {
+            http_only: false,
+            ..self.cookie_options
+        };
+        for cookie in [
+            cookies::clear(&self.cookie_name, self.cookie_options),
+            cookies::clear(ACTIVE_COOKIE, inactive),
+        ] {
+            if let Ok(value) = HeaderValue::from_str(&cookie) {
+                response.headers_mut().append(header::SET_COOKIE, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{http::StatusCode, routing::post, Json, Router};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    fn store() -> SessionStore {
+        let key = Sha256::digest(b"test-session-key");
+        SessionStore {
+            cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)),
+            sessions: RwLock::new(HashMap::new()),
+            cookie_name: "gb_session".to_string(),
+            ttl: Duration::from_secs(3600),
+            cookie_options: CookieOptions {
+                http_only: true,
+                secure: true,
+                same_site: "Lax",
+            },
+            refreshes: Coalesce::default(),
+        }
+    }
+
+    fn upstream(base_url: &str) -> Option<Upstream> {
+        crate::ui_server::upstream::Upstreams::from_env(base_url).select(REFRESH_PATH, None, None)
+    }
+
+    fn session_cookie(headers: &HeaderMap) -> Option<String> {
+        headers
+            .get_all(header::SET_COOKIE)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .find_map(|v| v.strip_prefix("gb_session="))
+            .and_then(|v| v.split(';').next())
+            .map(str::to_string)
+    }
+
+    fn request_with(id: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if let Ok(value) = HeaderValue::from_str(&format!("gb_session={id}")) {
+            headers.insert(header::COOKIE, value);
+        }
+        headers
+    }
+
+    async fn login(store: &SessionStore, path: &str, previous: Option<&str>) -> (String, String) {
+        let body = serde_json::json!({
+            "access_token": "access-1",
+            "refresh_token": "refresh-1",
+            "expires_in": 600,
+            "user": "ana",
+        });
+        let response = Response::new(Body::from(body.to_string()));
+        let response = store.capture(path, response, previous).await;
+        let id = session_cookie(response.headers()).unwrap_or_default();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap_or_default();
+        (id, String::from_utf8_lossy(&bytes).into_owned())
+    }
+
+    #[tokio::test]
+    async fn capture_keeps_tokens_out_of_the_browser() {
+        let store = store();
+        let (id, body) = login(&store, "/api/auth/login", None).await;
+
+        assert!(!id.is_empty());
+        assert!(!body.contains("access-1") && !body.contains("refresh-1"));
+        assert!(body.contains(r#""session":"cookie""#));
+        assert_eq!(store.session_id(&request_with(&id)), Some(id.clone()));
+        assert_eq!(store.session_id(&request_with("forged")), None);
+        assert_eq!(store.refresh_token(&id).as_deref(), Some("refresh-1"));
+        let Some(upstream) = upstream("http://unused") else {
+            return;
+        };
+        assert_eq!(
+            store.access_token(&id, &upstream).await.as_deref(),
+            Some("access-1")
+        );
+    }
+
+    #[tokio::test]
+    async fn login_rotates_the_session_and_refresh_keeps_it() {
+        let store = store();
+        let (first, _) = login(&store, "/api/auth/login", None).await;
+
+        let (refreshed, _) = login(&store, REFRESH_PATH, Some(&first)).await;
+        assert_eq!(refreshed, first);
+
+        let (second, _) = login(&store, "/api/auth/login", Some(&first)).await;
+        assert_ne!(second, first);
+        assert_eq!(store.session_id(&request_with(&first)), None);
+        assert!(store.session_id(&request_with(&second)).is_some());
+    }
+
+    #[tokio::test]
+    async fn login_never_inherits_the_previous_refresh_token() {
+        let store = store();
+        let (first, _) = login(&store, "/api/auth/login", None).await;
+        assert_eq!(store.refresh_token(&first).as_deref(), Some("refresh-1"));
+
+        let body = serde_json::json!({ "access_token": "access-b", "expires_in": 600 });
+        let response = Response::new(Body::from(body.to_string()));
+        let response = store
+            .capture("/api/auth/login", response, Some(&first))
+            .await;
+        let second = session_cookie(response.headers()).unwrap_or_default();
+        assert!(!second.is_empty() && second != first);
+        assert_eq!(store.refresh_token(&second), None);
+
+        let body = serde_json::json!({ "access_token": "access-c", "expires_in": 600 });
+        let (third, _) = login(&store, "/api/auth/login", None).await;
+        let response = Response::new(Body::from(body.to_string()));
+        let response = store.capture(REFRESH_PATH, response, Some(&third)).await;
+        assert_eq!(
+            session_cookie(response.headers()).as_deref(),
+            Some(third.as_str())
+        );
+        assert_eq!(store.refresh_token(&third).as_deref(), Some("refresh-1"));
+    }
+
+    #[test]
+    fn sealed_tokens_are_bound_to_their_session() {
+        let store = store();
+        let mut headers = HeaderMap::new();
+        let grant = serde_json::json!({ "access_token": "a" });
+        assert!(store.start(None, Some(&grant), None, &mut headers));
+        let id = session_cookie(&headers).unwrap_or_default();
+
+        let sealed = store
+            .sessions
+            .read()
+            .ok()
+            .and_then(|s| s.get(&hash_id(&id)).map(|s| s.sealed.clone()))
+            .unwrap_or_default();
+        if let Ok(mut sessions) = store.sessions.write() {
+            sessions.insert(
+                hash_id("other"),
+                Session {
+                    sealed,
+                    expires_at: now_secs() + 60,
+                },
+            );
+        }
+        assert!(store.open(&id).is_some());
+        assert!(store.open("other").is_none());
+    }
+
+    #[tokio::test]
+    async fn concurrent_requests_share_one_refresh() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let app = Router::new().route(
+            REFRESH_PATH,
+            post(move || {
+                let counter = counter.clone();
+                async move {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    (
+                        StatusCode::OK,
+                        Json(serde_json::json!({ "access_token": "fresh", "expires_in": 600 })),
+                    )
+                }
+            }),
+        );
+        let Ok(listener) = tokio::net::TcpListener::bind("127.0.0.1:0").await else {
+            return;
+        };
+        let Ok(addr) = listener.local_addr() else {
+            return;
+        };
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        let base_url = format!("http://{addr}");
+
+        let store = Arc::new(store());
+        let mut headers = HeaderMap::new();
+        let grant = serde_json::json!({
+            "access_token": "stale",
+            "refresh_token": "r",
+            "expires_in": 0,
+        });
+        store.start(None, Some(&grant), None, &mut headers);
+        let id = session_cookie(&headers).unwrap_or_default();
+
+        let tasks: Vec<_> = (0..5)
+            .map(|_| {
+                let (store, id, base_url) = (store.clone(), id.clone(), base_url.clone());
+                tokio::spawn(async move {
+                    let upstream = upstream(&base_url)?;
+                    store.access_token(&id, &upstream).await
+                })
+            })
+            .collect();
+        for task in tasks {
+            assert_eq!(task.await.ok().flatten().as_deref(), Some("fresh"));
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(store.refresh_token(&id).as_deref(), Some("r"));
+    }
+
+    #[test]
+    fn end_expires_both_cookies() {
+        let store = store();
+        let mut headers = HeaderMap::new();
+        store.start(
+            None,
+            None,
+            Some(serde_json::json!({ "sub": "ana" })),
+            &mut headers,
+        );
+        let id = session_cookie(&headers).unwrap_or_default();
+        assert_eq!(
+            store.identity(&id),
+            Some(serde_json::json!({ "sub": "ana" }))
+        );
+
+        let mut response = Response::new(Body::empty());
+        store.end(Some(&id), &mut response);
+        let cleared: Vec<_> = response
+            .headers()
+            .get_all(header::SET_COOKIE)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .collect();
+        assert_eq!(cleared.len(), 2);
+        assert!(cleared.iter().all(|c| c.contains("Max-Age=0")));
+        assert_eq!(store.identity(&id), None);
+    }
+}
@@ -0,0 +1,298 @@
+use axum::{
+    body::{Body, Bytes},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use std::{io::SeekFrom, path::Path, time::UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ByteRange {
+    Full,
+    Partial { start: u64, end: u64 },
+    Unsatisfiable,
+}
+
+fn parse_range(value: &str, len: u64) -> ByteRange {
+    let Some(spec) = value.trim().strip_prefix("bytes=") else {
+        return ByteRange::Full;
+    };
+
+    // Multipart byteranges are not worth the complexity here; ignoring the
+    // header and sending the whole representation is allowed by RFC 9110.
+    if spec.contains(',') {
+        return ByteRange::Full;
+    }
+
+    let Some((first, last)) = spec.split_once('-') else {
+        return ByteRange::Full;
+    };
+    let (first, last) = (first.trim(), last.trim());
+
+    if first.is_empty() {
+        let Ok(suffix) = last.parse::<u64>() else {
+            return ByteRange::Full;
+        };
+        if suffix == 0 || len == 0 {
+            return ByteRange::Unsatisfiable;
+        }
+        return ByteRange::Partial {
+            start: len.saturating_sub(suffix),
+            end: len - 1,
+        };
+    }
+
+    let Ok(start) = first.parse::<u64>() else {
+        return ByteRange::Full;
+    };
+    if start >= len {
+        return ByteRange::Unsatisfiable;
+    }
+
+    let end = if last.is_empty() {
+        len - 1
+    } else {
+        match last.parse::<u64>() {
+            Ok(end) if end >= start => end.min(len - 1),
+            _ => return ByteRange::Full,
+        }
+    };
+
+    ByteRange::Partial { start, end }
+}
+
+fn header_str(headers: &HeaderMap, name: header::HeaderName) -> Option<&str> {
+    headers.get(name).and_then(|v| v.to_str().ok())
+}
+
+fn etag_matches(list: &str, etag: &str) -> bool {
+    list.split(',')
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == "*" || tag == etag)
+}
+
+fn requested_range(headers: &HeaderMap, etag: &str, len: u64) -> ByteRange {
+    let Some(range) = header_str(headers, header::RANGE) else {
+        return ByteRange::Full;
+    };
+
+    if let Some(if_range) = header_str(headers, header::IF_RANGE) {
+        if if_range.trim() != etag {
+            return ByteRange::Full;
+        }
+    }
+
+    parse_range(range, len)
+}
+
+fn build_response<F>(headers: &HeaderMap, mime: &str, etag: &str, len: u64, body: F) -> Response
+where
+    F: FnOnce(u64, u64) -> Option<Body>,
+{
+    if header_str(headers, header::IF_NONE_MATCH).is_some_and(|list| etag_matches(list, etag)) {
+        return (StatusCode::NOT_MODIFIED, [(header::ETAG, etag.to_string())]).into_response();
+    }
+
+    let builder = Response::builder()
+        .header(header::CONTENT_TYPE, mime)
+        .header(header::ACCEPT_RANGES, "bytes")
+        .header(header::ETAG, etag);
+
+    let result = match requested_range(headers, etag, len) {
+        ByteRange::Full => match body(0, len) {
+            Some(body) => builder
+                .status(StatusCode::OK)
+                .header(header::CONTENT_LENGTH, len)
+                .body(body),
+            None => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        },
+        ByteRange::Partial { start, end } => match body(start, end - start + 1) {
+            Some(body) => builder
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(header::CONTENT_RANGE, format!("bytes {start}-{end}/{len}"))
+                .header(header::CONTENT_LENGTH, end - start + 1)
+                .body(body),
+            None => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        },
+        ByteRange::Unsatisfiable => builder
+            .status(StatusCode::RANGE_NOT_SATISFIABLE)
+            .header(header::CONTENT_RANGE, format!("bytes */{len}"))
+            .body(Body::empty()),
+    };
+
+    result.unwrap_or_else(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())
+}
+
+pub async fn serve_file(headers: &HeaderMap, path: &Path) -> Option<Response> {
+    let mut file = tokio::fs::File::open(path).await.ok()?;
+    let metadata = file.metadata().await.ok()?;
+    if !metadata.is_file() {
+        return None;
+    }
+
+    let len = metadata.len();
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs());
+    let etag = format!("\"{len:x}-{modified:x}\"");
+    let mime = mime_guess::from_path(path).first_or_octet_stream();
+
+    let start = match requested_range(headers, &etag, len) {
+        ByteRange::Partial { start, .. } => start,
+        ByteRange::Full | ByteRange::Unsatisfiable => 0,
+    };
+    if start > 0 {
+        file.seek(SeekFrom::Start(start)).await.ok()?;
+    }
+
+    Some(build_response(
+        headers,
+        mime.as_ref(),
+        &etag,
+        len,
+        |_, count| Some(Body::from_stream(ReaderStream::new(file.take(count)))),
+    ))
+}
+
+#[cfg(feature = "embed-ui")]
+pub fn serve_embedded(
+    headers: &HeaderMap,
+    asset_path: &str,
+    file: rust_embed::EmbeddedFile,
+) -> Response {
+    let etag = format!(
+        "\"{}\"",
+        file.metadata
+            .sha256_hash()
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect::<String>()
+    );
+    let mime = mime_guess::from_path(asset_path).first_or_octet_stream();
+    let data = match file.data {
+        std::borrow::Cow::Borrowed(bytes) => axum::body::Bytes::from_static(bytes),
+        std::borrow::Cow::Owned(bytes) => axum::body::Bytes::from(bytes),
+    };
+    serve_bytes(headers, mime.as_ref(), &etag, data)
+}
+
+pub fn serve_bytes(headers: &HeaderMap, mime: &str, etag: &str, data: Bytes) -> Response {
+    let len = data.len() as u64;
+    build_response(headers, mime, etag, len, |start, count| {
+        let start = usize::try_from(start).ok()?;
+        let end = start.checked_add(usize::try_from(count).ok()?)?;
+        Some(Body::from(data.slice(start..end)))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    #[test]
+    fn parses_single_ranges() {
+        assert_eq!(
+            parse_range("bytes=0-99", 1000),
+            ByteRange::Partial { start: 0, end: 99 }
+        );
+        assert_eq!(
+            parse_range("bytes=900-", 1000),
+            ByteRange::Partial {
+                start: 900,
+                end: 999
+            }
+        );
+        assert_eq!(
+            parse_range("bytes=990-5000", 1000),
+            ByteRange::Partial {
+                start: 990,
+                end: 999
+            }
+        );
+        assert_eq!(
+            parse_range("bytes=-100", 1000),
+            ByteRange::Partial {
+                start: 900,
+                end: 999
+            }
+        );
+        assert_eq!(
+            parse_range("bytes=-5000", 1000),
+            ByteRange::Partial { start: 0, end: 999 }
+        );
+    }
+
+    #[test]
+    fn handles_unsatisfiable_and_ignored_ranges() {
+        assert_eq!(parse_range("bytes=1000-", 1000), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range("bytes=-0", 1000), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range("bytes=0-", 0), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range("bytes=-10", 0), ByteRange::Unsatisfiable);
+
+        for ignored in [
+            "items=0-10",
+            "bytes=0-10,20-30",
+            "bytes=10",
+            "bytes=20-10",
+            "bytes=a-b",
+            "bytes=0-18446744073709551616",
+            "bytes=-18446744073709551616",
+        ] {
+            assert_eq!(parse_range(ignored, 1000), ByteRange::Full, "{ignored}");
+        }
+        assert_eq!(
+            parse_range("bytes=0-18446744073709551615", 1000),
+            ByteRange::Partial { start: 0, end: 999 }
+        );
+    }
+
+    #[test]
+    fn if_range_must_match_the_etag() {
+        let etag = "\"abc\"";
+        let mut headers = HeaderMap::new();
+        headers.insert(header::RANGE, HeaderValue::from_static("bytes=0-9"));
+        assert_eq!(
+            requested_range(&headers, etag, 100),
+            ByteRange::Partial { start: 0, end: 9 }
+        );
+
+        headers.insert(header::IF_RANGE, HeaderValue::from_static("\"abc\""));
+        assert_eq!(
+            requested_range(&headers, etag, 100),
+            ByteRange::Partial { start: 0, end: 9 }
+        );
+        for stale in ["\"old\"", "W/\"abc\"", "Wed, 21 Oct 2015 07:28:00 GMT"] {
+            headers.insert(header::IF_RANGE, HeaderValue::from_static(stale));
+            assert_eq!(requested_range(&headers, etag, 100), ByteRange::Full);
+        }
+    }
+
+    #[test]
+    fn serves_partial_content_and_not_modified() {
+        let data = Bytes::from_static(b"0123456789");
+        let mut headers = HeaderMap::new();
+        headers.insert(header::RANGE, HeaderValue::from_static("bytes=2-4"));
+        let response = serve_bytes(&headers, "text/plain", "\"v1\"", data.clone());
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(
+            response.headers().get(header::CONTENT_RANGE),
+            Some(&HeaderValue::from_static("bytes 2-4/10"))
+        );
+
+        headers.insert(header::RANGE, HeaderValue::from_static("bytes=10-"));
+        let response = serve_bytes(&headers, "text/plain", "\"v1\"", data.clone());
+        assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::IF_NONE_MATCH,
+            HeaderValue::from_static("\"v0\", W/\"v1\""),
+        );
+        let response = serve_bytes(&headers, "text/plain", "\"v1\"", data);
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    }
+}
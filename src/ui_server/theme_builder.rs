@@ -0,0 +1,626 @@
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Json, Response},
+};
+use serde::Deserialize;
+use std::fmt::Write;
+
+use crate::shared::AppState;
+
+const TEXT_CONTRAST: f64 = 4.5;
+const UI_CONTRAST: f64 = 3.0;
+const DEFAULT_RADIUS: &str = "0.5rem";
+const DEFAULT_BODY_FONT: &str =
+    r#""Inter", -apple-system, BlinkMacSystemFont, "Segoe UI", Roboto, sans-serif"#;
+const DEFAULT_MONO_FONT: &str = r#"ui-monospace, SFMono-Regular, Menlo, Consolas, monospace"#;
+const SPACING: [(&str, f64); 6] = [
+    ("xs", 4.0),
+    ("sm", 8.0),
+    ("md", 16.0),
+    ("lg", 24.0),
+    ("xl", 32.0),
+    ("2xl", 48.0),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rgb([u8; 3]);
+
+impl Rgb {
+    const BLACK: Self = Self([0, 0, 0]);
+    const WHITE: Self = Self([255, 255, 255]);
+
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim();
+        if let Some(hex) = value.strip_prefix('#') {
+            if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                return None;
+            }
+            let hex: String = match hex.len() {
+                3 => hex.chars().flat_map(|c| [c, c]).collect(),
+                6 => hex.to_string(),
+                _ => return None,
+            };
+            let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+            return Some(Self([channel(0)?, channel(2)?, channel(4)?]));
+        }
+        let inner = value
+            .strip_prefix("hsl(")
+            .and_then(|v| v.strip_suffix(')'))
+            .unwrap_or(value);
+        let parts: Vec<&str> = inner.split([' ', ',']).filter(|p| !p.is_empty()).collect();
+        let [hue, saturation, light] = parts.as_slice() else {
+            return None;
+        };
+        let hue = hue.trim_end_matches("deg").parse::<f64>().ok()?;
+        let percent = |p: &str| {
+            p.strip_suffix('%')?
+                .parse::<f64>()
+                .ok()
+                .filter(|v| (0.0..=100.0).contains(v))
+                .map(|v| v / 100.0)
+        };
+        if !hue.is_finite() {
+            return None;
+        }
+        Some(Self::from_hsl(hue, percent(saturation)?, percent(light)?))
+    }
+
+    fn from_hsl(hue: f64, saturation: f64, light: f64) -> Self {
+        let hue = hue.rem_euclid(360.0);
+        let a = saturation * light.min(1.0 - light);
+        let channel = |n: f64| {
+            let k = (n + hue / 30.0) % 12.0;
+            let value = light - a * (k - 3.0).min(9.0 - k).clamp(-1.0, 1.0);
+            (value * 255.0).round().clamp(0.0, 255.0) as u8
+        };
+        Self([channel(0.0), channel(8.0), channel(4.0)])
+    }
+
+    fn channels(self) -> [f64; 3] {
+        self.0.map(|c| f64::from(c) / 255.0)
+    }
+
+    pub fn hex(self) -> String {
+        let [r, g, b] = self.0;
+        format!("#{r:02x}{g:02x}{b:02x}")
+    }
+
+    pub fn triple(self) -> String {
+        let [r, g, b] = self.channels();
+        let max = r.max(g).max(b);
+        let min = r.min(g).min(b);
+        let light = (max + min) / 2.0;
+        let delta = max - min;
+        if delta < f64::EPSILON {
+            return format!("0 0% {}%", round(light * 100.0));
+        }
+        let saturation = delta / (1.0 - (2.0 * light - 1.0).abs());
+        let [red, green, _] = self.0;
+        let brightest = self.0.iter().max().copied().unwrap_or_default();
+        let hue = if brightest == red {
+            ((g - b) / delta).rem_euclid(6.0)
+        } else if brightest == green {
+            (b - r) / delta + 2.0
+        } else {
+            (r - g) / delta + 4.0
+        } * 60.0;
+        format!(
+            "{} {}% {}%",
+            round(hue),
+            round(saturation * 100.0),
+            round(light * 100.0)
+        )
+    }
+
+    fn rgba(self, alpha: f64) -> String {
+        let [r, g, b] = self.0;
+        format!("rgba({r}, {g}, {b}, {alpha})")
+    }
+
+    pub fn luminance(self) -> f64 {
+        let [r, g, b] = self.channels().map(|c| {
+            if c <= 0.040_45 {
+                c / 12.92
+            } else {
+                ((c + 0.055) / 1.055).powf(2.4)
+            }
+        });
+        0.2126 * r + 0.7152 * g + 0.0722 * b
+    }
+
+    fn mix(self, other: Self, amount: f64) -> Self {
+        let mut mixed = [0; 3];
+        for (i, channel) in mixed.iter_mut().enumerate() {
+            let (from, to) = (f64::from(self.0[i]), f64::from(other.0[i]));
+            *channel = (from + (to - from) * amount).round().clamp(0.0, 255.0) as u8;
+        }
+        Self(mixed)
+    }
+
+    fn readable_text(self) -> Self {
+        if contrast(Self::WHITE, self) >= contrast(Self::BLACK, self) {
+            Self::WHITE
+        } else {
+            Self::BLACK
+        }
+    }
+
+    fn legible_on(self, background: Self, toward: Self, minimum: f64) -> Self {
+        (0..=20)
+            .map(|step| self.mix(toward, f64::from(step) / 20.0))
+            .find(|c| contrast(*c, background) >= minimum)
+            .unwrap_or(toward)
+    }
+}
+
+fn round(value: f64) -> f64 {
+    (value * 10.0).round() / 10.0
+}
+
+pub fn contrast(a: Rgb, b: Rgb) -> f64 {
+    let (a, b) = (a.luminance(), b.luminance());
+    (a.max(b) + 0.05) / (a.min(b) + 0.05)
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Tokens {
+    name: Option<String>,
+    description: Option<String>,
+    author: Option<String>,
+    colors: Colors,
+    radius: Option<String>,
+    #[serde(default)]
+    fonts: Fonts,
+    #[serde(default)]
+    density: Density,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Colors {
+    primary: String,
+    background: String,
+    foreground: Option<String>,
+    surface: Option<String>,
+    border: Option<String>,
+    muted: Option<String>,
+    accent: Option<String>,
+    success: Option<String>,
+    warning: Option<String>,
+    error: Option<String>,
+    info: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Fonts {
+    body: Option<String>,
+    heading: Option<String>,
+    mono: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Density {
+    Compact,
+    #[default]
+    Comfortable,
+    Spacious,
+}
+
+impl Density {
+    fn scale(self) -> f64 {
+        match self {
+            Self::Compact => 0.75,
+            Self::Comfortable => 1.0,
+            Self::Spacious => 1.25,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Build {
+    pub css: String,
+    pub warnings: Vec<String>,
+}
+
+#[derive(Default)]
+struct Report {
+    errors: Vec<String>,
+    warnings: Vec<String>,
+}
+
+impl Report {
+    fn color(&mut self, name: &str, value: Option<&str>) -> Option<Rgb> {
+        let value = value?;
+        let parsed = Rgb::parse(value);
+        if parsed.is_none() {
+            self.errors.push(format!(
+                "colors.{name} must be #hex or hsl(), not {value:?}"
+            ));
+        }
+        parsed
+    }
+
+    fn contrast(&mut self, what: &str, text: Rgb, background: Rgb, minimum: f64, fatal: bool) {
+        let ratio = contrast(text, background);
+        if ratio < minimum {
+            let message = format!(
+                "{what} contrast is {ratio:.2}:1, below the WCAG AA minimum of {minimum}:1"
+            );
+            if fatal {
+                self.errors.push(message);
+            } else {
+                self.warnings.push(message);
+            }
+        }
+    }
+}
+
+fn valid_radius(radius: &str) -> bool {
+    let number = radius
+        .strip_suffix("rem")
+        .or_else(|| radius.strip_suffix("px"))
+        .or_else(|| radius.strip_suffix("em"))
+        .unwrap_or(if radius == "0" { radius } else { "" });
+    !number.is_empty()
+        && number
+            .parse::<f64>()
+            .is_ok_and(|n| (0.0..=64.0).contains(&n))
+}
+
+fn valid_font_stack(stack: &str) -> bool {
+    !stack.trim().is_empty()
+        && stack.len() <= 200
+        && stack
+            .chars()
+            .all(|c| c.is_alphanumeric() || " ,-_.'\"".contains(c))
+        && stack.matches('"').count().is_multiple_of(2)
+        && stack.matches('\'').count().is_multiple_of(2)
+}
+
+fn valid_label(label: &str) -> bool {
+    label.len() <= 200 && !label.contains("*/") && !label.contains(['\n', '\r', '<'])
+}
+
+pub fn compile(id: &str, source: &str) -> Result<Build, Vec<String>> {
+    let tokens: Tokens =
+        serde_json::from_str(source).map_err(|e| vec![format!("invalid tokens: {e}")])?;
+    let mut report = Report::default();
+
+    for (field, label) in [
+        ("name", &tokens.name),
+        ("description", &tokens.description),
+        ("author", &tokens.author),
+    ] {
+        if label.as_deref().is_some_and(|l| !valid_label(l)) {
+            report
+                .errors
+                .push(format!("{field} must be a single line without */ or <"));
+        }
+    }
+    let radius = tokens.radius.as_deref().unwrap_or(DEFAULT_RADIUS);
+    if !valid_radius(radius) {
+        report.errors.push(format!(
+            "radius must be a length such as 0.5rem or 8px, not {radius:?}"
+        ));
+    }
+    let body_font = tokens.fonts.body.as_deref().unwrap_or(DEFAULT_BODY_FONT);
+    let heading_font = tokens.fonts.heading.as_deref().unwrap_or(body_font);
+    let mono_font = tokens.fonts.mono.as_deref().unwrap_or(DEFAULT_MONO_FONT);
+    for (field, stack) in [
+        ("body", body_font),
+        ("heading", heading_font),
+        ("mono", mono_font),
+    ] {
+        if !valid_font_stack(stack) {
+            report.errors.push(format!(
+                "fonts.{field} must be a comma-separated list of font family names"
+            ));
+        }
+    }
+
+    let colors = &tokens.colors;
+    let primary = report.color("primary", Some(&colors.primary));
+    let background = report.color("background", Some(&colors.background));
+    let foreground = report.color("foreground", colors.foreground.as_deref());
+    let surface = report.color("surface", colors.surface.as_deref());
+    let border = report.color("border", colors.border.as_deref());
+    let muted = report.color("muted", colors.muted.as_deref());
+    let accent = report.color("accent", colors.accent.as_deref());
+    let success = report.color("success", colors.success.as_deref());
+    let warning = report.color("warning", colors.warning.as_deref());
+    let error = report.color("error", colors.error.as_deref());
+    let info = report.color("info", colors.info.as_deref());
+    let (Some(primary), Some(background)) = (primary, background) else {
+        return Err(report.errors);
+    };
+
+    let foreground = foreground.unwrap_or_else(|| background.readable_text().mix(background, 0.08));
+    let surface = surface.unwrap_or_else(|| background.mix(foreground, 0.04));
+    let surface_hover = surface.mix(foreground, 0.06);
+    let border = border.unwrap_or_else(|| background.mix(foreground, 0.15));
+    let muted = muted.unwrap_or_else(|| {
+        foreground
+            .mix(background, 0.4)
+            .legible_on(surface, foreground, TEXT_CONTRAST)
+    });
+    let tertiary = foreground
+        .mix(background, 0.55)
+        .legible_on(background, foreground, UI_CONTRAST);
+    let accent = accent.unwrap_or(surface_hover);
+    // The stock status colours are adjusted to the background; chosen ones
+    // are only checked
+    let status = |chosen: Option<Rgb>, stock: Rgb| {
+        chosen.unwrap_or_else(|| stock.legible_on(background, foreground, UI_CONTRAST))
+    };
+    let success = status(success, Rgb([0x22, 0xc5, 0x5e]));
+    let warning = status(warning, Rgb([0xf5, 0x9e, 0x0b]));
+    let error = status(error, Rgb([0xef, 0x44, 0x44]));
+    let info = status(info, Rgb([0x3b, 0x82, 0xf6]));
+    let primary_text = primary.readable_text();
+
+    report.contrast(
+        "Text on background",
+        foreground,
+        background,
+        TEXT_CONTRAST,
+        true,
+    );
+    report.contrast("Text on surface", foreground, surface, TEXT_CONTRAST, true);
+    report.contrast(
+        "Muted text on surface",
+        muted,
+        surface,
+        TEXT_CONTRAST,
+        false,
+    );
+    report.contrast(
+        "Text on primary",
+        primary_text,
+        primary,
+        TEXT_CONTRAST,
+        false,
+    );
+    report.contrast(
+        "Primary on background",
+        primary,
+        background,
+        UI_CONTRAST,
+        false,
+    );
+    for (name, color) in [
+        ("Success", success),
+        ("Warning", warning),
+        ("Error", error),
+        ("Info", info),
+    ] {
+        report.contrast(
+            &format!("{name} on background"),
+            color,
+            background,
+            UI_CONTRAST,
+            false,
+        );
+    }
+    if !report.errors.is_empty() {
+        return Err(report.errors);
+    }
+
+    let name = tokens.name.as_deref().unwrap_or(id);
+    let mode = if background.readable_text() == Rgb::WHITE {
+        "dark"
+    } else {
+        "light"
+    };
+    let mut header = format!("/* {name} Theme\n * @name {name}\n");
+    if let Some(description) = &tokens.description {
+        let _ = writeln!(header, " * @description {description}");
+    }
+    if let Some(author) = &tokens.author {
+        let _ = writeln!(header, " * @author {author}");
+    }
+    let _ = writeln!(
+        header,
+        " * @mode {mode}\n * Generated from {id}.tokens.json; edit the tokens, not this file.\n */"
+    );
+
+    let triple = |name: &str, color: Rgb| format!("    --{name}: {};\n", color.triple());
+    let hex = |name: &str, color: Rgb| format!("    --{name}: {};\n", color.hex());
+    let mut css = header;
+    css.push_str("\n:root {\n");
+    for (name, color) in [
+        ("background", background),
+        ("foreground", foreground),
+        ("card", surface),
+        ("card-foreground", foreground),
+        ("popover", surface),
+        ("popover-foreground", foreground),
+        ("primary", primary),
+        ("primary-foreground", primary_text),
+        ("secondary", surface_hover),
+        ("secondary-foreground", foreground),
+        ("muted", surface_hover),
+        ("muted-foreground", muted),
+        ("accent", accent),
+        ("accent-foreground", accent.readable_text()),
+        ("destructive", error),
+        ("destructive-foreground", error.readable_text()),
+        ("border", border),
+        ("input", border),
+        ("ring", primary),
+        ("chart-1", primary),
+        ("chart-2", accent),
+        ("chart-3", warning),
+        ("chart-4", success),
+        ("chart-5", info),
+    ] {
+        css.push_str(&triple(name, color));
+    }
+    let _ = writeln!(css, "    --radius: {radius};");
+
+    css.push_str("\n    /* base.css and config-colors.css */\n");
+    for (name, color) in [
+        ("color1", primary),
+        ("color2", accent),
+        ("primary-hover", primary.mix(Rgb::BLACK, 0.15)),
+        ("bg", background),
+        ("surface", surface),
+        ("surface-hover", surface_hover),
+        ("border-light", border.mix(foreground, 0.1)),
+        ("border-hover", border.mix(foreground, 0.25)),
+        ("text", foreground),
+        ("text-primary", foreground),
+        ("text-secondary", muted),
+        ("text-tertiary", tertiary),
+        ("success", success),
+        ("warning", warning),
+        ("error", error),
+        ("info", info),
+        ("sentient-accent", primary),
+    ] {
+        css.push_str(&hex(name, color));
+    }
+    let [r, g, b] = background.0;
+    let _ = writeln!(css, "    --primary-light: {};", primary.rgba(0.1));
+    let _ = writeln!(css, "    --bg-rgb: {r}, {g}, {b};");
+
+    css.push_str("\n    /* Typography and density */\n");
+    let _ = writeln!(css, "    --font-family: {body_font};");
+    let _ = writeln!(css, "    --font-sans: {body_font};");
+    let _ = writeln!(css, "    --font-heading: {heading_font};");
+    let _ = writeln!(css, "    --font-mono: {mono_font};");
+    let _ = writeln!(css, "    --sentient-font-family: {body_font};");
+    for (step, pixels) in SPACING {
+        let _ = writeln!(
+            css,
+            "    --space-{step}: {}px;",
+            (pixels * tokens.density.scale()).round()
+        );
+    }
+    css.push_str("}\n");
+
+    Ok(Build {
+        css,
+        warnings: report.warnings,
+    })
+}
+
+pub async fn preview(State(state): State<AppState>, headers: HeaderMap, body: String) -> Response {
+    if !state.themes.can_build(&headers) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    match compile("preview", &body) {
+        Ok(build) => Json(serde_json::json!({
+            "css": build.css,
+            "warnings": build.warnings,
+        }))
+        .into_response(),
+        Err(errors) => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(serde_json::json!({ "errors": errors })),
+        )
+            .into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ui_server::themes::{validate, variable};
+
+    #[test]
+    fn converts_and_measures_colours() {
+        let blue = Rgb::parse("#3b82f6");
+        assert_eq!(blue.map(Rgb::hex).as_deref(), Some("#3b82f6"));
+        assert_eq!(Rgb::parse("#fff"), Some(Rgb::WHITE));
+        assert_eq!(Rgb::parse("hsl(0, 0%, 0%)"), Some(Rgb::BLACK));
+        assert_eq!(
+            Rgb::parse("217.2 91.2% 59.8%").map(Rgb::hex).as_deref(),
+            Some("#3b82f6")
+        );
+        assert_eq!(Rgb([255, 0, 0]).triple(), "0 100% 50%");
+        assert!(Rgb::parse("#12345").is_none() && Rgb::parse("red").is_none());
+
+        assert!((contrast(Rgb::BLACK, Rgb::WHITE) - 21.0).abs() < 1e-9);
+        let gray = Rgb([0x76, 0x76, 0x76]);
+        assert!((contrast(gray, Rgb::WHITE) - 4.54).abs() < 0.01);
+    }
+
+    #[test]
+    fn compiles_tokens() {
+        let build = compile(
+            "acme",
+            r##"{
+                "name": "Acme",
+                "colors": { "primary": "#0055ff", "background": "#0b1020" },
+                "radius": "12px",
+                "fonts": { "body": "\"Acme Sans\", sans-serif" },
+                "density": "compact"
+            }"##,
+        )
+        .unwrap_or_else(|errors| Build {
+            css: errors.join("\n"),
+            warnings: Vec::new(),
+        });
+        let css = &build.css;
+        assert!(css.contains("@mode dark"), "{css}");
+        assert!(css.contains("--color1: #0055ff;"));
+        assert!(css.contains("--radius: 12px;"));
+        assert!(css.contains("--space-md: 12px;"));
+        assert!(css.contains(r#"--font-sans: "Acme Sans", sans-serif;"#));
+        assert!(validate(css).is_empty());
+
+        let muted = variable(css, "--text-secondary").and_then(Rgb::parse);
+        let surface = variable(css, "--surface").and_then(Rgb::parse);
+        assert!(muted
+            .zip(surface)
+            .is_some_and(|(m, s)| contrast(m, s) >= TEXT_CONTRAST));
+    }
+
+    #[test]
+    fn rejects_unreadable_or_unsafe_tokens() {
+        let errors = compile(
+            "low",
+            r##"{"colors": {"primary": "#777", "background": "#ffffff", "foreground": "#bbbbbb"}}"##,
+        )
+        .err()
+        .unwrap_or_default();
+        assert!(errors.iter().any(|e| e.starts_with("Text on background")));
+
+        let errors = compile(
+            "evil",
+            r##"{"name": "x */ body{}", "colors": {"primary": "url(x)", "background": "#fff"}, "radius": "1px;}"}"##,
+        )
+        .err()
+        .unwrap_or_default();
+        assert_eq!(errors.len(), 3, "{errors:?}");
+
+        let warned = compile(
+            "pale",
+            r##"{"colors": {"primary": "#ffee88", "background": "#ffffff"}}"##,
+        );
+        // Only the chosen primary; the stock status colours are adjusted
+        assert!(
+            warned.is_ok_and(|b| matches!(b.warnings.as_slice(), [w] if w.starts_with("Primary")))
+        );
+    }
+
+    #[tokio::test]
+    async fn preview_is_hidden_without_the_build_token() {
+        let state = AppState::new();
+        let tokens = r##"{"colors": {"primary": "#0055ff", "background": "#0b1020"}}"##;
+        let response = preview(State(state.clone()), HeaderMap::new(), tokens.to_string()).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            axum::http::header::AUTHORIZATION,
+            axum::http::HeaderValue::from_static("Bearer maintenance-secret"),
+        );
+        let response = preview(State(state), headers, tokens.to_string()).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
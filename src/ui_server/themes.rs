@@ -0,0 +1,691 @@
+use axum::{
+    body::Bytes,
+    extract::{Request, State},
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode, Uri},
+    middleware::Next,
+    response::{IntoResponse, Json, Response},
+};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use super::{cookies, html, overlay, read_ui_text, route, static_files, theme_builder};
+use crate::shared::AppState;
+
+#[cfg(feature = "embed-ui")]
+use super::Assets;
+
+pub const THEMES_DIR: &str = "suite/public/themes";
+pub const THEME_COOKIE: &str = "gb_theme";
+const TOKENS_SUFFIX: &str = ".tokens.json";
+const REQUIRED_VARIABLES: [&str; 3] = ["--background", "--foreground", "--primary"];
+const MAX_THEME_BYTES: usize = 256 * 1024;
+const RESCAN_AFTER: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Theme {
+    pub id: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub author: Option<String>,
+    pub mode: &'static str,
+    pub primary: Option<String>,
+    pub href: String,
+    pub custom: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Rejected {
+    pub id: String,
+    pub errors: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    name: Option<String>,
+    description: Option<String>,
+    author: Option<String>,
+    mode: Option<String>,
+    primary: Option<String>,
+}
+
+#[derive(Debug)]
+struct Generated {
+    source: String,
+    css: Bytes,
+    etag: String,
+}
+
+impl Generated {
+    fn serve(&self, headers: &HeaderMap) -> Response {
+        let mut response = static_files::serve_bytes(
+            headers,
+            "text/css; charset=utf-8",
+            &self.etag,
+            self.css.clone(),
+        );
+        // Token edits take effect without waiting for a browser cache to expire
+        response
+            .headers_mut()
+            .insert(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+        response
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Catalogue {
+    themes: Vec<Theme>,
+    rejected: Vec<Rejected>,
+    generated: HashMap<String, Arc<Generated>>,
+}
+
+impl Catalogue {
+    pub fn get(&self, id: &str) -> Option<&Theme> {
+        self.themes.iter().find(|t| t.id == id)
+    }
+
+    fn is_rejected(&self, id: &str) -> bool {
+        self.rejected.iter().any(|r| r.id == id)
+    }
+}
+
+#[derive(Debug)]
+pub struct ThemeService {
+    inject: bool,
+    build_token: Option<String>,
+    catalogue: RwLock<Option<(Instant, Arc<Catalogue>)>>,
+}
+
+impl ThemeService {
+    #[must_use]
+    pub fn from_env() -> Self {
+        let service = Self {
+            inject: std::env::var("BOTUI_THEMES").map_or(true, |v| v != "false"),
+            build_token: std::env::var("BOTUI_THEME_BUILD_TOKEN")
+                .ok()
+                .filter(|v| !v.is_empty()),
+            catalogue: RwLock::new(None),
+        };
+        let catalogue = service.catalogue();
+        for theme in catalogue.themes.iter().filter(|t| !t.warnings.is_empty()) {
+            warn!("Theme {}: {}", theme.id, theme.warnings.join("; "));
+        }
+        for rejected in &catalogue.rejected {
+            warn!(
+                "Rejected custom theme {}: {}",
+                rejected.id,
+                rejected.errors.join("; ")
+            );
+        }
+        info!(
+            "{} themes available, {} custom themes rejected",
+            catalogue.themes.len(),
+            catalogue.rejected.len()
+        );
+        service
+    }
+
+    pub fn can_build(&self, headers: &HeaderMap) -> bool {
+        let Some(token) = &self.build_token else {
+            return false;
+        };
+        headers
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .is_some_and(|given| Sha256::digest(given) == Sha256::digest(token))
+    }
+
+    pub fn catalogue(&self) -> Arc<Catalogue> {
+        let previous = self.catalogue.read().ok().and_then(|c| c.clone());
+        if let Some((_, catalogue)) = previous
+            .as_ref()
+            .filter(|(scanned, _)| scanned.elapsed() < RESCAN_AFTER)
+        {
+            return catalogue.clone();
+        }
+        let catalogue = Arc::new(scan(previous.as_ref().map(|(_, c)| c.as_ref())));
+        if let Ok(mut cached) = self.catalogue.write() {
+            *cached = Some((Instant::now(), catalogue.clone()));
+        }
+        catalogue
+    }
+
+    pub fn selected(&self, state: &AppState, headers: &HeaderMap, uri: &Uri) -> Option<Theme> {
+        let catalogue = self.catalogue();
+        if let Some(theme) =
+            cookies::get(headers, THEME_COOKIE).and_then(|id| catalogue.get(&id).cloned())
+        {
+            return Some(theme);
+        }
+        let bot = state
+            .hosts
+            .bot_for_request(headers, uri)
+            .or_else(|| route::parse(uri.path()).bot);
+        let branding = state.branding.resolve_cached(bot.as_deref())?;
+        catalogue.get(branding.theme.as_deref()?).cloned()
+    }
+}
+
+fn stock_ids() -> Vec<String> {
+    #[cfg(feature = "embed-ui")]
+    let files: Vec<String> = Assets::iter()
+        .filter_map(|file| {
+            file.strip_prefix(THEMES_DIR)?
+                .strip_prefix('/')
+                .map(str::to_string)
+        })
+        .collect();
+    #[cfg(not(feature = "embed-ui"))]
+    let files: Vec<String> = std::fs::read_dir(super::get_ui_root().join(THEMES_DIR))
+        .map(|entries| {
+            entries
+                .flatten()
+                .map(|e| e.file_name().to_string_lossy().into_owned())
+                .collect()
+        })
+        .unwrap_or_default();
+    theme_ids(files)
+}
+
+fn theme_ids(files: impl IntoIterator<Item = String>) -> Vec<String> {
+    let mut ids: Vec<String> = files
+        .into_iter()
+        .filter_map(|file| file.strip_suffix(".css").map(str::to_string))
+        .collect();
+    ids.sort();
+    ids
+}
+
+fn scan(previous: Option<&Catalogue>) -> Catalogue {
+    let mut catalogue = Catalogue::default();
+    let mut seen: Vec<String> = Vec::new();
+
+    for dir in overlay::overlay_dirs() {
+        let dir = dir.join(THEMES_DIR);
+        let files = std::fs::read_dir(&dir)
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .map(|e| e.file_name().to_string_lossy().into_owned())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        let mut ids = theme_ids(files.iter().cloned());
+        ids.extend(
+            files
+                .iter()
+                .filter_map(|file| file.strip_suffix(TOKENS_SUFFIX))
+                .map(str::to_string),
+        );
+        ids.sort();
+        ids.dedup();
+        for id in ids {
+            if seen.contains(&id) {
+                continue;
+            }
+            seen.push(id.clone());
+            if !route::is_valid_name(&id) {
+                catalogue.rejected.push(Rejected {
+                    id,
+                    errors: vec!["file name must use letters, digits, '-' or '_'".to_string()],
+                });
+                continue;
+            }
+            // Hand-written CSS wins over tokens for the same id
+            let loaded = if dir.join(format!("{id}.css")).is_file() {
+                load_custom(&dir, &id)
+            } else {
+                load_generated(&dir, &id, previous).map(|(theme, generated)| {
+                    catalogue.generated.insert(id.clone(), generated);
+                    theme
+                })
+            };
+            match loaded {
+                Ok(theme) => catalogue.themes.push(theme),
+                Err(errors) => {
+                    let known = previous.is_some_and(|p| p.rejected.iter().any(|r| r.id == id));
+                    if !known {
+                        warn!("Skipping theme {id}: {}", errors.join("; "));
+                    }
+                    catalogue.rejected.push(Rejected { id, errors });
+                }
+            }
+        }
+    }
+
+    for id in stock_ids() {
+        if seen.contains(&id) {
+            continue;
+        }
+        let css = read_ui_text(&format!("{THEMES_DIR}/{id}.css")).unwrap_or_default();
+        let manifest = read_ui_text(&format!("{THEMES_DIR}/{id}.json"))
+            .ok()
+            .and_then(|raw| serde_json::from_str(&raw).ok())
+            .unwrap_or_default();
+        catalogue.themes.push(describe(&id, &css, manifest, false));
+    }
+
+    catalogue.themes.sort_by_key(|t| t.name.to_lowercase());
+    catalogue
+}
+
+fn load_custom(dir: &Path, id: &str) -> Result<Theme, Vec<String>> {
+    let css_path = dir.join(format!("{id}.css"));
+    let size = std::fs::metadata(&css_path).map_or(0, |m| m.len());
+    if size > MAX_THEME_BYTES as u64 {
+        return Err(vec![format!("larger than {MAX_THEME_BYTES} bytes")]);
+    }
+    let css = std::fs::read_to_string(&css_path).map_err(|e| vec![format!("unreadable: {e}")])?;
+
+    let mut errors = validate(&css);
+    let manifest_path = dir.join(format!("{id}.json"));
+    let manifest = if manifest_path.is_file() {
+        std::fs::read_to_string(&manifest_path)
+            .map_err(|e| e.to_string())
+            .and_then(|raw| serde_json::from_str::<Manifest>(&raw).map_err(|e| e.to_string()))
+            .unwrap_or_else(|e| {
+                errors.push(format!("invalid {id}.json: {e}"));
+                Manifest::default()
+            })
+    } else {
+        Manifest::default()
+    };
+
+    if errors.is_empty() {
+        Ok(describe(id, &css, manifest, true))
+    } else {
+        Err(errors)
+    }
+}
+
+fn load_generated(
+    dir: &Path,
+    id: &str,
+    previous: Option<&Catalogue>,
+) -> Result<(Theme, Arc<Generated>), Vec<String>> {
+    let path = dir.join(format!("{id}{TOKENS_SUFFIX}"));
+    let size = std::fs::metadata(&path).map_or(0, |m| m.len());
+    if size > MAX_THEME_BYTES as u64 {
+        return Err(vec![format!("larger than {MAX_THEME_BYTES} bytes")]);
+    }
+    let source = std::fs::read_to_string(&path).map_err(|e| vec![format!("unreadable: {e}")])?;
+    if let Some(unchanged) = previous.and_then(|p| {
+        let generated = p.generated.get(id).filter(|g| g.source == source)?;
+        Some((p.get(id)?.clone(), generated.clone()))
+    }) {
+        return Ok(unchanged);
+    }
+
+    let build = theme_builder::compile(id, &source)?;
+    let errors = validate(&build.css);
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    let mut theme = describe(id, &build.css, Manifest::default(), true);
+    theme.warnings = build.warnings;
+    let etag = format!(
+        "\"{}\"",
+        Sha256::digest(build.css.as_bytes())[..16]
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect::<String>()
+    );
+    let generated = Generated {
+        source,
+        css: Bytes::from(build.css),
+        etag,
+    };
+    Ok((theme, Arc::new(generated)))
+}
+
+pub fn validate(css: &str) -> Vec<String> {
+    let mut errors = Vec::new();
+    let lower = css.to_ascii_lowercase();
+    for (pattern, reason) in [
+        ("@import", "@import is not allowed"),
+        ("expression(", "CSS expressions are not allowed"),
+        ("javascript:", "javascript: URLs are not allowed"),
+        ("behavior:", "behaviors are not allowed"),
+        ("-moz-binding", "bindings are not allowed"),
+        ("</", "markup is not allowed"),
+    ] {
+        if lower.contains(pattern) {
+            errors.push(reason.to_string());
+        }
+    }
+
+    for (index, _) in lower.match_indices("url(") {
+        let target = lower[index + 4..]
+            .trim_start()
+            .trim_start_matches(['"', '\'']);
+        let external = target.starts_with("//") || target.contains("://");
+        if external || (target.starts_with("data:") && !target.starts_with("data:image/")) {
+            errors.push("url() may only reference local files or data:image/ URIs".to_string());
+            break;
+        }
+    }
+
+    let without_comments = strip_comments(css);
+    match without_comments {
+        Some(code) => {
+            let mut depth: i32 = 0;
+            for c in code.chars() {
+                match c {
+                    '{' => depth += 1,
+                    '}' => depth -= 1,
+                    _ => {}
+                }
+                if depth < 0 {
+                    break;
+                }
+            }
+            if depth != 0 {
+                errors.push("unbalanced braces".to_string());
+            }
+            for name in REQUIRED_VARIABLES {
+                match variable(&code, name) {
+                    None => errors.push(format!("missing {name}")),
+                    Some(value) if lightness(value).is_none() => {
+                        errors.push(format!("{name} is not a colour"));
+                    }
+                    Some(_) => {}
+                }
+            }
+        }
+        None => errors.push("unterminated comment".to_string()),
+    }
+    errors
+}
+
+fn strip_comments(css: &str) -> Option<String> {
+    let mut out = String::with_capacity(css.len());
+    let mut rest = css;
+    while let Some(start) = rest.find("/*") {
+        out.push_str(&rest[..start]);
+        let end = rest[start + 2..].find("*/")?;
+        rest = &rest[start + 2 + end + 2..];
+    }
+    out.push_str(rest);
+    Some(out)
+}
+
+pub fn variable<'a>(css: &'a str, name: &str) -> Option<&'a str> {
+    let mut search = 0;
+    while let Some(offset) = css[search..].find(name) {
+        let start = search + offset;
+        search = start + name.len();
+        let standalone = !css[..start].ends_with(|c: char| c.is_ascii_alphanumeric() || c == '-');
+        let Some(value) = css[search..].trim_start().strip_prefix(':') else {
+            continue;
+        };
+        if standalone {
+            let end = value.find([';', '}']).unwrap_or(value.len());
+            return Some(value[..end].trim()).filter(|v| !v.is_empty());
+        }
+    }
+    None
+}
+
+pub fn lightness(value: &str) -> Option<f64> {
+    let value = value.trim();
+    if let Some(hex) = value.strip_prefix('#') {
+        if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        let hex: String = match hex.len() {
+            3 => hex.chars().flat_map(|c| [c, c]).collect(),
+            6 | 8 => hex[..6].to_string(),
+            _ => return None,
+        };
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok().map(f64::from);
+        let (r, g, b) = (channel(0)?, channel(2)?, channel(4)?);
+        return Some((r * 299.0 + g * 587.0 + b * 114.0) / 1000.0 / 255.0);
+    }
+    let inner = value
+        .strip_prefix("hsl(")
+        .or_else(|| value.strip_prefix("hsla("))
+        .and_then(|v| v.strip_suffix(')'))
+        .unwrap_or(value);
+    let parts: Vec<&str> = inner
+        .split([' ', ','])
+        .filter(|p| !p.is_empty() && *p != "/")
+        .collect();
+    match parts.as_slice() {
+        [_, saturation, light, ..] if saturation.ends_with('%') => light
+            .strip_suffix('%')?
+            .parse::<f64>()
+            .ok()
+            .map(|l| (l / 100.0).clamp(0.0, 1.0)),
+        _ => None,
+    }
+}
+
+fn css_color(value: &str) -> String {
+    if value.starts_with('#') || value.contains('(') {
+        value.to_string()
+    } else {
+        format!("hsl({value})")
+    }
+}
+
+fn describe(id: &str, css: &str, manifest: Manifest, custom: bool) -> Theme {
+    let comment = css
+        .find("/*")
+        .and_then(|start| {
+            css[start + 2..]
+                .find("*/")
+                .map(|end| &css[start + 2..start + 2 + end])
+        })
+        .unwrap_or_default();
+    let tag = |name: &str| {
+        comment.lines().find_map(|line| {
+            line.trim()
+                .trim_start_matches('*')
+                .trim()
+                .strip_prefix(name)
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+        })
+    };
+    let title = comment
+        .lines()
+        .map(|l| l.trim().trim_start_matches('*').trim())
+        .find(|l| !l.is_empty() && !l.starts_with('@'))
+        .map(|l| {
+            let l = l.split(" Theme").next().unwrap_or(l);
+            l.trim().to_string()
+        })
+        .filter(|l| !l.is_empty());
+
+    let background = variable(css, "--background").or_else(|| variable(css, "--color-bg"));
+    let derived_mode = match background.and_then(lightness) {
+        Some(l) if l < 0.5 => "dark",
+        _ => "light",
+    };
+    let mode = match manifest.mode.or_else(|| tag("@mode")).as_deref() {
+        Some("dark") => "dark",
+        Some("light") => "light",
+        _ => derived_mode,
+    };
+
+    Theme {
+        id: id.to_string(),
+        name: manifest
+            .name
+            .or_else(|| tag("@name"))
+            .or(title)
+            .unwrap_or_else(|| id.to_string()),
+        description: manifest.description.or_else(|| tag("@description")),
+        author: manifest.author.or_else(|| tag("@author")),
+        mode,
+        primary: manifest.primary.or_else(|| {
+            variable(css, "--primary")
+                .or_else(|| variable(css, "--color-primary"))
+                .map(css_color)
+        }),
+        href: format!("/{THEMES_DIR}/{id}.css"),
+        custom,
+        warnings: Vec::new(),
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Listing<'a> {
+    themes: &'a [Theme],
+    rejected: &'a [Rejected],
+    selected: Option<String>,
+}
+
+pub async fn list(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    let catalogue = state.themes.catalogue();
+    // The bot is the one of the page asking
+    let page = super::referer_path(&headers)
+        .and_then(|path| path.parse::<Uri>().ok())
+        .unwrap_or_default();
+    let selected = state.themes.selected(&state, &headers, &page).map(|t| t.id);
+    Json(Listing {
+        themes: &catalogue.themes,
+        rejected: &catalogue.rejected,
+        selected,
+    })
+    .into_response()
+}
+
+pub async fn apply(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let service = state.themes.clone();
+    let path = route::normalize(request.uri().path()).unwrap_or_default();
+    if let Some(id) = path
+        .strip_prefix(&format!("/{THEMES_DIR}/"))
+        .and_then(|file| file.strip_suffix(".css"))
+    {
+        let catalogue = service.catalogue();
+        if catalogue.is_rejected(id) {
+            return StatusCode::NOT_FOUND.into_response();
+        }
+        let readable = matches!(*request.method(), Method::GET | Method::HEAD);
+        if let Some(generated) = catalogue.generated.get(id).filter(|_| readable) {
+            return generated.serve(request.headers());
+        }
+    }
+    if !service.inject || request.method() != Method::GET {
+        return next.run(request).await;
+    }
+
+    let headers = request.headers().clone();
+    let uri = request.uri().clone();
+    let response = next.run(request).await;
+    if !html::is_rewritable(&response) {
+        return response;
+    }
+    let Some(theme) = service.selected(&state, &headers, &uri) else {
+        return response;
+    };
+
+    let mut response = html::rewrite_response(response, |page| {
+        if !page.contains("</head>") || page.contains(r#"id="theme-css""#) {
+            return page.to_string();
+        }
+        let mut page = html::rewrite_tags(page, "html", |tag| {
+            Some(html::set_attr(tag, "html", "data-theme-id", &theme.id))
+        });
+        html::insert_into_head(
+            &mut page,
+            &format!(
+                r#"<link id="theme-css" rel="stylesheet" href="{}">"#,
+                html::escape(&theme.href)
+            ),
+        );
+        page
+    })
+    .await;
+    response
+        .headers_mut()
+        .append(header::VARY, HeaderValue::from_static("Cookie"));
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VALID: &str = "/* Harbor Theme\n * @author Jane\n */\n:root {\n  --background: 210 40% 8%;\n  --foreground: 0 0% 98%;\n  --primary: 200 90% 50%;\n}\n";
+
+    #[test]
+    fn building_needs_its_own_token() {
+        let service = |build_token: Option<&str>| ThemeService {
+            inject: true,
+            build_token: build_token.map(str::to_string),
+            catalogue: RwLock::new(None),
+        };
+        let bearer = |token: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::AUTHORIZATION, HeaderValue::from_static(token));
+            headers
+        };
+
+        assert!(service(Some("build-secret")).can_build(&bearer("Bearer build-secret")));
+        assert!(!service(Some("build-secret")).can_build(&bearer("Bearer other")));
+        assert!(!service(Some("build-secret")).can_build(&bearer("build-secret")));
+        assert!(!service(Some("build-secret")).can_build(&HeaderMap::new()));
+        assert!(!service(None).can_build(&bearer("Bearer ")));
+    }
+
+    #[test]
+    fn describes_from_css() {
+        let theme = describe("harbor", VALID, Manifest::default(), true);
+        assert_eq!(theme.name, "Harbor");
+        assert_eq!(theme.author.as_deref(), Some("Jane"));
+        assert_eq!(theme.mode, "dark");
+        assert_eq!(theme.primary.as_deref(), Some("hsl(200 90% 50%)"));
+        assert_eq!(theme.href, "/suite/public/themes/harbor.css");
+
+        let manifest = Manifest {
+            name: Some("Harbour Night".to_string()),
+            ..Manifest::default()
+        };
+        assert_eq!(
+            describe("harbor", VALID, manifest, true).name,
+            "Harbour Night"
+        );
+    }
+
+    #[test]
+    fn validates_custom_css() {
+        assert!(validate(VALID).is_empty());
+        let hostile = format!(
+            "{VALID}@import url(https://evil.example/x.css);\nbody {{ background: url(//evil.example/t.png); }}"
+        );
+        let errors = validate(&hostile);
+        assert!(errors.iter().any(|e| e.contains("@import")));
+        assert!(errors.iter().any(|e| e.contains("url()")));
+        assert!(validate(":root { --primary: red; ").len() >= 2);
+        assert!(validate(&format!(
+            "{VALID} .a {{ background: url(data:image/png;base64,AA) }}"
+        ))
+        .is_empty());
+    }
+
+    #[test]
+    fn reads_variables_and_lightness() {
+        let css = ":root { --primary-foreground: #fff; --primary: 0 0% 10%; --color-bg: #0f172a; }";
+        assert_eq!(variable(css, "--primary"), Some("0 0% 10%"));
+        assert_eq!(lightness("0 0% 10%"), Some(0.1));
+        assert!(lightness("#0f172a").is_some_and(|l| l < 0.2));
+        assert!(lightness("hsl(0, 0%, 90%)").is_some_and(|l| l > 0.8));
+        assert_eq!(lightness("#ééé"), None);
+        assert_eq!(lightness("#0f172é"), None);
+
+        let non_ascii = VALID.replace("210 40% 8%", "#ééé");
+        assert!(validate(&non_ascii)
+            .iter()
+            .any(|e| e == "--background is not a colour"));
+    }
+}
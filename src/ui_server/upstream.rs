@@ -0,0 +1,399 @@
+use log::{error, info, warn};
+use serde::Deserialize;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::proxy_policy::path_matches;
+
+pub const DEFAULT_POOL: &str = "default";
+const DEFAULT_HEALTH_PATH: &str = "/health";
+const DEFAULT_HEALTH_INTERVAL_SECS: u64 = 10;
+const HEALTH_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Balance {
+    #[default]
+    RoundRobin,
+    LeastConn,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+struct PoolConfig {
+    servers: Vec<String>,
+    balance: Balance,
+    health_path: Option<String>,
+    health_interval_secs: u64,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            servers: Vec::new(),
+            balance: Balance::default(),
+            health_path: Some(DEFAULT_HEALTH_PATH.to_string()),
+            health_interval_secs: DEFAULT_HEALTH_INTERVAL_SECS,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+struct RouteConfig {
+    path: Option<String>,
+    bot: Option<String>,
+    pool: String,
+}
+
+impl RouteConfig {
+    fn matches(&self, path: &str, bot: Option<&str>) -> bool {
+        self.path
+            .as_deref()
+            .is_none_or(|pattern| path_matches(pattern, path))
+            && self.bot.as_deref().is_none_or(|wanted| bot == Some(wanted))
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct UpstreamsConfig {
+    pools: HashMap<String, PoolConfig>,
+    routes: Vec<RouteConfig>,
+}
+
+#[derive(Debug)]
+struct Server {
+    url: String,
+    healthy: AtomicBool,
+    active: AtomicUsize,
+}
+
+#[derive(Debug)]
+struct Pool {
+    name: String,
+    servers: Vec<Arc<Server>>,
+    balance: Balance,
+    next: AtomicUsize,
+    health_path: Option<String>,
+    health_interval: Duration,
+    client: reqwest::Client,
+}
+
+impl Pool {
+    fn new(name: &str, config: PoolConfig) -> Self {
+        // Redirects go back to the browser, with their Location rewritten by the proxy
+        let client = reqwest::Client::builder()
+            .danger_accept_invalid_certs(true)
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap_or_else(|_| reqwest::Client::new());
+        Self {
+            name: name.to_string(),
+            servers: config
+                .servers
+                .iter()
+                .map(|url| {
+                    Arc::new(Server {
+                        url: url.trim_end_matches('/').to_string(),
+                        healthy: AtomicBool::new(true),
+                        active: AtomicUsize::new(0),
+                    })
+                })
+                .collect(),
+            balance: config.balance,
+            next: AtomicUsize::new(0),
+            health_path: config.health_path.filter(|p| !p.is_empty()),
+            health_interval: Duration::from_secs(config.health_interval_secs.max(1)),
+            client,
+        }
+    }
+
+    fn candidates(&self) -> Vec<&Arc<Server>> {
+        let healthy: Vec<_> = self
+            .servers
+            .iter()
+            .filter(|s| s.healthy.load(Ordering::Relaxed))
+            .collect();
+        if healthy.is_empty() {
+            self.servers.iter().collect()
+        } else {
+            healthy
+        }
+    }
+
+    fn pick(&self, affinity: Option<&str>) -> Option<Arc<Server>> {
+        let candidates = self.candidates();
+        let server = match affinity {
+            // Rendezvous hashing: a key keeps its replica while that replica
+            // stays healthy, and only its keys move when it goes away.
+            Some(key) => candidates.into_iter().max_by_key(|server| {
+                let mut hasher = DefaultHasher::new();
+                (key, &server.url).hash(&mut hasher);
+                hasher.finish()
+            }),
+            None => match self.balance {
+                Balance::RoundRobin => {
+                    let n = self.next.fetch_add(1, Ordering::Relaxed);
+                    candidates.get(n % candidates.len().max(1)).copied()
+                }
+                Balance::LeastConn => candidates
+                    .into_iter()
+                    .min_by_key(|server| server.active.load(Ordering::Relaxed)),
+            },
+        };
+        server.cloned()
+    }
+}
+
+#[derive(Debug)]
+pub struct Upstream {
+    server: Arc<Server>,
+    pool: String,
+    client: reqwest::Client,
+}
+
+impl Upstream {
+    fn new(server: Arc<Server>, pool: &Pool) -> Self {
+        server.active.fetch_add(1, Ordering::SeqCst);
+        Self {
+            server,
+            pool: pool.name.clone(),
+            client: pool.client.clone(),
+        }
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.server.url
+    }
+
+    pub fn client(&self) -> &reqwest::Client {
+        &self.client
+    }
+
+    pub fn ws_base_url(&self) -> String {
+        self.server
+            .url
+            .replace("https://", "wss://")
+            .replace("http://", "ws://")
+    }
+
+    pub fn pool(&self) -> &str {
+        &self.pool
+    }
+}
+
+impl Drop for Upstream {
+    fn drop(&mut self) {
+        self.server.active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[derive(Debug)]
+pub struct Upstreams {
+    pools: HashMap<String, Pool>,
+    routes: Vec<RouteConfig>,
+}
+
+impl Upstreams {
+    #[must_use]
+    pub fn from_env(default_url: &str) -> Self {
+        let config = std::env::var("BOTUI_UPSTREAMS_FILE")
+            .ok()
+            .and_then(|path| {
+                let raw = std::fs::read_to_string(&path)
+                    .map_err(|e| error!("Failed to read upstreams {path}: {e}"))
+                    .ok()?;
+                serde_json::from_str::<UpstreamsConfig>(&raw)
+                    .map_err(|e| error!("Invalid upstreams {path}: {e}"))
+                    .ok()
+            })
+            .unwrap_or_default();
+        Self::new(default_url, config)
+    }
+
+    fn new(default_url: &str, mut config: UpstreamsConfig) -> Self {
+        config.pools.retain(|name, pool| {
+            let keep = !pool.servers.is_empty();
+            if !keep {
+                warn!("Ignoring upstream pool {name} without servers");
+            }
+            keep
+        });
+        config
+            .pools
+            .entry(DEFAULT_POOL.to_string())
+            .or_insert_with(|| PoolConfig {
+                servers: vec![default_url.to_string()],
+                // The single default server is probed by /health already
+                health_path: None,
+                ..PoolConfig::default()
+            });
+
+        let pools: HashMap<String, Pool> = config
+            .pools
+            .into_iter()
+            .map(|(name, pool)| (name.clone(), Pool::new(&name, pool)))
+            .collect();
+        let routes: Vec<RouteConfig> = config
+            .routes
+            .into_iter()
+            .filter(|route| {
+                let known = pools.contains_key(&route.pool);
+                if !known {
+                    warn!("Ignoring upstream route to unknown pool {}", route.pool);
+                }
+                known
+            })
+            .collect();
+
+        if pools.len() > 1 || !routes.is_empty() {
+            info!(
+                "Upstream routing: {} pools, {} routes",
+                pools.len(),
+                routes.len()
+            );
+        }
+        Self { pools, routes }
+    }
+
+    fn pool_for(&self, path: &str, bot: Option<&str>) -> Option<&Pool> {
+        let name = self
+            .routes
+            .iter()
+            .find(|route| route.matches(path, bot))
+            .map_or(DEFAULT_POOL, |route| route.pool.as_str());
+        self.pools.get(name)
+    }
+
+    pub fn select(
+        &self,
+        path: &str,
+        bot: Option<&str>,
+        affinity: Option<&str>,
+    ) -> Option<Upstream> {
+        let pool = self.pool_for(path, bot)?;
+        pool.pick(affinity)
+            .map(|server| Upstream::new(server, pool))
+    }
+
+    pub fn spawn_health_checks(self: &Arc<Self>) {
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            warn!("No async runtime; upstream health checks disabled");
+            return;
+        };
+        for pool in self.pools.values() {
+            let Some(health_path) = pool.health_path.clone() else {
+                continue;
+            };
+            let upstreams = Arc::clone(self);
+            let name = pool.name.clone();
+            let client = pool.client.clone();
+            let interval = pool.health_interval;
+            runtime.spawn(async move {
+                let mut ticker = tokio::time::interval(interval);
+                loop {
+                    ticker.tick().await;
+                    let Some(pool) = upstreams.pools.get(&name) else {
+                        return;
+                    };
+                    for server in &pool.servers {
+                        let url = format!("{}{health_path}", server.url);
+                        let ok = client
+                            .get(&url)
+                            .timeout(HEALTH_TIMEOUT)
+                            .send()
+                            .await
+                            .is_ok_and(|resp| resp.status().is_success());
+                        let was = server.healthy.swap(ok, Ordering::Relaxed);
+                        if was && !ok {
+                            warn!("Upstream {} in pool {name} is unhealthy", server.url);
+                        } else if !was && ok {
+                            info!("Upstream {} in pool {name} recovered", server.url);
+                        }
+                    }
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn upstreams() -> Upstreams {
+        let config: UpstreamsConfig = serde_json::from_str(
+            r#"{
+                "pools": {
+                    "main": { "servers": ["http://a", "http://b", "http://c"] },
+                    "drive": { "servers": ["http://drive"] }
+                },
+                "routes": [
+                    { "path": "/api/drive/*", "pool": "drive" },
+                    { "bot": "edu", "pool": "main" }
+                ]
+            }"#,
+        )
+        .unwrap_or_default();
+        Upstreams::new("http://botserver:8080", config)
+    }
+
+    #[test]
+    fn routes_pick_pools() {
+        let upstreams = upstreams();
+        let pool = |path, bot| {
+            upstreams
+                .select(path, bot, None)
+                .map(|u| u.pool().to_string())
+        };
+        assert_eq!(pool("/api/drive/list", None).as_deref(), Some("drive"));
+        assert_eq!(pool("/api/chat", Some("edu")).as_deref(), Some("main"));
+        assert_eq!(pool("/api/chat", None).as_deref(), Some(DEFAULT_POOL));
+    }
+
+    #[test]
+    fn affinity_is_sticky_and_fails_over() {
+        let upstreams = upstreams();
+        let first = upstreams
+            .select("/ws", Some("edu"), Some("session-1"))
+            .map(|u| u.base_url().to_string());
+        for _ in 0..5 {
+            let again = upstreams
+                .select("/ws", Some("edu"), Some("session-1"))
+                .map(|u| u.base_url().to_string());
+            assert_eq!(again, first);
+        }
+        if let Some(server) = upstreams.pools.get("main").and_then(|pool| {
+            pool.servers
+                .iter()
+                .find(|s| Some(s.url.as_str()) == first.as_deref())
+        }) {
+            server.healthy.store(false, Ordering::Relaxed);
+        }
+        let moved = upstreams
+            .select("/ws", Some("edu"), Some("session-1"))
+            .map(|u| u.base_url().to_string());
+        assert!(moved.is_some() && moved != first);
+    }
+
+    #[test]
+    fn least_conn_prefers_idle_server() {
+        let config: UpstreamsConfig = serde_json::from_str(
+            r#"{ "pools": { "default": { "servers": ["http://a", "http://b"], "balance": "least_conn" } } }"#,
+        )
+        .unwrap_or_default();
+        let upstreams = Upstreams::new("http://unused", config);
+        let busy = upstreams.select("/api/x", None, None);
+        let other = upstreams.select("/api/x", None, None);
+        assert!(busy.is_some() && other.is_some());
+        assert_ne!(
+            busy.as_ref().map(Upstream::base_url),
+            other.as_ref().map(Upstream::base_url)
+        );
+    }
+}
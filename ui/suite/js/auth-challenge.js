@@ -0,0 +1,103 @@
+/**
+ * AUTH CHALLENGE - Proof-of-work for repeated sign-in attempts
+ *
+ * After several failed attempts botui answers /api/auth/* requests with
+ * 428 and `code: "challenge_required"`. This script fetches a challenge,
+ * finds a solution and repeats the request with it, for both HTMX forms
+ * and fetch() calls.
+ */
+
+(function (window, document) {
+  "use strict";
+
+  var CHALLENGE_URL = "/api/auth/challenge";
+  var pending = null;
+
+  function leadingZeroBits(bytes) {
+    var bits = 0;
+    for (var i = 0; i < bytes.length; i++) {
+      if (bytes[i] === 0) {
+        bits += 8;
+        continue;
+      }
+      return bits + Math.clz32(bytes[i]) - 24;
+    }
+    return bits;
+  }
+
+  async function solve(challenge, difficulty) {
+    var encoder = new TextEncoder();
+    for (var nonce = 0; ; nonce++) {
+      var digest = await crypto.subtle.digest(
+        "SHA-256",
+        encoder.encode(challenge + ":" + nonce),
+      );
+      if (leadingZeroBits(new Uint8Array(digest)) >= difficulty) {
+        return String(nonce);
+      }
+    }
+  }
+
+  async function obtain(fetchImpl) {
+    var response = await fetchImpl.call(window, CHALLENGE_URL, {
+      credentials: "same-origin",
+    });
+    var data = await response.json();
+    var solution = await solve(data.challenge, data.difficulty);
+    return { challenge: data.challenge, solution: solution };
+  }
+
+  function isChallenge(status, body) {
+    if (status !== 428) return false;
+    try {
+      return JSON.parse(body).code === "challenge_required";
+    } catch (e) {
+      return false;
+    }
+  }
+
+  if (!window.crypto || !window.crypto.subtle) {
+    console.warn("[AuthChallenge] Web Crypto unavailable (needs HTTPS)");
+    return;
+  }
+
+  var originalFetch = window.fetch;
+
+  document.addEventListener("htmx:configRequest", function (event) {
+    if (pending) {
+      event.detail.headers["X-Auth-Challenge"] = pending.challenge;
+      event.detail.headers["X-Auth-Solution"] = pending.solution;
+      pending = null;
+    }
+  });
+
+  document.addEventListener("htmx:responseError", function (event) {
+    var xhr = event.detail.xhr;
+    if (!xhr || !isChallenge(xhr.status, xhr.responseText)) return;
+
+    var elt = event.detail.elt;
+    obtain(originalFetch).then(function (solved) {
+      pending = solved;
+      htmx.trigger(elt, elt.tagName === "FORM" ? "submit" : "click");
+    });
+  });
+
+  window.fetch = async function (input, init) {
+    var response = await originalFetch.call(window, input, init);
+    var url = typeof input === "string" ? input : input.url;
+    if (response.status !== 428 || url.indexOf("/api/auth/") === -1) {
+      return response;
+    }
+
+    var body = await response.clone().text();
+    if (!isChallenge(response.status, body)) return response;
+
+    var solved = await obtain(originalFetch);
+    var retry = Object.assign({}, init);
+    var headers = new Headers((init && init.headers) || {});
+    headers.set("X-Auth-Challenge", solved.challenge);
+    headers.set("X-Auth-Solution", solved.solution);
+    retry.headers = headers;
+    return originalFetch.call(window, input, retry);
+  };
+})(window, document);
@@ -3,6 +3,9 @@
 
   const DEFAULT_LOCALE = "en";
   const STORAGE_KEY = "gb-locale";
+  // Mirrors the stored locale so botui can render pages already translated
+  const LOCALE_COOKIE = "gb_locale";
+  const RTL_LANGUAGES = ["ar", "ckb", "dv", "fa", "he", "iw", "ps", "sd", "ug", "ur", "yi"];
   const CACHE_VERSION = "v2";
   const CACHE_TTL_MS = 3600000;
 
@@ -20,12 +23,35 @@
   let translations = {};
   let isInitialized = false;
 
+  function persistLocale(locale) {
+    localStorage.setItem(STORAGE_KEY, locale);
+    document.cookie = `${LOCALE_COOKIE}=${encodeURIComponent(locale)}; Path=/; Max-Age=31536000; SameSite=Lax`;
+  }
+
+  function applyDocumentLocale(locale) {
+    const language = locale.split("-")[0].toLowerCase();
+    document.documentElement.setAttribute("lang", locale);
+    document.documentElement.setAttribute(
+      "dir",
+      RTL_LANGUAGES.includes(language) ? "rtl" : "ltr",
+    );
+  }
+
   function detectBrowserLocale() {
     const stored = localStorage.getItem(STORAGE_KEY);
     if (stored) {
+      if (!document.cookie.includes(`${LOCALE_COOKIE}=`)) {
+        persistLocale(stored);
+      }
       return stored;
     }
 
+    // Locale botui negotiated when rendering the page
+    const rendered = document.documentElement.getAttribute("data-locale");
+    if (rendered) {
+      return rendered;
+    }
+
     const browserLang =
       navigator.language || navigator.userLanguage || DEFAULT_LOCALE;
     const shortLang = browserLang.split("-")[0];
@@ -195,11 +221,11 @@
       return;
     }
 
-    localStorage.setItem(STORAGE_KEY, locale);
+    persistLocale(locale);
     await loadTranslations(locale);
     translatePage();
 
-    document.documentElement.setAttribute("lang", locale.split("-")[0]);
+    applyDocumentLocale(locale);
 
     window.dispatchEvent(
       new CustomEvent("localeChanged", {
@@ -29,6 +29,7 @@
     initialized: false,
 
     getToken: function () {
+      if (this.hasServerSession()) return null;
       return (
         localStorage.getItem(AUTH_KEYS.ACCESS_TOKEN) ||
         sessionStorage.getItem(AUTH_KEYS.ACCESS_TOKEN) ||
@@ -52,9 +53,21 @@
       );
     },
 
+    getCsrfToken: function () {
+      // The cookie follows sign-in and sign-out; the meta tag is a fallback
+      var match = document.cookie.match(/(?:^|;\s*)gb_csrf=([^;]+)/);
+      if (match) return match[1];
+      var meta = document.querySelector('meta[name="csrf-token"]');
+      return meta && meta.content ? meta.content : null;
+    },
+
+    hasServerSession: function () {
+      return /(?:^|;\s*)gb_session_active=1(?:;|$)/.test(document.cookie);
+    },
+
     isAuthenticated: function () {
       var token = this.getToken();
-      if (!token) return false;
+      if (!token) return this.hasServerSession();
 
       var expires =
         localStorage.getItem(AUTH_KEYS.TOKEN_EXPIRES) ||
@@ -97,6 +110,10 @@
       if (sessionId && !headers["X-Session-ID"]) {
         headers["X-Session-ID"] = sessionId;
       }
+      var csrfToken = this.getCsrfToken();
+      if (csrfToken && !headers["X-CSRF-Token"]) {
+        headers["X-CSRF-Token"] = csrfToken;
+      }
 
       return headers;
     },
@@ -116,6 +133,14 @@
         return;
       }
 
+      // botui holds the tokens of a server session; drop stale copies
+      if (this.hasServerSession()) {
+        ["ACCESS_TOKEN", "REFRESH_TOKEN", "TOKEN_EXPIRES"].forEach(function (key) {
+          localStorage.removeItem(AUTH_KEYS[key]);
+          sessionStorage.removeItem(AUTH_KEYS[key]);
+        });
+      }
+
       var self = this;
 
       this.initHTMXInterceptor();
@@ -163,6 +188,20 @@
         if (sessionId) {
           event.detail.headers["X-Session-ID"] = sessionId;
         }
+        var csrfToken = self.getCsrfToken();
+        if (csrfToken && event.detail.verb !== "get") {
+          event.detail.headers["X-CSRF-Token"] = csrfToken;
+        }
+      });
+
+      // botui error fragments carry their own retry control, so show them
+      // in place instead of treating the response as a failed swap
+      document.addEventListener("htmx:beforeSwap", function (event) {
+        var xhr = event.detail.xhr;
+        if (xhr && xhr.getResponseHeader("X-GB-Error") === "fragment") {
+          event.detail.shouldSwap = true;
+          event.detail.isError = false;
+        }
       });
 
       document.addEventListener("htmx:responseError", function (event) {
@@ -258,6 +297,12 @@
             this.setRequestHeader("X-Session-ID", sessionId);
           } catch (e) {}
         }
+        var csrfToken = self.getCsrfToken();
+        if (csrfToken && String(this._gbMethod).toUpperCase() !== "GET") {
+          try {
+            this.setRequestHeader("X-CSRF-Token", csrfToken);
+          } catch (e) {}
+        }
 
         this.addEventListener("load", function () {
           if (xhr.status === 401) {
@@ -727,8 +727,8 @@ document.addEventListener("DOMContentLoaded", () => {
     return "default";
   };
 
-  // Set global bot name
-  window.__INITIAL_BOT_NAME__ = detectBotFromPath();
+  // Set global bot name, keeping the one injected by the server (host-based selection)
+  window.__INITIAL_BOT_NAME__ = window.__INITIAL_BOT_NAME__ || detectBotFromPath();
   console.log(`🤖 Bot detected from path: ${window.__INITIAL_BOT_NAME__}`);
 
   // Check if bot is public to skip authentication
@@ -55,6 +55,31 @@ const ThemeManager = (() => {
     { id: "xtreegold", name: "📁 XTree", file: "xtreegold.css" },
   ];
 
+  // Mirrors the choice so botui can link the theme into served pages
+  function persistTheme(id) {
+    document.cookie = `gb_theme=${encodeURIComponent(id)}; Path=/; Max-Age=31536000; SameSite=Lax`;
+  }
+
+  // Adds custom themes from overlays, as listed by botui
+  function loadServerThemes() {
+    return fetch("/api/themes")
+      .then((response) => (response.ok ? response.json() : null))
+      .then((data) => {
+        if (!data || !Array.isArray(data.themes)) return;
+        data.themes.forEach((t) => {
+          if (!themes.find((known) => known.id === t.id)) {
+            themes.push({ id: t.id, name: t.name, file: `${t.id}.css` });
+          }
+        });
+        const container = document.getElementById("themeSelectorContainer");
+        if (container && container.firstChild) {
+          container.innerHTML = "";
+          container.appendChild(createDropdown());
+        }
+      })
+      .catch((e) => console.warn("Could not list server themes:", e));
+  }
+
   function loadTheme(id) {
     const theme = themes.find((t) => t.id === id);
     if (!theme) {
@@ -84,6 +109,7 @@ const ThemeManager = (() => {
       currentThemeId = id;
       const botId = getCurrentBotId();
       localStorage.setItem(`gb-theme-${botId}`, id);
+      persistTheme(id);
 
       // Keep data-theme="sentient" on html so CSS selectors work
       // The inline styles will override the colors
@@ -319,6 +345,14 @@ const ThemeManager = (() => {
     // Priority: 1) localStorage user preference, 2) bot-specific theme, 3) default
     const botId = getCurrentBotId();
     let saved = localStorage.getItem(`gb-theme-${botId}`);
+    // Theme botui already linked into the page, if any
+    const served = document.documentElement.getAttribute("data-theme-id");
+    if (served && !themes.find((t) => t.id === served)) {
+      themes.push({ id: served, name: served, file: `${served}.css` });
+    }
+    if (!saved && served) {
+      saved = served;
+    }
     if (!saved || !themes.find((t) => t.id === saved)) {
       // No user preference, try bot-specific theme
       saved = botThemeMap[botId] || "light";
@@ -336,6 +370,8 @@ const ThemeManager = (() => {
       container.appendChild(createDropdown());
     }
 
+    loadServerThemes();
+
     console.log("✓ Theme Manager initialized");
   }
 